@@ -0,0 +1,74 @@
+//! A whole game between three players on in-memory storage, with a simulated
+//! clock to move through the phases.
+//!
+//! Run with `cargo run --example basic_usage`.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use zkret_santa_filecoin::crypto::{decrypt_data, encryption::associated_data, ZKProofSystem};
+use zkret_santa_filecoin::protocol::{Clock, Deadline, GameConfig};
+use zkret_santa_filecoin::{DHKeyExchange, KeyPair, MemoryStorage, Result, SecretSantaProtocol};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    println!("Generating proving keys...");
+    let zk_system = ZKProofSystem::new()?;
+    let organizer = KeyPair::generate();
+    let config = GameConfig {
+        created_at: 1_000,
+        enter_deadline: Deadline::Timestamp(2_000),
+        choice_deadline: Deadline::Timestamp(3_000),
+        recovery_deadline: Deadline::Timestamp(3_500),
+        reveal_deadline: Deadline::Timestamp(4_000),
+        ..GameConfig::new("office", &organizer, zk_system.params_hash())
+    };
+
+    // Storage and protocol share the clock, so records are judged by the
+    // time they were stored at
+    let time = Arc::new(AtomicU64::new(1_500));
+    let clock: Clock = {
+        let time = time.clone();
+        Arc::new(move || time.load(Ordering::SeqCst))
+    };
+    let mut storage = MemoryStorage::new();
+    storage.set_clock(clock.clone());
+
+    let mut game = SecretSantaProtocol::create_game(storage, zk_system, config.sign(&organizer)?).await?;
+    game.set_clock(clock);
+
+    let names = ["alice", "bob", "carol"];
+    let players: Vec<(KeyPair, DHKeyExchange)> = names.iter()
+        .map(|_| (KeyPair::generate(), DHKeyExchange::generate()))
+        .collect();
+
+    for (name, (keypair, _)) in names.iter().zip(&players) {
+        game.enter_phase(keypair).await?;
+        println!("{} entered", name);
+    }
+
+    // Each player picks the next one, so nobody is left with only themselves
+    time.store(2_500, Ordering::SeqCst);
+    for (i, (keypair, dh)) in players.iter().enumerate() {
+        let (chosen, _) = &players[(i + 1) % players.len()];
+        game.choice_phase(keypair, chosen.public_key.as_bytes(), dh).await?;
+    }
+    println!("{} choices made", game.state().choices().len());
+
+    // Players reveal themselves to whoever chose them
+    time.store(3_700, Ordering::SeqCst);
+    for (name, (keypair, _)) in names.iter().zip(&players) {
+        let santa_dh = game.state().choice_for(keypair.public_key.as_bytes()).unwrap().chooser_dh_public_key.clone();
+        game.reveal_phase(keypair, &format!("{}@example.com", name), &santa_dh).await?;
+    }
+
+    for (name, (_, dh)) in names.iter().zip(&players) {
+        let reveal = game.state().reveal_for(&dh.public_key()).unwrap();
+        let game_id = game.game_id().as_bytes();
+        let shared_secret = dh.compute_shared_secret(&reveal.dh_public_key, game_id)?;
+        let associated_data = associated_data(game_id, &reveal.dh_public_key, &dh.public_key());
+        let identity = decrypt_data(&reveal.encrypted_identity, &shared_secret, &associated_data)?;
+        println!("{} is Secret Santa for {}", name, String::from_utf8_lossy(&identity));
+    }
+
+    Ok(())
+}
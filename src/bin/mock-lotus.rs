@@ -0,0 +1,66 @@
+use clap::Parser;
+use std::net::SocketAddr;
+use std::time::Duration;
+use zkret_santa_filecoin::filecoin::{MockConfig, MockLotus};
+use zkret_santa_filecoin::protocol::game::{CALIBRATION_GENESIS, MAINNET_GENESIS};
+
+/// Mock Lotus node for playing zkretctl locally, built with `--features mock`
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Address to serve the RPC API on
+    #[arg(long, default_value = "127.0.0.1:1234")]
+    listen: SocketAddr,
+
+    /// Bearer token clients must send
+    #[arg(long)]
+    auth_token: Option<String>,
+
+    /// Network name reported to clients (`mainnet` switches to f addresses)
+    #[arg(long, default_value = "calibrationnet")]
+    network: String,
+
+    /// Seconds a deal waits for acceptance
+    #[arg(long, default_value_t = 5)]
+    accept_delay: u64,
+
+    /// Seconds a deal spends sealing before it becomes active
+    #[arg(long, default_value_t = 30)]
+    seal_delay: u64,
+
+    /// Fail every deal with this message
+    #[arg(long)]
+    fail_deals: Option<String>,
+
+    /// Offer no retrievals
+    #[arg(long)]
+    fail_retrievals: bool,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let config = MockConfig {
+        genesis_timestamp: if args.network == "mainnet" { MAINNET_GENESIS } else { CALIBRATION_GENESIS },
+        network: args.network,
+        auth_token: args.auth_token,
+        accept_delay: Duration::from_secs(args.accept_delay),
+        seal_delay: Duration::from_secs(args.seal_delay),
+        deal_failure: args.fail_deals,
+        fail_retrievals: args.fail_retrievals,
+        ..MockConfig::default()
+    };
+    let miner = config.miners[0].address.clone();
+
+    let mock = match MockLotus::bind(args.listen, config).await {
+        Ok(mock) => mock,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    println!("Mock Lotus listening on {}", mock.url());
+    println!("Use: zkretctl --filecoin-endpoint {} --miner {} ...", mock.url(), miner);
+    mock.run().await;
+}
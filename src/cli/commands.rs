@@ -1,8 +1,21 @@
-use crate::crypto::{KeyPair, DHKeyExchange};
-use crate::filecoin::FilecoinStorage;
-use crate::protocol::SecretSantaProtocol;
+use crate::crypto::{decrypt_data, KeyPair, DHKeyExchange, Keystore, ZKProofSystem};
+use crate::crypto::merkle::MAX_LEAVES;
+use crate::filecoin::deals::MIN_DEAL_DURATION;
+use crate::filecoin::retrieval::DEFAULT_GATEWAY;
+use crate::filecoin::{
+    DealConfig, DealManager, FilecoinStorage, LocalStorage, LotusClient, RecordIndex, RecordType, RetrievalSource,
+    Retriever, StorageBackend, StorageRecord,
+};
+use crate::protocol::game::{is_game_record, list_games, load_game, CALIBRATION_GENESIS, MAINNET_GENESIS};
+use crate::protocol::{Deadline, GameConfig, GameId, SecretSantaProtocol};
+use crate::utils::clock::unix_now;
 use clap::{Parser, Subcommand};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+/// Environment variable holding the keystore passphrase, checked before prompting
+const PASSPHRASE_ENV: &str = "ZKRET_PASSPHRASE";
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -10,17 +23,64 @@ pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
     
-    /// Path to keypair file
+    /// Path to the encrypted keystore file
     #[arg(short, long, default_value = "key.zkret")]
     pub keypair_file: PathBuf,
     
-    /// Filecoin endpoint
-    #[arg(long, default_value = "https://api.node.glif.io")]
+    /// Lotus JSON-RPC endpoint. Records are imported through files on the
+    /// node, so it must share this machine's filesystem; hosted gateways
+    /// cannot store records
+    #[arg(long, default_value = "http://127.0.0.1:1234/rpc/v1")]
     pub filecoin_endpoint: String,
+
+    /// Timeout for each Lotus request, in seconds
+    #[arg(long, default_value_t = 30)]
+    pub rpc_timeout: u64,
     
     /// Authentication token for Filecoin
     #[arg(long, env = "FILECOIN_AUTH_TOKEN")]
-    pub auth_token: String,
+    pub auth_token: Option<String>,
+
+    /// Play offline against a shared local directory instead of Filecoin
+    #[arg(long)]
+    pub local_dir: Option<PathBuf>,
+
+    /// Record index kept for Filecoin storage (local directories keep their own)
+    #[arg(long, default_value = "records.jsonl")]
+    pub index_file: PathBuf,
+
+    /// File tracking the storage deals of records stored on Filecoin
+    #[arg(long, default_value = "deals.json")]
+    pub deals_file: PathBuf,
+
+    /// Miner to propose storage deals to (repeatable, cheapest acceptable ask
+    /// wins). Without one records are still announced, but no deal is made
+    #[arg(long = "miner")]
+    pub miners: Vec<String>,
+
+    /// Highest accepted deal price in attoFIL per GiB per epoch
+    #[arg(long)]
+    pub max_price: Option<u128>,
+
+    /// Storage deal duration in epochs
+    #[arg(long, default_value_t = MIN_DEAL_DURATION)]
+    pub deal_duration: u64,
+
+    /// Directory caching every record fetched from Filecoin
+    #[arg(long, default_value = "cache")]
+    pub cache_dir: PathBuf,
+
+    /// IPFS gateway tried before a Lotus retrieval deal (repeatable, in order)
+    #[arg(long = "gateway", default_value = DEFAULT_GATEWAY)]
+    pub gateways: Vec<String>,
+
+    /// Directory holding the game's shared Groth16 parameters
+    #[arg(long, default_value = "params")]
+    pub params_dir: PathBuf,
+
+    /// Game to play, as printed by `zkretctl games list`
+    #[arg(long, env = "ZKRET_GAME")]
+    pub game: Option<GameId>,
 }
 
 #[derive(Subcommand)]
@@ -40,6 +100,19 @@ pub enum Commands {
         chosen_public_key: String,
     },
     
+    /// Check whether your choice was accepted or has to be made again
+    ChoiceCheck,
+
+    /// List the participants you may pick in the recovery round
+    RecoveryList,
+
+    /// Pick a participant in the recovery round, if you have not chosen
+    RecoveryMake {
+        /// Public key of the chosen participant (hex encoded)
+        #[arg(long)]
+        chosen_public_key: String,
+    },
+
     /// Check if you have a Secret Santa (someone chose you)
     CheckMySanta,
     
@@ -54,24 +127,218 @@ pub enum Commands {
     
     /// Display protocol status
     Status,
+
+    /// Manage the shared Groth16 parameters
+    Params {
+        #[command(subcommand)]
+        action: ParamsCommand,
+    },
+
+    /// Run a multi-party setup ceremony, so no single contributor learns the
+    /// trapdoor of the circuit parameters
+    Ceremony {
+        #[command(subcommand)]
+        action: CeremonyCommand,
+    },
+
+    /// Export every record of the game as a CARv1 file
+    Export {
+        /// Output CAR file
+        #[arg(long, default_value = "game.car")]
+        out: PathBuf,
+    },
+
+    /// Import the records of a CAR file into the current storage backend
+    Import {
+        /// CAR file to import
+        car: PathBuf,
+    },
+
+    /// Manage the encrypted keystore
+    Keystore {
+        #[command(subcommand)]
+        action: KeystoreCommand,
+    },
+
+    /// Manage the local record index
+    Index {
+        #[command(subcommand)]
+        action: IndexCommand,
+    },
+
+    /// List the storage deal status of every record
+    Deals {
+        /// Poll the deals that are due before listing
+        #[arg(long)]
+        refresh: bool,
+    },
+
+    /// Create and list games
+    Games {
+        #[command(subcommand)]
+        action: GamesCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum GamesCommand {
+    /// Start a new game organized with your key
+    Create {
+        /// Name shown to participants
+        #[arg(long)]
+        name: String,
+
+        #[arg(long, default_value_t = 3)]
+        min_participants: u32,
+
+        #[arg(long, default_value_t = MAX_LEAVES as u32)]
+        max_participants: u32,
+
+        /// End of the ENTER phase, as a unix timestamp or `epoch:<height>`
+        #[arg(long)]
+        enter_deadline: Deadline,
+
+        /// End of the CHOICE phase
+        #[arg(long)]
+        choice_deadline: Deadline,
+
+        /// End of the recovery round for players left without a valid choice
+        #[arg(long)]
+        recovery_deadline: Deadline,
+
+        /// End of the REVEAL phase
+        #[arg(long)]
+        reveal_deadline: Deadline,
+
+        /// Gift budget shown to participants
+        #[arg(long, default_value = "")]
+        budget: String,
+
+        /// Play on the calibration network instead of mainnet, which sets the
+        /// genesis that epoch deadlines and record times count from
+        #[arg(long)]
+        calibration: bool,
+    },
+
+    /// List every game with a config record in storage
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum IndexCommand {
+    /// Replace the record index with the records of a CAR export or another local directory
+    Rebuild {
+        /// CAR file exported with `zkretctl export`
+        #[arg(long, conflicts_with = "from_local_dir", required_unless_present = "from_local_dir")]
+        from_car: Option<PathBuf>,
+
+        /// Local storage directory to list records from
+        #[arg(long)]
+        from_local_dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum KeystoreCommand {
+    /// Encrypt a plaintext keypair file (and its .dh file) written by older versions
+    Migrate,
+}
+
+#[derive(Subcommand)]
+pub enum ParamsCommand {
+    /// Run a local circuit setup and write the parameters to a directory.
+    /// Whoever runs it can forge proofs; use `ceremony` for shared games
+    Generate {
+        /// Output directory
+        #[arg(long, default_value = "params")]
+        out_dir: PathBuf,
+    },
+
+    /// Verify a parameter directory and print its hashes
+    Inspect {
+        /// Parameter directory
+        #[arg(long, default_value = "params")]
+        dir: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CeremonyCommand {
+    /// Start a ceremony with fresh powers of tau
+    Init {
+        /// Ceremony directory shared by all contributors
+        #[arg(long, default_value = "ceremony")]
+        dir: PathBuf,
+    },
+
+    /// Add your contribution to the current phase and publish its transcript
+    /// to Filecoin
+    Contribute {
+        /// Ceremony directory shared by all contributors
+        #[arg(long, default_value = "ceremony")]
+        dir: PathBuf,
+
+        /// Name recorded in the transcript
+        #[arg(long)]
+        name: String,
+    },
+
+    /// Close phase 1 and derive the circuit parameters that phase 2 builds on
+    Derive {
+        /// Ceremony directory shared by all contributors
+        #[arg(long, default_value = "ceremony")]
+        dir: PathBuf,
+    },
+
+    /// Verify every contribution made so far
+    Verify {
+        /// Ceremony directory shared by all contributors
+        #[arg(long, default_value = "ceremony")]
+        dir: PathBuf,
+    },
+
+    /// Verify the ceremony and write the final parameters
+    Finalize {
+        /// Ceremony directory shared by all contributors
+        #[arg(long, default_value = "ceremony")]
+        dir: PathBuf,
+
+        /// Output parameter directory
+        #[arg(long, default_value = "params")]
+        out_dir: PathBuf,
+    },
 }
 
 pub async fn execute_command(cli: Cli) -> crate::utils::Result<()> {
-    // Initialize Filecoin storage
-    let mut storage = FilecoinStorage::new(&cli.filecoin_endpoint, &cli.auth_token).await?;
-    let mut protocol = SecretSantaProtocol::new(storage).await?;
+    // Key generation, parameter management and game setup work offline and
+    // before any game or parameters exist
+    match &cli.command {
+        Commands::Keygen => return generate_keystore(&cli),
+        Commands::Params { action } => return execute_params_command(action),
+        Commands::Ceremony { action } => return execute_ceremony_command(&cli, action).await,
+        Commands::Keystore { action } => return execute_keystore_command(&cli, action),
+        Commands::Export { out } => return export_game(&cli, out).await,
+        Commands::Import { car } => return import_game(&cli, car).await,
+        Commands::Index { action } => return execute_index_command(&cli, action).await,
+        Commands::Deals { refresh } => return list_deals(&cli, *refresh).await,
+        Commands::Games { action } => return execute_games_command(&cli, action).await,
+        _ => {}
+    }
 
-    match cli.command {
-        Commands::Keygen => {
-            let keypair = KeyPair::generate();
-            save_keypair(&keypair, &cli.keypair_file)?;
-            println!("Generated new keypair and saved to: {}", cli.keypair_file.display());
-            println!("Public key: {}", hex::encode(keypair.public_key.as_bytes()));
-        }
+    // Initialize storage and pick up records published by other participants
+    let game = cli.game
+        .ok_or_else(|| crate::utils::Error::InvalidInput(
+            "--game is required, see `zkretctl games list`".to_string()
+        ))?;
+    let mut storage = open_storage(&cli, Some(&game)).await?;
+    storage.sync().await?;
+    let zk_system = ZKProofSystem::from_params_dir(&cli.params_dir)?;
+    let mut protocol = SecretSantaProtocol::new(storage, zk_system, game).await?;
 
+    match cli.command {
         Commands::Enter => {
-            let keypair = load_keypair(&cli.keypair_file)?;
-            protocol.enter_phase(&keypair).await?;
+            let keystore = Keystore::load(&cli.keypair_file, &read_passphrase(false)?)?;
+            protocol.enter_phase(keystore.keypair()).await?;
             println!("Successfully entered the Secret Santa protocol!");
         }
 
@@ -84,22 +351,67 @@ pub async fn execute_command(cli: Cli) -> crate::utils::Result<()> {
         }
 
         Commands::ChoiceMake { chosen_public_key } => {
-            let keypair = load_keypair(&cli.keypair_file)?;
+            let passphrase = read_passphrase(false)?;
+            let mut keystore = Keystore::load(&cli.keypair_file, &passphrase)?;
             let chosen_pk_bytes = hex::decode(&chosen_public_key)
                 .map_err(|e| crate::utils::Error::InvalidInput(e.to_string()))?;
             
-            let dh_keypair = DHKeyExchange::generate();
-            protocol.choice_phase(&keypair, &chosen_pk_bytes, &dh_keypair).await?;
-            
-            // Save DH keypair for later use in reveal phase
-            save_dh_keypair(&dh_keypair, &cli.keypair_file)?;
+            // An accepted choice keeps its DH key, never replace it
+            if protocol.state().choice_by(&protocol.choice_nullifier(keystore.keypair())).is_some() {
+                return Err(crate::utils::Error::ProtocolError(
+                    "You have already chosen in this game".to_string()
+                ));
+            }
+
+            // Save DH keypair for the reveal phase before publishing, so it cannot be lost
+            ensure_dh_key(&mut keystore, &game, &cli.keypair_file, &passphrase)?;
+
+            let dh_keypair = dh_key_for_game(&keystore, &game)?;
+            protocol.choice_phase(keystore.keypair(), &chosen_pk_bytes, dh_keypair).await?;
             
             println!("Successfully chose participant: {}", chosen_public_key);
         }
 
+        Commands::ChoiceCheck => {
+            let keystore = Keystore::load(&cli.keypair_file, &read_passphrase(false)?)?;
+            match protocol.confirm_choice(keystore.keypair()).await {
+                Ok(choice) => println!("Your choice of {} was accepted", hex::encode(&choice.chosen_public_key)),
+                Err(e) => println!("{}. Run `choice-list` and `choice-make` to choose again.", e),
+            }
+        }
+
+        Commands::RecoveryList => {
+            let keystore = Keystore::load(&cli.keypair_file, &read_passphrase(false)?)?;
+            println!("Public keys open to you in the recovery round:");
+            for (i, pk) in protocol.get_recovery_choices(keystore.keypair()).iter().enumerate() {
+                println!("  {}: {}", i + 1, hex::encode(pk));
+            }
+        }
+
+        Commands::RecoveryMake { chosen_public_key } => {
+            let passphrase = read_passphrase(false)?;
+            let mut keystore = Keystore::load(&cli.keypair_file, &passphrase)?;
+            let chosen_pk_bytes = hex::decode(&chosen_public_key)
+                .map_err(|e| crate::utils::Error::InvalidInput(e.to_string()))?;
+
+            if protocol.state().choice_by(&protocol.choice_nullifier(keystore.keypair())).is_some() {
+                return Err(crate::utils::Error::ProtocolError(
+                    "You have already chosen in this game".to_string()
+                ));
+            }
+
+            ensure_dh_key(&mut keystore, &game, &cli.keypair_file, &passphrase)?;
+
+            let dh_keypair = dh_key_for_game(&keystore, &game)?;
+            protocol.recovery_phase(keystore.keypair(), &chosen_pk_bytes, dh_keypair).await?;
+
+            println!("Successfully chose participant: {}", chosen_public_key);
+        }
+
         Commands::CheckMySanta => {
-            let keypair = load_keypair(&cli.keypair_file)?;
-            let has_santa = check_if_chosen(&protocol, keypair.public_key.as_bytes()).await?;
+            let public_key = hex::decode(Keystore::read_public_key(&cli.keypair_file)?)
+                .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+            let has_santa = check_if_chosen(&protocol, &public_key);
             
             if has_santa {
                 println!("You have a Secret Santa! They will contact you once you reveal your info.");
@@ -109,21 +421,21 @@ pub async fn execute_command(cli: Cli) -> crate::utils::Result<()> {
         }
 
         Commands::Reveal { info_plaintext } => {
-            let keypair = load_keypair(&cli.keypair_file)?;
-            let dh_keypair = load_dh_keypair(&cli.keypair_file)?;
+            let keystore = Keystore::load(&cli.keypair_file, &read_passphrase(false)?)?;
+            let keypair = keystore.keypair();
             
             // Get Santa's DH public key from choice transaction
-            let santa_dh_pk = get_santa_dh_public_key(&protocol, keypair.public_key.as_bytes()).await?;
+            let santa_dh_pk = get_santa_dh_public_key(&protocol, keypair.public_key.as_bytes())?;
             
-            protocol.reveal_phase(&keypair, &info_plaintext, &dh_keypair, &santa_dh_pk).await?;
+            protocol.reveal_phase(keypair, &info_plaintext, &santa_dh_pk).await?;
             println!("Successfully revealed your information to your Secret Santa!");
         }
 
         Commands::CheckMySantee => {
-            let keypair = load_keypair(&cli.keypair_file)?;
-            let dh_keypair = load_dh_keypair(&cli.keypair_file)?;
+            let keystore = Keystore::load(&cli.keypair_file, &read_passphrase(false)?)?;
+            let dh_keypair = dh_key_for_game(&keystore, &game)?;
             
-            let santee_info = get_santee_revealed_info(&protocol, &keypair, &dh_keypair).await?;
+            let santee_info = get_santee_revealed_info(&protocol, dh_keypair)?;
             
             match santee_info {
                 Some(info) => {
@@ -142,79 +454,479 @@ pub async fn execute_command(cli: Cli) -> crate::utils::Result<()> {
             
             let choices = protocol.get_available_choices().await?;
             println!("Available participants: {}", choices.len());
+
+            let state = protocol.state();
+            let config = &state.config().config;
+            let [enter, choice, recovery, reveal] = config.deadlines();
+            println!("Game: {} ({})", config.name, state.game_id());
+            if !config.budget.is_empty() {
+                println!("Budget: {}", config.budget);
+            }
+            println!("Participants: {} to {}", config.min_participants, config.max_participants);
+            println!(
+                "Deadlines (unix time): enter {}, choice {}, recovery {}, reveal {}",
+                enter, choice, recovery, reveal
+            );
+            println!("Entered: {}, chosen: {}, revealed: {}",
+                state.participants().len(), state.choices().len(), state.reveals().len());
+            if state.needs_recovery() {
+                println!("Without a Santa: {} (see `recovery-list`)", state.available_choices().len());
+            }
+            for rejection in state.rejected() {
+                println!("Rejected {} ({}): {}", rejection.record_id, rejection.content_cid, rejection.reason);
+            }
+        }
+
+        Commands::Keygen
+        | Commands::Params { .. }
+        | Commands::Ceremony { .. }
+        | Commands::Keystore { .. }
+        | Commands::Export { .. }
+        | Commands::Import { .. }
+        | Commands::Index { .. }
+        | Commands::Deals { .. }
+        | Commands::Games { .. } => {
+            unreachable!("handled before storage initialization")
         }
     }
 
     Ok(())
 }
 
-// Helper functions for file I/O and protocol queries
-fn save_keypair(keypair: &KeyPair, path: &PathBuf) -> crate::utils::Result<()> {
-    let (public_hex, secret_hex) = keypair.to_hex_strings();
-    let data = format!("{}:{}", public_hex, secret_hex);
-    
-    std::fs::write(path, data)
-        .map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
-    
+fn generate_keystore(cli: &Cli) -> crate::utils::Result<()> {
+    if cli.keypair_file.exists() {
+        return Err(crate::utils::Error::FileError(format!(
+            "{} already exists, refusing to overwrite it",
+            cli.keypair_file.display()
+        )));
+    }
+
+    let passphrase = read_passphrase(true)?;
+    let keystore = Keystore::new(KeyPair::generate());
+    keystore.save(&cli.keypair_file, &passphrase)?;
+    println!("Generated new keypair and saved to: {}", cli.keypair_file.display());
+    println!("Public key: {}", hex::encode(keystore.keypair().public_key.as_bytes()));
+
     Ok(())
 }
 
-fn load_keypair(path: &PathBuf) -> crate::utils::Result<KeyPair> {
-    let data = std::fs::read_to_string(path)
-        .map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
-    
-    let parts: Vec<&str> = data.trim().split(':').collect();
-    if parts.len() != 2 {
-        return Err(crate::utils::Error::FileError("Invalid keypair file format".to_string()));
+fn execute_params_command(action: &ParamsCommand) -> crate::utils::Result<()> {
+    match action {
+        ParamsCommand::Generate { out_dir } => {
+            println!("Running circuit setup, this may take a while...");
+            let zk_system = ZKProofSystem::new()?;
+            let manifest = zk_system.write_params_dir(out_dir)?;
+            println!("Wrote parameters to: {}", out_dir.display());
+            println!("Parameter set hash: {}", manifest.params_hash);
+        }
+
+        ParamsCommand::Inspect { dir } => {
+            let manifest = crate::crypto::params::inspect_params(dir)?;
+            println!("Parameter format version: {}", manifest.version);
+            println!("Parameter set hash: {}", manifest.params_hash);
+            for circuit in &manifest.circuits {
+                println!("  {:?} ({} public inputs)", circuit.proof_type, circuit.num_public_inputs);
+                println!("    proving key:   {} {}", circuit.proving_key_file, circuit.proving_key_hash);
+                println!("    verifying key: {} {}", circuit.verifying_key_file, circuit.verifying_key_hash);
+            }
+        }
     }
-    
-    KeyPair::from_hex_strings(parts[0], parts[1])
+
+    Ok(())
 }
 
-fn save_dh_keypair(dh_keypair: &DHKeyExchange, base_path: &PathBuf) -> crate::utils::Result<()> {
-    let dh_path = base_path.with_extension("dh");
-    let hex_data = hex::encode(dh_keypair.secret_key());
-    
-    std::fs::write(dh_path, hex_data)
+async fn execute_ceremony_command(cli: &Cli, action: &CeremonyCommand) -> crate::utils::Result<()> {
+    match action {
+        CeremonyCommand::Init { dir } => {
+            let ceremony = crate::crypto::ceremony::Ceremony::init(dir)?;
+            println!("Started ceremony in: {}", dir.display());
+            println!("Initial powers of tau hash: {}", ceremony.log().initial_hash);
+        }
+
+        CeremonyCommand::Contribute { dir, name } => {
+            let mut ceremony = crate::crypto::ceremony::Ceremony::open(dir)?;
+            let transcript = ceremony.contribute(name, &mut rand::rngs::OsRng)?;
+
+            // Publish the transcript so other players can audit the ceremony
+            let mut storage = open_storage(cli, cli.game.as_ref()).await?;
+            let transcript_data = bincode::serialize(&transcript)
+                .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+            let record = storage.put(transcript_data, RecordType::CeremonyContribution).await?;
+            ceremony.set_record_cid(transcript.index, &record.content_cid.to_string())?;
+
+            println!("Phase-{} contribution {} recorded", transcript.phase(), transcript.index);
+            println!("New hash: {}", transcript.params_hash);
+            println!("Transcript CID: {}", record.content_cid);
+        }
+
+        CeremonyCommand::Derive { dir } => {
+            let mut ceremony = crate::crypto::ceremony::Ceremony::open(dir)?;
+            let manifest = ceremony.derive()?;
+            println!("Closed phase 1 after {} contributions", ceremony.rounds());
+            println!("Derived parameter hash: {}", manifest.params_hash);
+        }
+
+        CeremonyCommand::Verify { dir } => {
+            let ceremony = crate::crypto::ceremony::Ceremony::open(dir)?;
+            ceremony.verify()?;
+            println!("All {} contributions are valid", ceremony.rounds());
+            for entry in &ceremony.log().contributions {
+                println!(
+                    "  {} (phase {}): {} -> {}",
+                    entry.transcript.index,
+                    entry.transcript.phase(),
+                    entry.transcript.contributor,
+                    entry.transcript.params_hash
+                );
+            }
+        }
+
+        CeremonyCommand::Finalize { dir, out_dir } => {
+            let ceremony = crate::crypto::ceremony::Ceremony::open(dir)?;
+            let manifest = ceremony.finalize(out_dir)?;
+            println!("Wrote final parameters to: {}", out_dir.display());
+            println!("Parameter set hash: {}", manifest.params_hash);
+        }
+    }
+
+    Ok(())
+}
+
+fn execute_keystore_command(cli: &Cli, action: &KeystoreCommand) -> crate::utils::Result<()> {
+    match action {
+        KeystoreCommand::Migrate => {
+            let passphrase = read_passphrase(true)?;
+            let keystore = Keystore::migrate_legacy(&cli.keypair_file, &passphrase)?;
+            println!("Encrypted keypair file: {}", cli.keypair_file.display());
+            println!("Public key: {}", hex::encode(keystore.keypair().public_key.as_bytes()));
+            for game in keystore.games() {
+                println!("  DH key for game: {}", game);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn export_game(cli: &Cli, out: &PathBuf) -> crate::utils::Result<()> {
+    let game = cli.game
+        .ok_or_else(|| crate::utils::Error::InvalidInput(
+            "--game is required, see `zkretctl games list`".to_string()
+        ))?;
+    let mut storage = open_storage(cli, Some(&game)).await?;
+    storage.sync().await?;
+    let file = std::fs::File::create(out)
         .map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
-    
+
+    // The storage may hold other games, keep only the records of this one
+    let keep = |record: &StorageRecord, data: &[u8]| is_game_record(record.record_type, data, &game);
+    let root = crate::filecoin::car::export_car(storage.as_ref(), keep, std::io::BufWriter::new(file)).await?;
+    println!("Exported game to: {}", out.display());
+    println!("Root CID: {}", root);
+
     Ok(())
 }
 
-fn load_dh_keypair(base_path: &PathBuf) -> crate::utils::Result<DHKeyExchange> {
-    let dh_path = base_path.with_extension("dh");
-    let hex_data = std::fs::read_to_string(dh_path)
+async fn import_game(cli: &Cli, car: &PathBuf) -> crate::utils::Result<()> {
+    let mut storage = open_storage(cli, cli.game.as_ref()).await?;
+    let file = std::fs::File::open(car)
         .map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
-    
-    let secret_bytes = hex::decode(hex_data.trim())
-        .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
-    
-    DHKeyExchange::from_secret_bytes(&secret_bytes)
+
+    let records = crate::filecoin::car::import_car(storage.as_mut(), std::io::BufReader::new(file)).await?;
+    println!("Imported {} records from: {}", records.len(), car.display());
+
+    Ok(())
+}
+
+async fn execute_games_command(cli: &Cli, action: &GamesCommand) -> crate::utils::Result<()> {
+    match action {
+        GamesCommand::Create {
+            name,
+            min_participants,
+            max_participants,
+            enter_deadline,
+            choice_deadline,
+            recovery_deadline,
+            reveal_deadline,
+            budget,
+            calibration,
+        } => {
+            let keystore = Keystore::load(&cli.keypair_file, &read_passphrase(false)?)?;
+            // The game is bound to the parameter set every player must load
+            let params = crate::crypto::params::inspect_params(&cli.params_dir)?;
+            let config = GameConfig {
+                min_participants: *min_participants,
+                max_participants: *max_participants,
+                enter_deadline: *enter_deadline,
+                choice_deadline: *choice_deadline,
+                recovery_deadline: *recovery_deadline,
+                reveal_deadline: *reveal_deadline,
+                budget: budget.clone(),
+                genesis_timestamp: if *calibration { CALIBRATION_GENESIS } else { MAINNET_GENESIS },
+                ..GameConfig::new(name, keystore.keypair(), &params.params_hash)
+            };
+            let config = config.sign(keystore.keypair())?;
+            let game = config.id()?;
+
+            let mut storage: Box<dyn StorageBackend> = match &cli.local_dir {
+                Some(local_dir) => Box::new(LocalStorage::open(local_dir)?),
+                None => {
+                    // Records are timed by epoch, which only means something
+                    // against the genesis of the chain they land on
+                    let storage = open_filecoin_storage(cli, Some(&game)).await?;
+                    let network = storage.client().state_network_name().await?;
+                    let expected = if *calibration { "calibrationnet" } else { "mainnet" };
+                    if network != expected {
+                        return Err(crate::utils::Error::InvalidInput(format!(
+                            "The node is on {} but the game would count epochs on {}, check --calibration",
+                            network, expected
+                        )));
+                    }
+                    Box::new(storage)
+                }
+            };
+            storage.put(config.encode()?, RecordType::GameConfig).await?;
+            println!("Created game {}", name);
+            println!("Game id: {}", game);
+            println!("Parameter set: {}", params.params_hash);
+            println!("Share the id with participants, they play with --game {}", game);
+        }
+
+        GamesCommand::List => {
+            let mut storage = open_storage(cli, None).await?;
+            storage.sync().await?;
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            for (game, signed) in list_games(storage.as_ref()).await? {
+                println!(
+                    "{}  {} ({:?}, organizer {})",
+                    game, signed.config.name, signed.config.phase_at(now), hex::encode(&signed.config.organizer)
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn execute_index_command(cli: &Cli, action: &IndexCommand) -> crate::utils::Result<()> {
+    match action {
+        IndexCommand::Rebuild { from_car, from_local_dir } => {
+            let records = match (from_car, from_local_dir) {
+                (Some(car), _) => {
+                    let file = std::fs::File::open(car)
+                        .map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
+                    let car = crate::filecoin::car::read_car(std::io::BufReader::new(file))?;
+                    // The manifest's times are only claims of whoever built the
+                    // file; Filecoin indexes learn the epochs again on sync
+                    let now = unix_now();
+                    car.manifest()?.records.iter()
+                        .map(|entry| Ok(StorageRecord { timestamp: now, epoch: None, ..entry.to_record()? }))
+                        .collect::<crate::utils::Result<Vec<_>>>()?
+                }
+                (None, Some(dir)) => {
+                    let source = LocalStorage::open(dir)?;
+                    let mut records = Vec::new();
+                    for record_type in RecordType::ALL {
+                        records.extend(source.list(record_type).await?);
+                    }
+                    records
+                }
+                (None, None) => {
+                    return Err(crate::utils::Error::InvalidInput(
+                        "Either --from-car or --from-local-dir is required".to_string()
+                    ));
+                }
+            };
+
+            let index = RecordIndex::open(&index_path(cli))?;
+            let count = index.rebuild(records)?;
+            println!("Rebuilt {} with {} records", index.path().display(), count);
+        }
+    }
+
+    Ok(())
+}
+
+async fn list_deals(cli: &Cli, refresh: bool) -> crate::utils::Result<()> {
+    if cli.local_dir.is_some() {
+        return Err(crate::utils::Error::InvalidInput(
+            "Records in a local directory have no storage deals".to_string()
+        ));
+    }
+
+    if refresh {
+        let mut storage = open_filecoin_storage(cli, cli.game.as_ref()).await?;
+        let changed = storage.poll_deals().await?;
+        println!("{} deals changed state", changed);
+        print_deals(storage.index(), storage.deals())
+    } else {
+        let index = RecordIndex::open(&cli.index_file)?;
+        let deals = DealManager::open(&cli.deals_file, deal_config(cli))?;
+        print_deals(&index, &deals)
+    }
+}
+
+fn print_deals(index: &RecordIndex, deals: &DealManager) -> crate::utils::Result<()> {
+    for record in index.records()? {
+        print!("{:?} {}: ", record.record_type, record.content_cid);
+        match deals.deal_for(&record.content_cid) {
+            // Never reached a miner
+            Some(deal) if deal.proposal_cid.is_empty() => println!("{}", deal.state),
+            Some(deal) => println!(
+                "{} with {} (proposal {}, {} polls)",
+                deal.state, deal.miner, deal.proposal_cid, deal.attempts
+            ),
+            // Records synced from other participants are stored by their authors
+            None => println!("no deal from this node"),
+        }
+    }
+
+    Ok(())
+}
+
+// Helper functions for file I/O and protocol queries
+fn index_path(cli: &Cli) -> PathBuf {
+    match &cli.local_dir {
+        Some(local_dir) => local_dir.join(crate::filecoin::local::INDEX_FILE),
+        None => cli.index_file.clone(),
+    }
+}
+
+/// Storage for the records of `game`. On Filecoin the game decides which
+/// address records are announced to and synced from.
+async fn open_storage(cli: &Cli, game: Option<&GameId>) -> crate::utils::Result<Box<dyn StorageBackend>> {
+    match &cli.local_dir {
+        Some(local_dir) => Ok(Box::new(LocalStorage::open(local_dir)?)),
+        None => Ok(Box::new(open_filecoin_storage(cli, game).await?)),
+    }
+}
+
+fn deal_config(cli: &Cli) -> DealConfig {
+    DealConfig {
+        miners: cli.miners.clone(),
+        max_price_per_gib_epoch: cli.max_price,
+        duration_epochs: cli.deal_duration,
+        ..DealConfig::default()
+    }
+}
+
+async fn open_filecoin_storage(cli: &Cli, game: Option<&GameId>) -> crate::utils::Result<FilecoinStorage> {
+    let auth_token = cli.auth_token.as_deref()
+        .ok_or_else(|| crate::utils::Error::InvalidInput(
+            "--auth-token or FILECOIN_AUTH_TOKEN is required unless --local-dir is given".to_string()
+        ))?;
+
+    let timeout = Duration::from_secs(cli.rpc_timeout);
+    let client = LotusClient::new(&cli.filecoin_endpoint, Some(auth_token), timeout)?;
+    let deals = DealManager::open(&cli.deals_file, deal_config(cli))?;
+
+    // Cheapest sources first; a paid retrieval deal is the last resort
+    let mut sources = vec![RetrievalSource::Cache(cli.cache_dir.clone())];
+    sources.extend(cli.gateways.iter().cloned().map(RetrievalSource::Gateway));
+    sources.push(RetrievalSource::Lotus(client.clone()));
+    let retriever = Retriever::new(sources, timeout)?;
+
+    match game {
+        Some(game) => {
+            let mut storage = FilecoinStorage::new(client, &cli.index_file, game.as_bytes(), deals, retriever).await?;
+            // Nothing sent before the game was created belongs to it. A game
+            // that is not indexed yet is looked for on the whole chain once
+            if let Ok(signed) = load_game(&storage, game).await {
+                storage.set_start_epoch(signed.config.creation_epoch());
+            }
+            Ok(storage)
+        }
+        // Listing games, and records outside any game such as ceremony
+        // transcripts, use the discovery address
+        None => FilecoinStorage::discovery(client, &cli.index_file, deals, retriever).await,
+    }
+}
+
+fn read_passphrase(confirm: bool) -> crate::utils::Result<Zeroizing<String>> {
+    let passphrase = match std::env::var(PASSPHRASE_ENV) {
+        Ok(passphrase) => Zeroizing::new(passphrase),
+        Err(_) => {
+            let passphrase = Zeroizing::new(rpassword::prompt_password("Keystore passphrase: ")
+                .map_err(|e| crate::utils::Error::FileError(e.to_string()))?);
+
+            if confirm {
+                let repeated = Zeroizing::new(rpassword::prompt_password("Repeat passphrase: ")
+                    .map_err(|e| crate::utils::Error::FileError(e.to_string()))?);
+                if *repeated != *passphrase {
+                    return Err(crate::utils::Error::InvalidInput("Passphrases do not match".to_string()));
+                }
+            }
+
+            passphrase
+        }
+    };
+
+    if confirm && passphrase.is_empty() {
+        return Err(crate::utils::Error::InvalidInput("Passphrase must not be empty".to_string()));
+    }
+
+    Ok(passphrase)
+}
+
+/// Generate and save a DH key for `game` unless the keystore has one. A
+/// choice that may already be published keeps the key it was made with.
+fn ensure_dh_key(keystore: &mut Keystore, game: &GameId, path: &Path, passphrase: &str) -> crate::utils::Result<()> {
+    if keystore.dh_key(&game.to_string()).is_none() {
+        keystore.insert_dh_key(&game.to_string(), DHKeyExchange::generate());
+        keystore.save(path, passphrase)?;
+    }
+
+    Ok(())
 }
 
-async fn check_if_chosen(
-    protocol: &SecretSantaProtocol,
+fn dh_key_for_game<'a>(keystore: &'a Keystore, game: &GameId) -> crate::utils::Result<&'a DHKeyExchange> {
+    keystore.dh_key(&game.to_string())
+        .ok_or_else(|| crate::utils::Error::ProtocolError(
+            "No DH key stored for this game, make a choice first".to_string()
+        ))
+}
+
+fn check_if_chosen<S: StorageBackend>(
+    protocol: &SecretSantaProtocol<S>,
     public_key: &[u8],
-) -> crate::utils::Result<bool> {
-    // Implementation would check if this public key appears in any choice transaction
-    todo!("Implement check_if_chosen")
+) -> bool {
+    protocol.state().choice_for(public_key).is_some()
 }
 
-async fn get_santa_dh_public_key(
-    protocol: &SecretSantaProtocol,
+fn get_santa_dh_public_key<S: StorageBackend>(
+    protocol: &SecretSantaProtocol<S>,
     public_key: &[u8],
 ) -> crate::utils::Result<Vec<u8>> {
-    // Implementation would find the choice transaction where this key was chosen
-    // and return the chooser's DH public key
-    todo!("Implement get_santa_dh_public_key")
+    protocol.state().choice_for(public_key)
+        .map(|choice| choice.chooser_dh_public_key.clone())
+        .ok_or_else(|| crate::utils::Error::ProtocolError(
+            "Nobody has chosen you yet".to_string()
+        ))
 }
 
-async fn get_santee_revealed_info(
-    protocol: &SecretSantaProtocol,
-    keypair: &KeyPair,
+/// Decrypt the REVEAL addressed to our DH key, if the santee has published it
+fn get_santee_revealed_info<S: StorageBackend>(
+    protocol: &SecretSantaProtocol<S>,
     dh_keypair: &DHKeyExchange,
 ) -> crate::utils::Result<Option<String>> {
-    // Implementation would find reveal transaction from chosen participant
-    // and decrypt their information using DH shared secret
-    todo!("Implement get_santee_revealed_info")
+    let reveal = match protocol.state().reveal_for(&dh_keypair.public_key()) {
+        Some(reveal) => reveal,
+        None => return Ok(None),
+    };
+
+    let game_id = protocol.game_id().as_bytes();
+    let shared_secret = dh_keypair.compute_shared_secret(&reveal.dh_public_key, game_id)?;
+    let associated_data = crate::crypto::encryption::associated_data(
+        game_id,
+        &reveal.dh_public_key,
+        &dh_keypair.public_key(),
+    );
+    let info = decrypt_data(&reveal.encrypted_identity, &shared_secret, &associated_data)?;
+
+    String::from_utf8(info)
+        .map(Some)
+        .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))
 }
@@ -0,0 +1,620 @@
+//! Two-phase multi-party trusted setup for the game circuits.
+//!
+//! Phase 1 builds powers of tau (see `powers_of_tau`): every contributor
+//! multiplies the circuit-independent secrets `τ`, `α` and `β` by fresh
+//! factors and discards them. `derive` then closes phase 1 and computes the
+//! Groth16 keys of every circuit from the final powers.
+//!
+//! Phase 2 re-randomises `δ`. Every contributor multiplies the `δ` of each
+//! circuit's keys by a fresh secret `δ'` (and divides the `H` and `L` queries
+//! by it), then throws `δ'` away.
+//!
+//! Nobody knows the final trapdoor as long as one contributor in each phase
+//! discarded their secrets. Each contribution carries a proof of knowledge of
+//! its secrets: a random `s` in G1, `s·x`, and `r·x` where `r` is hashed into
+//! G2 from the ceremony context and `s`, `s·x`. Verification checks those
+//! proofs, that each round is the previous one moved by the proven secrets,
+//! and that the phase-2 starting keys are exactly the ones the final powers
+//! give.
+
+use super::circuits::{ChoiceCircuit, EnterCircuit, RecoveryCircuit, RevealCircuit};
+use super::params::{self, ParamsManifest};
+use super::powers_of_tau::{self, PowersContribution, PowersOfTau};
+use super::zk_proofs::ProofType;
+use ark_bn254::{Bn254, Fq, Fq2, Fr, G1Affine, G1Projective, G2Affine};
+use ark_ec::pairing::Pairing;
+use ark_ec::{AffineRepr, CurveGroup, VariableBaseMSM};
+use ark_ff::{Field, PrimeField, UniformRand, Zero};
+use ark_groth16::{ProvingKey, VerifyingKey};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use rand::rngs::OsRng;
+use rand::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Name of the ceremony log inside a ceremony directory
+pub const CEREMONY_FILE: &str = "ceremony.json";
+
+const CONTEXT_DOMAIN: &[u8] = b"zkret-santa/ceremony/v2";
+const HASH_TO_G2_DOMAIN: &[u8] = b"zkret-santa/ceremony/hash-to-g2";
+
+/// One contributor's update to a single circuit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CircuitContribution {
+    pub proof_type: ProofType,
+    /// New `δ·G1` and `δ·G2` after this contribution
+    pub delta_g1: Vec<u8>,
+    pub delta_g2: Vec<u8>,
+    /// Proof of knowledge of `δ'`
+    pub s_g1: Vec<u8>,
+    pub s_delta_g1: Vec<u8>,
+    pub r_delta_g2: Vec<u8>,
+}
+
+/// What a contribution changed, depending on the phase it was made in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ContributionUpdate {
+    Powers(PowersContribution),
+    Circuits(Vec<CircuitContribution>),
+}
+
+/// Transcript of one contribution round.
+///
+/// This is what gets published as a `CeremonyContribution` storage record.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContributionTranscript {
+    pub index: usize,
+    pub contributor: String,
+    pub previous_hash: String,
+    /// Hash of the powers or parameters this contribution produced
+    pub params_hash: String,
+    pub update: ContributionUpdate,
+    pub timestamp: u64,
+}
+
+impl ContributionTranscript {
+    pub fn phase(&self) -> u8 {
+        match self.update {
+            ContributionUpdate::Powers(_) => 1,
+            ContributionUpdate::Circuits(_) => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CeremonyEntry {
+    pub transcript: ContributionTranscript,
+    /// CID of the published transcript record, once stored
+    pub record_cid: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CeremonyLog {
+    /// Hash of the initial powers of tau
+    pub initial_hash: String,
+    /// Number of phase-1 contributions, set once the circuit keys are derived
+    pub phase1_rounds: Option<usize>,
+    pub contributions: Vec<CeremonyEntry>,
+}
+
+/// A ceremony directory: the powers of tau of every phase-1 round, one
+/// parameter directory per phase-2 round, and the log
+pub struct Ceremony {
+    dir: PathBuf,
+    log: CeremonyLog,
+}
+
+impl Ceremony {
+    /// Start a ceremony in `dir` with powers of tau large enough for every circuit
+    pub fn init(dir: &Path) -> crate::utils::Result<Self> {
+        if dir.join(CEREMONY_FILE).exists() {
+            return Err(crate::utils::Error::FileError(format!(
+                "A ceremony already exists in {}", dir.display()
+            )));
+        }
+        std::fs::create_dir_all(dir)
+            .map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
+
+        let powers = PowersOfTau::new(required_size()?)?;
+        let ceremony = Self {
+            dir: dir.to_path_buf(),
+            log: CeremonyLog {
+                initial_hash: write_powers(&powers_file(dir, 0), &powers)?,
+                phase1_rounds: None,
+                contributions: Vec::new(),
+            },
+        };
+        ceremony.save()?;
+
+        Ok(ceremony)
+    }
+
+    pub fn open(dir: &Path) -> crate::utils::Result<Self> {
+        let data = std::fs::read(dir.join(CEREMONY_FILE))
+            .map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
+        let log = serde_json::from_slice(&data)
+            .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            log,
+        })
+    }
+
+    pub fn log(&self) -> &CeremonyLog {
+        &self.log
+    }
+
+    /// Number of contributions made so far, over both phases
+    pub fn rounds(&self) -> usize {
+        self.log.contributions.len()
+    }
+
+    /// Add a contribution on top of the latest round of the current phase
+    pub fn contribute<R: RngCore + CryptoRng>(
+        &mut self,
+        contributor: &str,
+        rng: &mut R,
+    ) -> crate::utils::Result<ContributionTranscript> {
+        let index = self.rounds() + 1;
+
+        let (previous_hash, params_hash, update) = if self.log.phase1_rounds.is_none() {
+            let (previous_hash, previous) = read_powers(&powers_file(&self.dir, index - 1))?;
+            let context = contribution_context(index, contributor, &previous_hash);
+
+            let (powers, contribution) = previous.contribute(&context, rng)?;
+            let params_hash = write_powers(&powers_file(&self.dir, index), &powers)?;
+            (previous_hash, params_hash, ContributionUpdate::Powers(contribution))
+        } else {
+            let (previous_manifest, previous_keys, _) = params::load_params(&round_dir(&self.dir, index - 1))?;
+            let previous_hash = round_hash(&previous_manifest);
+            let context = contribution_context(index, contributor, &previous_hash);
+
+            let mut proving_keys = HashMap::new();
+            let mut circuits = Vec::new();
+            for proof_type in ProofType::ALL {
+                let proving_key = previous_keys.get(&proof_type)
+                    .ok_or_else(|| crate::utils::Error::CryptoError(format!("Missing proving key for {:?}", proof_type)))?;
+
+                let (updated, contribution) = contribute_circuit(proof_type, proving_key, &context, rng)?;
+                proving_keys.insert(proof_type, updated);
+                circuits.push(contribution);
+            }
+
+            let manifest = write_round(&round_dir(&self.dir, index), &proving_keys)?;
+            (previous_hash, round_hash(&manifest), ContributionUpdate::Circuits(circuits))
+        };
+
+        let transcript = ContributionTranscript {
+            index,
+            contributor: contributor.to_string(),
+            previous_hash,
+            params_hash,
+            update,
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        };
+
+        self.log.contributions.push(CeremonyEntry {
+            transcript: transcript.clone(),
+            record_cid: None,
+        });
+        self.save()?;
+
+        Ok(transcript)
+    }
+
+    /// Close phase 1 and derive every circuit's keys from the final powers of
+    /// tau, which phase 2 then builds on
+    pub fn derive(&mut self) -> crate::utils::Result<ParamsManifest> {
+        if self.log.phase1_rounds.is_some() {
+            return Err(crate::utils::Error::CryptoError(
+                "The circuit keys have already been derived".to_string()
+            ));
+        }
+        if self.rounds() == 0 {
+            return Err(crate::utils::Error::CryptoError(
+                "Phase 1 needs at least one contribution before the circuit keys are derived".to_string()
+            ));
+        }
+
+        self.verify()?;
+
+        let phase1_rounds = self.rounds();
+        let (_, powers) = read_powers(&powers_file(&self.dir, phase1_rounds))?;
+        let manifest = write_round(&round_dir(&self.dir, phase1_rounds), &derive_proving_keys(&powers)?)?;
+
+        self.log.phase1_rounds = Some(phase1_rounds);
+        self.save()?;
+
+        Ok(manifest)
+    }
+
+    /// Remember where the transcript of round `index` was published
+    pub fn set_record_cid(&mut self, index: usize, cid: &str) -> crate::utils::Result<()> {
+        let entry = index.checked_sub(1)
+            .and_then(|i| self.log.contributions.get_mut(i))
+            .ok_or_else(|| crate::utils::Error::InvalidInput(format!("No contribution {}", index)))?;
+        entry.record_cid = Some(cid.to_string());
+        self.save()
+    }
+
+    /// Verify every round against the one before it
+    pub fn verify(&self) -> crate::utils::Result<()> {
+        let (initial_hash, mut previous_powers) = read_powers(&powers_file(&self.dir, 0))?;
+        if initial_hash != self.log.initial_hash || previous_powers != PowersOfTau::new(previous_powers.size())? {
+            return Err(crate::utils::Error::CryptoError(
+                "Initial powers of tau do not match the ceremony log".to_string()
+            ));
+        }
+
+        let phase1_rounds = self.log.phase1_rounds.unwrap_or(self.rounds());
+        if phase1_rounds > self.rounds() {
+            return Err(crate::utils::Error::CryptoError(
+                "The ceremony log closes phase 1 after contributions it does not have".to_string()
+            ));
+        }
+
+        let mut previous_hash = self.log.initial_hash.clone();
+        for (i, entry) in self.log.contributions[..phase1_rounds].iter().enumerate() {
+            let transcript = &entry.transcript;
+            let index = i + 1;
+            check_extends(transcript, index, &previous_hash)?;
+
+            let ContributionUpdate::Powers(contribution) = &transcript.update else {
+                return Err(crate::utils::Error::CryptoError(format!(
+                    "Contribution {} is not a phase-1 contribution", index
+                )));
+            };
+
+            let (hash, powers) = read_powers(&powers_file(&self.dir, index))?;
+            if hash != transcript.params_hash {
+                return Err(crate::utils::Error::CryptoError(format!(
+                    "Powers of tau of round {} do not match its transcript", index
+                )));
+            }
+
+            let context = contribution_context(index, &transcript.contributor, &previous_hash);
+            if !powers_of_tau::verify_contribution(&previous_powers, &powers, contribution, &context)? {
+                return Err(crate::utils::Error::CryptoError(format!(
+                    "Contribution {} by {} is invalid", index, transcript.contributor
+                )));
+            }
+
+            previous_hash = hash;
+            previous_powers = powers;
+        }
+
+        if self.log.phase1_rounds.is_none() {
+            return Ok(());
+        }
+
+        // Phase 2 has to start from exactly the keys the final powers give
+        let (manifest, mut previous_keys, _) = params::load_params(&round_dir(&self.dir, phase1_rounds))?;
+        if previous_keys != derive_proving_keys(&previous_powers)? {
+            return Err(crate::utils::Error::CryptoError(
+                "Derived circuit keys do not match the final powers of tau".to_string()
+            ));
+        }
+
+        let mut previous_hash = round_hash(&manifest);
+        for (i, entry) in self.log.contributions.iter().enumerate().skip(phase1_rounds) {
+            let transcript = &entry.transcript;
+            let index = i + 1;
+            check_extends(transcript, index, &previous_hash)?;
+
+            let ContributionUpdate::Circuits(circuits) = &transcript.update else {
+                return Err(crate::utils::Error::CryptoError(format!(
+                    "Contribution {} is not a phase-2 contribution", index
+                )));
+            };
+
+            let (manifest, keys, _) = params::load_params(&round_dir(&self.dir, index))?;
+            if round_hash(&manifest) != transcript.params_hash {
+                return Err(crate::utils::Error::CryptoError(format!(
+                    "Parameters of round {} do not match its transcript", index
+                )));
+            }
+
+            let context = contribution_context(index, &transcript.contributor, &previous_hash);
+            for proof_type in ProofType::ALL {
+                let contribution = circuits.iter()
+                    .find(|c| c.proof_type == proof_type)
+                    .ok_or_else(|| crate::utils::Error::CryptoError(format!(
+                        "Contribution {} is missing {:?}", index, proof_type
+                    )))?;
+
+                let before = previous_keys.get(&proof_type)
+                    .ok_or_else(|| crate::utils::Error::CryptoError(format!("Missing proving key for {:?}", proof_type)))?;
+                let after = keys.get(&proof_type)
+                    .ok_or_else(|| crate::utils::Error::CryptoError(format!("Missing proving key for {:?}", proof_type)))?;
+
+                if !verify_circuit_contribution(before, after, contribution, &context)? {
+                    return Err(crate::utils::Error::CryptoError(format!(
+                        "Contribution {} by {} is invalid for {:?}",
+                        index, transcript.contributor, proof_type
+                    )));
+                }
+            }
+
+            previous_hash = transcript.params_hash.clone();
+            previous_keys = keys;
+        }
+
+        Ok(())
+    }
+
+    /// Verify the whole ceremony and write the final round as a parameter directory
+    pub fn finalize(&self, out_dir: &Path) -> crate::utils::Result<ParamsManifest> {
+        match self.log.phase1_rounds {
+            Some(phase1_rounds) if self.rounds() > phase1_rounds => {}
+            Some(_) => return Err(crate::utils::Error::CryptoError(
+                "Cannot finalize a ceremony without phase-2 contributions".to_string()
+            )),
+            None => return Err(crate::utils::Error::CryptoError(
+                "Cannot finalize a ceremony before the circuit keys are derived".to_string()
+            )),
+        }
+
+        self.verify()?;
+
+        let (_manifest, proving_keys, _) = params::load_params(&round_dir(&self.dir, self.rounds()))?;
+        write_round(out_dir, &proving_keys)
+    }
+
+    fn save(&self) -> crate::utils::Result<()> {
+        let data = serde_json::to_vec_pretty(&self.log)
+            .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+        std::fs::write(self.dir.join(CEREMONY_FILE), data)
+            .map_err(|e| crate::utils::Error::FileError(e.to_string()))
+    }
+}
+
+/// Apply a secret `δ'` to one circuit's proving key
+pub fn contribute_circuit<R: RngCore + CryptoRng>(
+    proof_type: ProofType,
+    proving_key: &ProvingKey<Bn254>,
+    context: &[u8],
+    rng: &mut R,
+) -> crate::utils::Result<(ProvingKey<Bn254>, CircuitContribution)> {
+    let mut delta = Fr::rand(rng);
+    while delta.is_zero() {
+        delta = Fr::rand(rng);
+    }
+    let delta_inverse = delta.inverse().expect("non-zero field element is invertible");
+
+    let mut updated = proving_key.clone();
+    updated.delta_g1 = (proving_key.delta_g1 * delta).into_affine();
+    updated.vk.delta_g2 = (proving_key.vk.delta_g2 * delta).into_affine();
+    updated.h_query = scale_g1(&proving_key.h_query, delta_inverse);
+    updated.l_query = scale_g1(&proving_key.l_query, delta_inverse);
+
+    let s_g1 = (G1Affine::generator() * Fr::rand(rng)).into_affine();
+    let s_delta_g1 = (s_g1 * delta).into_affine();
+    let r_g2 = hash_to_g2(context, &s_g1, &s_delta_g1)?;
+    let r_delta_g2 = (r_g2 * delta).into_affine();
+
+    let contribution = CircuitContribution {
+        proof_type,
+        delta_g1: to_bytes(&updated.delta_g1)?,
+        delta_g2: to_bytes(&updated.vk.delta_g2)?,
+        s_g1: to_bytes(&s_g1)?,
+        s_delta_g1: to_bytes(&s_delta_g1)?,
+        r_delta_g2: to_bytes(&r_delta_g2)?,
+    };
+
+    Ok((updated, contribution))
+}
+
+/// Check that `after` is `before` updated by the secret proven in `contribution`
+pub fn verify_circuit_contribution(
+    before: &ProvingKey<Bn254>,
+    after: &ProvingKey<Bn254>,
+    contribution: &CircuitContribution,
+    context: &[u8],
+) -> crate::utils::Result<bool> {
+    // Everything outside δ, H and L must be untouched
+    if !same_circuit_independent_part(before, after) {
+        return Ok(false);
+    }
+
+    let delta_g1: G1Affine = from_bytes(&contribution.delta_g1)?;
+    let delta_g2: G2Affine = from_bytes(&contribution.delta_g2)?;
+    let s_g1: G1Affine = from_bytes(&contribution.s_g1)?;
+    let s_delta_g1: G1Affine = from_bytes(&contribution.s_delta_g1)?;
+    let r_delta_g2: G2Affine = from_bytes(&contribution.r_delta_g2)?;
+
+    if delta_g1 != after.delta_g1 || delta_g2 != after.vk.delta_g2 {
+        return Ok(false);
+    }
+    if s_g1.is_zero() || s_delta_g1.is_zero() || delta_g1.is_zero() {
+        return Ok(false);
+    }
+
+    // Proof of knowledge of δ': e(s, r·δ') == e(s·δ', r)
+    let r_g2 = hash_to_g2(context, &s_g1, &s_delta_g1)?;
+    if Bn254::pairing(s_g1, r_delta_g2) != Bn254::pairing(s_delta_g1, r_g2) {
+        return Ok(false);
+    }
+
+    // New δ is the old δ times δ': e(δ_old·G1, r·δ') == e(δ_new·G1, r)
+    if Bn254::pairing(before.delta_g1, r_delta_g2) != Bn254::pairing(after.delta_g1, r_g2) {
+        return Ok(false);
+    }
+
+    // δ in G2 moved by the same factor as δ in G1. The setup samples its own
+    // generators, so compare against the previous round rather than G1/G2 bases.
+    if Bn254::pairing(after.delta_g1, before.vk.delta_g2) != Bn254::pairing(before.delta_g1, after.vk.delta_g2) {
+        return Ok(false);
+    }
+
+    // H and L were divided by the same δ': random linear combinations must pair equally
+    let mut rng = OsRng;
+    for (old_query, new_query) in [(&before.h_query, &after.h_query), (&before.l_query, &after.l_query)] {
+        if old_query.len() != new_query.len() {
+            return Ok(false);
+        }
+
+        let challenges: Vec<Fr> = (0..old_query.len()).map(|_| Fr::rand(&mut rng)).collect();
+        let old_combined = G1Projective::msm(old_query, &challenges)
+            .map_err(|_| crate::utils::Error::CryptoError("MSM length mismatch".to_string()))?;
+        let new_combined = G1Projective::msm(new_query, &challenges)
+            .map_err(|_| crate::utils::Error::CryptoError("MSM length mismatch".to_string()))?;
+
+        if Bn254::pairing(new_combined, after.vk.delta_g2) != Bn254::pairing(old_combined, before.vk.delta_g2) {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Hash of a round's parameters, covering every proving and verifying key file
+pub fn round_hash(manifest: &ParamsManifest) -> String {
+    let mut hasher = Sha3_256::new();
+    for circuit in &manifest.circuits {
+        hasher.update(circuit.proof_type.name().as_bytes());
+        hasher.update(circuit.proving_key_hash.as_bytes());
+        hasher.update(circuit.verifying_key_hash.as_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
+
+fn contribution_context(index: usize, contributor: &str, previous_hash: &str) -> Vec<u8> {
+    let mut hasher = Sha3_256::new();
+    hasher.update(CONTEXT_DOMAIN);
+    hasher.update((index as u64).to_le_bytes());
+    hasher.update((contributor.len() as u64).to_le_bytes());
+    hasher.update(contributor.as_bytes());
+    hasher.update(previous_hash.as_bytes());
+    hasher.finalize().to_vec()
+}
+
+fn check_extends(transcript: &ContributionTranscript, index: usize, previous_hash: &str) -> crate::utils::Result<()> {
+    if transcript.index != index || transcript.previous_hash != previous_hash {
+        return Err(crate::utils::Error::CryptoError(format!(
+            "Contribution {} does not extend the previous round", index
+        )));
+    }
+    Ok(())
+}
+
+fn round_dir(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("round-{:04}", index))
+}
+
+fn powers_file(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("powers-{:04}.bin", index))
+}
+
+/// Write the powers and return the hash of the file
+fn write_powers(path: &Path, powers: &PowersOfTau) -> crate::utils::Result<String> {
+    let data = to_bytes(powers)?;
+    std::fs::write(path, &data)
+        .map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
+    Ok(params::content_hash(&data))
+}
+
+fn read_powers(path: &Path) -> crate::utils::Result<(String, PowersOfTau)> {
+    let data = std::fs::read(path)
+        .map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
+    Ok((params::content_hash(&data), from_bytes(&data)?))
+}
+
+/// Largest evaluation domain any game circuit needs
+fn required_size() -> crate::utils::Result<usize> {
+    Ok(powers_of_tau::required_size(EnterCircuit::blank())?
+        .max(powers_of_tau::required_size(ChoiceCircuit::blank())?)
+        .max(powers_of_tau::required_size(RevealCircuit::blank())?)
+        .max(powers_of_tau::required_size(RecoveryCircuit::blank())?))
+}
+
+fn derive_proving_keys(powers: &PowersOfTau) -> crate::utils::Result<HashMap<ProofType, ProvingKey<Bn254>>> {
+    let mut proving_keys = HashMap::new();
+    for proof_type in ProofType::ALL {
+        let proving_key = match proof_type {
+            ProofType::EnterPhase => powers.derive_proving_key(EnterCircuit::blank())?,
+            ProofType::ChoicePhase => powers.derive_proving_key(ChoiceCircuit::blank())?,
+            ProofType::RevealPhase => powers.derive_proving_key(RevealCircuit::blank())?,
+            ProofType::RecoveryPhase => powers.derive_proving_key(RecoveryCircuit::blank())?,
+        };
+        proving_keys.insert(proof_type, proving_key);
+    }
+    Ok(proving_keys)
+}
+
+fn write_round(
+    dir: &Path,
+    proving_keys: &HashMap<ProofType, ProvingKey<Bn254>>,
+) -> crate::utils::Result<ParamsManifest> {
+    let verifying_keys: HashMap<ProofType, VerifyingKey<Bn254>> = proving_keys.iter()
+        .map(|(proof_type, pk)| (*proof_type, pk.vk.clone()))
+        .collect();
+
+    params::write_params(dir, proving_keys, &verifying_keys)
+}
+
+fn same_circuit_independent_part(before: &ProvingKey<Bn254>, after: &ProvingKey<Bn254>) -> bool {
+    before.vk.alpha_g1 == after.vk.alpha_g1
+        && before.vk.beta_g2 == after.vk.beta_g2
+        && before.vk.gamma_g2 == after.vk.gamma_g2
+        && before.vk.gamma_abc_g1 == after.vk.gamma_abc_g1
+        && before.beta_g1 == after.beta_g1
+        && before.a_query == after.a_query
+        && before.b_g1_query == after.b_g1_query
+        && before.b_g2_query == after.b_g2_query
+}
+
+fn scale_g1(points: &[G1Affine], scalar: Fr) -> Vec<G1Affine> {
+    let scaled: Vec<G1Projective> = points.iter().map(|p| *p * scalar).collect();
+    G1Projective::normalize_batch(&scaled)
+}
+
+/// Try-and-increment hash into the prime-order subgroup of G2, used as the
+/// `r` of the proofs of knowledge
+pub fn hash_to_g2(context: &[u8], s_g1: &G1Affine, s_delta_g1: &G1Affine) -> crate::utils::Result<G2Affine> {
+    let mut seed = Sha3_256::new();
+    seed.update(HASH_TO_G2_DOMAIN);
+    seed.update(context);
+    seed.update(to_bytes(s_g1)?);
+    seed.update(to_bytes(s_delta_g1)?);
+    let seed = seed.finalize();
+
+    for counter in 0u64.. {
+        let coordinate = |part: u8| {
+            let mut hasher = Sha3_256::new();
+            hasher.update(seed);
+            hasher.update(counter.to_le_bytes());
+            hasher.update([part]);
+            Fq::from_le_bytes_mod_order(&hasher.finalize())
+        };
+
+        let x = Fq2::new(coordinate(0), coordinate(1));
+        if let Some(point) = G2Affine::get_point_from_x_unchecked(x, false) {
+            let point = point.clear_cofactor();
+            if !point.is_zero() {
+                return Ok(point);
+            }
+        }
+    }
+
+    unreachable!("hash_to_g2 always finds a point")
+}
+
+/// Compressed canonical encoding
+pub fn to_bytes<T: CanonicalSerialize>(value: &T) -> crate::utils::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    value.serialize_compressed(&mut bytes)
+        .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+    Ok(bytes)
+}
+
+/// Decode and validate a compressed canonical encoding
+pub fn from_bytes<T: CanonicalDeserialize>(bytes: &[u8]) -> crate::utils::Result<T> {
+    T::deserialize_compressed(bytes)
+        .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))
+}
@@ -0,0 +1,407 @@
+use super::merkle::{MerklePath, MERKLE_DEPTH};
+use super::public_inputs::hash_to_field;
+use ark_bn254::Fr;
+use ark_crypto_primitives::crh::poseidon::constraints::{CRHGadget, CRHParametersVar};
+use ark_crypto_primitives::crh::poseidon::CRH;
+use ark_crypto_primitives::crh::{CRHScheme, CRHSchemeGadget};
+use ark_crypto_primitives::sponge::poseidon::{find_poseidon_ark_and_mds, PoseidonConfig};
+use ark_ff::PrimeField;
+use ark_r1cs_std::alloc::AllocVar;
+use ark_r1cs_std::boolean::Boolean;
+use ark_r1cs_std::eq::EqGadget;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::select::CondSelectGadget;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use std::sync::OnceLock;
+
+/// Domain separator used when deriving the in-circuit identity secret
+const IDENTITY_DOMAIN: &[u8] = b"zkret-santa/identity/v1";
+
+/// Domain separator mixed into ENTER nullifiers
+const ENTER_NULLIFIER_DOMAIN: &[u8] = b"zkret-santa/nullifier/enter";
+
+/// Domain separator mixed into CHOICE nullifiers
+const CHOICE_NULLIFIER_DOMAIN: &[u8] = b"zkret-santa/nullifier/choice";
+
+// Poseidon parameters for BN254 with a width-3 state (rate 2, capacity 1)
+const POSEIDON_FULL_ROUNDS: usize = 8;
+const POSEIDON_PARTIAL_ROUNDS: usize = 57;
+const POSEIDON_ALPHA: u64 = 5;
+const POSEIDON_RATE: usize = 2;
+
+/// Poseidon configuration shared by every circuit and by the native hashing code
+pub fn poseidon_config() -> &'static PoseidonConfig<Fr> {
+    static CONFIG: OnceLock<PoseidonConfig<Fr>> = OnceLock::new();
+
+    CONFIG.get_or_init(|| {
+        let (ark, mds) = find_poseidon_ark_and_mds::<Fr>(
+            Fr::MODULUS_BIT_SIZE as u64,
+            POSEIDON_RATE,
+            POSEIDON_FULL_ROUNDS as u64,
+            POSEIDON_PARTIAL_ROUNDS as u64,
+            0,
+        );
+
+        PoseidonConfig::new(
+            POSEIDON_FULL_ROUNDS,
+            POSEIDON_PARTIAL_ROUNDS,
+            POSEIDON_ALPHA,
+            mds,
+            ark,
+            POSEIDON_RATE,
+            1,
+        )
+    })
+}
+
+/// Native Poseidon hash matching the in-circuit gadget
+pub fn poseidon_hash(inputs: &[Fr]) -> Fr {
+    CRH::<Fr>::evaluate(poseidon_config(), inputs)
+        .expect("Poseidon evaluation over a field slice cannot fail")
+}
+
+/// Derive the identity secret used inside the circuits from an ed25519 secret key
+pub fn identity_secret(secret_key: &[u8]) -> Fr {
+    hash_to_field(IDENTITY_DOMAIN, secret_key)
+}
+
+/// Commitment to an identity: `Poseidon(secret, pk_lo, pk_hi)`
+pub fn identity_commitment(identity_secret: Fr, public_key: [Fr; 2]) -> Fr {
+    poseidon_hash(&[identity_secret, public_key[0], public_key[1]])
+}
+
+fn enter_nullifier_domain() -> Fr {
+    Fr::from_le_bytes_mod_order(ENTER_NULLIFIER_DOMAIN)
+}
+
+/// One-time ENTER nullifier for a game: `Poseidon(secret, domain, game)`
+pub fn enter_nullifier(identity_secret: Fr, game: Fr) -> Fr {
+    poseidon_hash(&[identity_secret, enter_nullifier_domain(), game])
+}
+
+fn choice_nullifier_domain() -> Fr {
+    Fr::from_le_bytes_mod_order(CHOICE_NULLIFIER_DOMAIN)
+}
+
+/// One-time CHOICE nullifier for a game: `Poseidon(secret, domain, game)`
+pub fn choice_nullifier(identity_secret: Fr, game: Fr) -> Fr {
+    poseidon_hash(&[identity_secret, choice_nullifier_domain(), game])
+}
+
+/// ENTER phase circuit.
+///
+/// Proves knowledge of an identity secret `s` such that
+/// `commitment == Poseidon(s, pk_lo, pk_hi)` and that the nullifier was
+/// derived from `s` and the game, so the same secret cannot enter a game twice.
+///
+/// Public inputs, in order: `pk_lo`, `pk_hi`, `commitment`, `nullifier`, `game`.
+#[derive(Clone)]
+pub struct EnterCircuit {
+    pub identity_secret: Option<Fr>,
+    pub public_key: Option<[Fr; 2]>,
+    pub commitment: Option<Fr>,
+    pub nullifier: Option<Fr>,
+    pub game: Option<Fr>,
+}
+
+impl EnterCircuit {
+    /// Circuit without an assignment, used for parameter generation
+    pub fn blank() -> Self {
+        Self {
+            identity_secret: None,
+            public_key: None,
+            commitment: None,
+            nullifier: None,
+            game: None,
+        }
+    }
+}
+
+impl ConstraintSynthesizer<Fr> for EnterCircuit {
+    fn generate_constraints(self, cs: ConstraintSystemRef<Fr>) -> Result<(), SynthesisError> {
+        let [pk_lo, pk_hi] = allocate_limbs(cs.clone(), self.public_key, true)?;
+        let commitment = FpVar::new_input(cs.clone(), || {
+            self.commitment.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let nullifier = FpVar::new_input(cs.clone(), || {
+            self.nullifier.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let game = FpVar::new_input(cs.clone(), || {
+            self.game.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let secret = FpVar::new_witness(cs.clone(), || {
+            self.identity_secret.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        let params = CRHParametersVar::new_constant(cs.clone(), poseidon_config())?;
+        let computed = CRHGadget::<Fr>::evaluate(&params, &[secret.clone(), pk_lo, pk_hi])?;
+        computed.enforce_equal(&commitment)?;
+
+        // Nullifier: Poseidon(secret, domain, game)
+        let domain = FpVar::new_constant(cs, enter_nullifier_domain())?;
+        let computed_nullifier = CRHGadget::<Fr>::evaluate(&params, &[secret, domain, game])?;
+        computed_nullifier.enforce_equal(&nullifier)
+    }
+}
+
+/// CHOICE phase circuit.
+///
+/// Proves that the chooser's identity commitment is a leaf of the ENTER-set
+/// Merkle tree, that the chosen public key is not the chooser's own, and that
+/// the nullifier was derived from the chooser's identity secret and the game.
+/// The chooser's public key and position in the tree stay private.
+///
+/// Public inputs, in order: `root`, `nullifier`, `chosen_lo`, `chosen_hi`,
+/// `dh_lo`, `dh_hi`, `game`. The DH key is bound to the proof as a public
+/// input so it cannot be swapped out of a CHOICE record.
+#[derive(Clone)]
+pub struct ChoiceCircuit {
+    pub identity_secret: Option<Fr>,
+    pub chooser_public_key: Option<[Fr; 2]>,
+    pub path: Option<MerklePath>,
+    pub root: Option<Fr>,
+    pub nullifier: Option<Fr>,
+    pub chosen_public_key: Option<[Fr; 2]>,
+    pub dh_public_key: Option<[Fr; 2]>,
+    pub game: Option<Fr>,
+}
+
+impl ChoiceCircuit {
+    /// Circuit without an assignment, used for parameter generation
+    pub fn blank() -> Self {
+        Self {
+            identity_secret: None,
+            chooser_public_key: None,
+            path: None,
+            root: None,
+            nullifier: None,
+            chosen_public_key: None,
+            dh_public_key: None,
+            game: None,
+        }
+    }
+}
+
+impl ConstraintSynthesizer<Fr> for ChoiceCircuit {
+    fn generate_constraints(self, cs: ConstraintSystemRef<Fr>) -> Result<(), SynthesisError> {
+        let root = FpVar::new_input(cs.clone(), || {
+            self.root.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let nullifier = FpVar::new_input(cs.clone(), || {
+            self.nullifier.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let chosen = allocate_limbs(cs.clone(), self.chosen_public_key, true)?;
+        // Only bound as public inputs, no further constraints needed
+        let _dh = allocate_limbs(cs.clone(), self.dh_public_key, true)?;
+        let game = FpVar::new_input(cs.clone(), || {
+            self.game.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        let secret = FpVar::new_witness(cs.clone(), || {
+            self.identity_secret.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let chooser = allocate_limbs(cs.clone(), self.chooser_public_key, false)?;
+
+        let params = CRHParametersVar::new_constant(cs.clone(), poseidon_config())?;
+
+        // Membership: Poseidon(secret, pk) is a leaf under `root`
+        let leaf = CRHGadget::<Fr>::evaluate(
+            &params,
+            &[secret.clone(), chooser[0].clone(), chooser[1].clone()],
+        )?;
+        let computed_root = merkle_root_gadget(cs.clone(), &params, leaf, self.path.as_ref())?;
+        computed_root.enforce_equal(&root)?;
+
+        // Nullifier: Poseidon(secret, domain, game)
+        let domain = FpVar::new_constant(cs, choice_nullifier_domain())?;
+        let computed_nullifier = CRHGadget::<Fr>::evaluate(&params, &[secret, domain, game])?;
+        computed_nullifier.enforce_equal(&nullifier)?;
+
+        // No self-choice: the chosen key must differ from the chooser's in at least one limb
+        let same_lo = chooser[0].is_eq(&chosen[0])?;
+        let same_hi = chooser[1].is_eq(&chosen[1])?;
+        (same_lo & same_hi).enforce_equal(&Boolean::FALSE)
+    }
+}
+
+/// RECOVERY round circuit.
+///
+/// Lets a player who has not chosen pick a participant after the CHOICE
+/// deadline. Proves the player owns the commitment of their ENTER record and
+/// that the nullifier is their CHOICE nullifier for the game, so a recovery
+/// pick uses up the same one-time nullifier as a CHOICE. Unlike a CHOICE, the
+/// player's public key is public: resolving a dead-end needs to know who is
+/// stuck.
+///
+/// Public inputs, in order: `commitment`, `pk_lo`, `pk_hi`, `nullifier`,
+/// `chosen_lo`, `chosen_hi`, `dh_lo`, `dh_hi`, `game`.
+#[derive(Clone)]
+pub struct RecoveryCircuit {
+    pub identity_secret: Option<Fr>,
+    pub commitment: Option<Fr>,
+    pub public_key: Option<[Fr; 2]>,
+    pub nullifier: Option<Fr>,
+    pub chosen_public_key: Option<[Fr; 2]>,
+    pub dh_public_key: Option<[Fr; 2]>,
+    pub game: Option<Fr>,
+}
+
+impl RecoveryCircuit {
+    /// Circuit without an assignment, used for parameter generation
+    pub fn blank() -> Self {
+        Self {
+            identity_secret: None,
+            commitment: None,
+            public_key: None,
+            nullifier: None,
+            chosen_public_key: None,
+            dh_public_key: None,
+            game: None,
+        }
+    }
+}
+
+impl ConstraintSynthesizer<Fr> for RecoveryCircuit {
+    fn generate_constraints(self, cs: ConstraintSystemRef<Fr>) -> Result<(), SynthesisError> {
+        let commitment = FpVar::new_input(cs.clone(), || {
+            self.commitment.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let public_key = allocate_limbs(cs.clone(), self.public_key, true)?;
+        let nullifier = FpVar::new_input(cs.clone(), || {
+            self.nullifier.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let chosen = allocate_limbs(cs.clone(), self.chosen_public_key, true)?;
+        // Only bound as public inputs, no further constraints needed
+        let _dh = allocate_limbs(cs.clone(), self.dh_public_key, true)?;
+        let game = FpVar::new_input(cs.clone(), || {
+            self.game.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        let secret = FpVar::new_witness(cs.clone(), || {
+            self.identity_secret.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        let params = CRHParametersVar::new_constant(cs.clone(), poseidon_config())?;
+        let computed = CRHGadget::<Fr>::evaluate(
+            &params,
+            &[secret.clone(), public_key[0].clone(), public_key[1].clone()],
+        )?;
+        computed.enforce_equal(&commitment)?;
+
+        // Nullifier: the CHOICE nullifier Poseidon(secret, domain, game)
+        let domain = FpVar::new_constant(cs, choice_nullifier_domain())?;
+        let computed_nullifier = CRHGadget::<Fr>::evaluate(&params, &[secret, domain, game])?;
+        computed_nullifier.enforce_equal(&nullifier)?;
+
+        // No self-choice
+        let same_lo = public_key[0].is_eq(&chosen[0])?;
+        let same_hi = public_key[1].is_eq(&chosen[1])?;
+        (same_lo & same_hi).enforce_equal(&Boolean::FALSE)
+    }
+}
+
+/// REVEAL phase circuit.
+///
+/// Proves the revealer owns the identity commitment published in the ENTER
+/// record for `pk`, the key chosen in a CHOICE record. The Santa's DH key from
+/// that CHOICE record, the revealer's own DH key and the digest of the
+/// encrypted payload are bound to the proof as public inputs, so the payload
+/// cannot be re-addressed or replaced without a fresh proof from the revealer.
+///
+/// Public inputs, in order: `commitment`, `pk_lo`, `pk_hi`, `santa_dh_lo`,
+/// `santa_dh_hi`, `dh_lo`, `dh_hi`, `payload_digest`, `game`.
+#[derive(Clone)]
+pub struct RevealCircuit {
+    pub identity_secret: Option<Fr>,
+    pub commitment: Option<Fr>,
+    pub public_key: Option<[Fr; 2]>,
+    pub santa_dh_public_key: Option<[Fr; 2]>,
+    pub dh_public_key: Option<[Fr; 2]>,
+    pub payload_digest: Option<Fr>,
+    pub game: Option<Fr>,
+}
+
+impl RevealCircuit {
+    /// Circuit without an assignment, used for parameter generation
+    pub fn blank() -> Self {
+        Self {
+            identity_secret: None,
+            commitment: None,
+            public_key: None,
+            santa_dh_public_key: None,
+            dh_public_key: None,
+            payload_digest: None,
+            game: None,
+        }
+    }
+}
+
+impl ConstraintSynthesizer<Fr> for RevealCircuit {
+    fn generate_constraints(self, cs: ConstraintSystemRef<Fr>) -> Result<(), SynthesisError> {
+        let commitment = FpVar::new_input(cs.clone(), || {
+            self.commitment.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let [pk_lo, pk_hi] = allocate_limbs(cs.clone(), self.public_key, true)?;
+        // Only bound as public inputs, no further constraints needed
+        let _santa_dh = allocate_limbs(cs.clone(), self.santa_dh_public_key, true)?;
+        let _dh = allocate_limbs(cs.clone(), self.dh_public_key, true)?;
+        let _payload_digest = FpVar::new_input(cs.clone(), || {
+            self.payload_digest.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let _game = FpVar::new_input(cs.clone(), || {
+            self.game.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        let secret = FpVar::new_witness(cs.clone(), || {
+            self.identity_secret.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        let params = CRHParametersVar::new_constant(cs, poseidon_config())?;
+        let computed = CRHGadget::<Fr>::evaluate(&params, &[secret, pk_lo, pk_hi])?;
+
+        computed.enforce_equal(&commitment)
+    }
+}
+
+fn allocate_limbs(
+    cs: ConstraintSystemRef<Fr>,
+    limbs: Option<[Fr; 2]>,
+    public: bool,
+) -> Result<[FpVar<Fr>; 2], SynthesisError> {
+    let mut vars = Vec::with_capacity(2);
+    for i in 0..2 {
+        let value = || limbs.map(|l| l[i]).ok_or(SynthesisError::AssignmentMissing);
+        let var = if public {
+            FpVar::new_input(cs.clone(), value)?
+        } else {
+            FpVar::new_witness(cs.clone(), value)?
+        };
+        vars.push(var);
+    }
+
+    Ok([vars[0].clone(), vars[1].clone()])
+}
+
+fn merkle_root_gadget(
+    cs: ConstraintSystemRef<Fr>,
+    params: &CRHParametersVar<Fr>,
+    leaf: FpVar<Fr>,
+    path: Option<&MerklePath>,
+) -> Result<FpVar<Fr>, SynthesisError> {
+    let mut current = leaf;
+
+    for level in 0..MERKLE_DEPTH {
+        let sibling = FpVar::new_witness(cs.clone(), || {
+            path.map(|p| p.siblings[level]).ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let is_right = Boolean::new_witness(cs.clone(), || {
+            path.map(|p| p.is_right[level]).ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        let left = FpVar::conditionally_select(&is_right, &sibling, &current)?;
+        let right = FpVar::conditionally_select(&is_right, &current, &sibling)?;
+        current = CRHGadget::<Fr>::evaluate(params, &[left, right])?;
+    }
+
+    Ok(current)
+}
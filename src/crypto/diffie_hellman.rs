@@ -0,0 +1,102 @@
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use sha2::Sha256;
+use std::fmt;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+/// HKDF info prefix for symmetric keys derived from a DH shared secret
+const SHARED_KEY_DOMAIN: &[u8] = b"zkret-santa/dh/v1";
+
+/// X25519 key pair used between a Santa and their santee.
+///
+/// The secret is a `StaticSecret`, which is zeroized when dropped.
+pub struct DHKeyExchange {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+impl DHKeyExchange {
+    pub fn generate() -> Self {
+        let secret = StaticSecret::random_from_rng(OsRng);
+        let public = PublicKey::from(&secret);
+
+        Self { secret, public }
+    }
+
+    pub fn from_secret_bytes(secret_bytes: &[u8]) -> crate::utils::Result<Self> {
+        let secret_bytes: Zeroizing<[u8; 32]> = Zeroizing::new(secret_bytes.try_into()
+            .map_err(|_| crate::utils::Error::CryptoError(format!(
+                "Expected a 32-byte X25519 secret key, got {} bytes",
+                secret_bytes.len()
+            )))?);
+
+        let secret = StaticSecret::from(*secret_bytes);
+        let public = PublicKey::from(&secret);
+
+        Ok(Self { secret, public })
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        self.public.to_bytes()
+    }
+
+    pub fn secret_key(&self) -> Zeroizing<[u8; 32]> {
+        Zeroizing::new(self.secret.to_bytes())
+    }
+
+    /// Derive the 32-byte symmetric key shared with `their_public_key`.
+    ///
+    /// The raw X25519 output is expanded with HKDF-SHA256 under the game id and
+    /// both public keys, so the same DH keys never yield the same symmetric key
+    /// in two games. Low-order public keys, which would force a predictable
+    /// shared secret, are rejected.
+    pub fn compute_shared_secret(
+        &self,
+        their_public_key: &[u8],
+        game_id: &[u8],
+    ) -> crate::utils::Result<Zeroizing<[u8; 32]>> {
+        let their_public_key: [u8; 32] = their_public_key.try_into()
+            .map_err(|_| crate::utils::Error::CryptoError(format!(
+                "Expected a 32-byte X25519 public key, got {} bytes",
+                their_public_key.len()
+            )))?;
+
+        let shared_secret = self.secret.diffie_hellman(&PublicKey::from(their_public_key));
+        if !shared_secret.was_contributory() {
+            return Err(crate::utils::Error::CryptoError(
+                "Peer X25519 public key has low order".to_string()
+            ));
+        }
+
+        // Order the public keys so both sides build the same info string
+        let own_public_key = self.public_key();
+        let (first, second) = if own_public_key <= their_public_key {
+            (own_public_key, their_public_key)
+        } else {
+            (their_public_key, own_public_key)
+        };
+
+        let mut info = Vec::with_capacity(SHARED_KEY_DOMAIN.len() + 8 + game_id.len() + 64);
+        info.extend_from_slice(SHARED_KEY_DOMAIN);
+        info.extend_from_slice(&(game_id.len() as u64).to_le_bytes());
+        info.extend_from_slice(game_id);
+        info.extend_from_slice(&first);
+        info.extend_from_slice(&second);
+
+        let mut key = Zeroizing::new([0u8; 32]);
+        Hkdf::<Sha256>::new(None, shared_secret.as_bytes())
+            .expand(&info, key.as_mut())
+            .map_err(|e| crate::utils::Error::CryptoError(e.to_string()))?;
+
+        Ok(key)
+    }
+}
+
+impl fmt::Debug for DHKeyExchange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DHKeyExchange")
+            .field("public", &hex::encode(self.public.as_bytes()))
+            .finish_non_exhaustive()
+    }
+}
@@ -1,3 +1,92 @@
-pub fn encrypt(data: &[u8], key: &[u8]) -> Vec<u8> {
-    data.iter().zip(key.iter().cycle()).map(|(a, b)| a ^ b).collect()
+//! Envelope encryption for REVEAL payloads.
+//!
+//! An envelope is `version (1 byte) || nonce (12 bytes) || ciphertext || tag`,
+//! sealed with ChaCha20-Poly1305 under the key derived from the Santa/santee DH
+//! exchange. The version byte is authenticated together with the caller's
+//! associated data, see `associated_data`.
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::rngs::OsRng;
+
+/// Current envelope format
+pub const ENVELOPE_VERSION: u8 = 1;
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Domain separator for the associated data of REVEAL envelopes
+const ASSOCIATED_DATA_DOMAIN: &[u8] = b"zkret-santa/reveal/v1";
+
+/// Associated data binding an envelope to its game and to the DH keys of the
+/// santee (sender) and Santa (recipient). Every field is length-prefixed.
+pub fn associated_data(
+    game_id: &[u8],
+    santee_dh_public_key: &[u8],
+    santa_dh_public_key: &[u8],
+) -> Vec<u8> {
+    let mut data = ASSOCIATED_DATA_DOMAIN.to_vec();
+    for field in [game_id, santee_dh_public_key, santa_dh_public_key] {
+        data.extend_from_slice(&(field.len() as u64).to_le_bytes());
+        data.extend_from_slice(field);
+    }
+    data
+}
+
+/// Seal `plaintext` into a versioned envelope under a fresh random nonce
+pub fn encrypt_data(
+    plaintext: &[u8],
+    key: &[u8; 32],
+    associated_data: &[u8],
+) -> crate::utils::Result<Vec<u8>> {
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let aad = envelope_aad(ENVELOPE_VERSION, associated_data);
+
+    let ciphertext = cipher.encrypt(&nonce, Payload { msg: plaintext, aad: &aad })
+        .map_err(|e| crate::utils::Error::CryptoError(e.to_string()))?;
+
+    let mut envelope = Vec::with_capacity(1 + NONCE_LEN + ciphertext.len());
+    envelope.push(ENVELOPE_VERSION);
+    envelope.extend_from_slice(&nonce);
+    envelope.extend_from_slice(&ciphertext);
+
+    Ok(envelope)
+}
+
+/// Open an envelope produced by `encrypt_data`.
+///
+/// Returns `Error::AuthenticationFailed` when the tag does not verify, i.e. the
+/// envelope was modified, the associated data differs or the key is wrong.
+pub fn decrypt_data(
+    envelope: &[u8],
+    key: &[u8; 32],
+    associated_data: &[u8],
+) -> crate::utils::Result<Vec<u8>> {
+    if envelope.len() < 1 + NONCE_LEN + TAG_LEN {
+        return Err(crate::utils::Error::CryptoError(format!(
+            "Envelope too short: {} bytes", envelope.len()
+        )));
+    }
+
+    let version = envelope[0];
+    if version != ENVELOPE_VERSION {
+        return Err(crate::utils::Error::CryptoError(format!(
+            "Unsupported envelope version {}", version
+        )));
+    }
+
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce = Nonce::from_slice(&envelope[1..1 + NONCE_LEN]);
+    let aad = envelope_aad(version, associated_data);
+
+    cipher.decrypt(nonce, Payload { msg: &envelope[1 + NONCE_LEN..], aad: &aad })
+        .map_err(|_| crate::utils::Error::AuthenticationFailed)
+}
+
+fn envelope_aad(version: u8, associated_data: &[u8]) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(1 + associated_data.len());
+    aad.push(version);
+    aad.extend_from_slice(associated_data);
+    aad
 }
@@ -1,32 +1,39 @@
-use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer, Verifier};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 use rand::rngs::OsRng;
-use serde::{Deserialize, Serialize};
+use rand::RngCore;
 use std::fmt;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone)]
 pub struct KeyPair {
-    pub public_key: PublicKey,
-    secret_key: SecretKey,
+    pub public_key: VerifyingKey,
+    secret_key: SigningKey,
 }
 
 impl KeyPair {
     
     pub fn generate() -> Self {
-        let mut csprng = OsRng {};
-        let keypair = Keypair::generate(&mut csprng);
+        let mut secret_bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut secret_bytes);
+        let secret_key = SigningKey::from_bytes(&secret_bytes);
         
         Self {
-            public_key: keypair.public,
-            secret_key: keypair.secret,
+            public_key: secret_key.verifying_key(),
+            secret_key,
         }
     }
 
     
     pub fn from_bytes(public_bytes: &[u8], secret_bytes: &[u8]) -> crate::utils::Result<Self> {
-        let public_key = PublicKey::from_bytes(public_bytes)
-            .map_err(|e| crate::utils::Error::CryptoError(e.to_string()))?;
-        let secret_key = SecretKey::from_bytes(secret_bytes)
-            .map_err(|e| crate::utils::Error::CryptoError(e.to_string()))?;
+        let secret_bytes: &[u8; 32] = secret_bytes.try_into()
+            .map_err(|_| crate::utils::Error::CryptoError("Secret keys are 32 bytes".to_string()))?;
+        let secret_key = SigningKey::from_bytes(secret_bytes);
+        let public_key = secret_key.verifying_key();
+
+        if public_key.as_bytes()[..] != *public_bytes {
+            return Err(crate::utils::Error::CryptoError(
+                "Public key does not belong to the secret key".to_string()
+            ));
+        }
 
         Ok(Self {
             public_key,
@@ -34,19 +41,25 @@ impl KeyPair {
         })
     }
 
+    /// Raw ed25519 secret key, the seed the circuits derive identities from
+    pub fn secret_bytes(&self) -> &[u8; 32] {
+        self.secret_key.as_bytes()
+    }
+
     
     pub fn sign(&self, message: &[u8]) -> Signature {
-        let keypair = Keypair {
-            public: self.public_key,
-            secret: self.secret_key.clone(),
-        };
-        keypair.sign(message)
+        self.secret_key.sign(message)
+    }
+
+    /// Check a signature made with `sign`
+    pub fn verify(&self, message: &[u8], signature: &Signature) -> bool {
+        self.public_key.verify(message, signature).is_ok()
     }
 
     
     pub fn to_hex_strings(&self) -> (String, String) {
         let public_hex = hex::encode(self.public_key.as_bytes());
-        let secret_hex = hex::encode(self.secret_key.as_bytes());
+        let secret_hex = hex::encode(self.secret_bytes());
         (public_hex, secret_hex)
     }
 
@@ -67,3 +80,16 @@ impl fmt::Display for KeyPair {
     }
 }
 
+/// Check an ed25519 `signature` over `message` by the raw `public_key`
+pub fn verify_signature(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let public_key = match <&[u8; 32]>::try_from(public_key).map(VerifyingKey::from_bytes) {
+        Ok(Ok(public_key)) => public_key,
+        _ => return false,
+    };
+    let signature = match Signature::from_slice(signature) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+
+    public_key.verify(message, &signature).is_ok()
+}
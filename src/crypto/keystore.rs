@@ -0,0 +1,278 @@
+//! Passphrase-protected storage for a participant's keys.
+//!
+//! The keystore is a JSON document whose public key and KDF parameters are in
+//! the clear. The ed25519 secret key and every per-game X25519 secret are
+//! encrypted with a key derived from the passphrase using Argon2id, sealed in
+//! an `encryption` envelope whose associated data binds the public key.
+
+use super::encryption::{decrypt_data, encrypt_data};
+use super::{DHKeyExchange, KeyPair};
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+/// Current keystore format
+pub const KEYSTORE_VERSION: u32 = 1;
+
+/// Game name given to DH keys migrated from versions that played a single game
+pub const DEFAULT_GAME: &str = "default";
+
+const KEYSTORE_DOMAIN: &[u8] = b"zkret-santa/keystore/v1";
+const SALT_LEN: usize = 16;
+
+/// On-disk keystore document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeystoreFile {
+    pub version: u32,
+    /// Hex-encoded ed25519 public key, readable without the passphrase
+    pub public_key: String,
+    pub kdf: KdfParams,
+    /// Hex-encoded envelope holding the secret keys
+    pub ciphertext: String,
+}
+
+/// Argon2id parameters, stored so they can be raised without breaking old files
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub algorithm: String,
+    pub salt: String,
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl KdfParams {
+    /// Fresh parameters with a random salt
+    pub fn generate() -> Self {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+
+        Self {
+            algorithm: "argon2id".to_string(),
+            salt: hex::encode(salt),
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+
+    fn derive_key(&self, passphrase: &str) -> crate::utils::Result<Zeroizing<[u8; 32]>> {
+        if self.algorithm != "argon2id" {
+            return Err(crate::utils::Error::CryptoError(format!(
+                "Unsupported keystore KDF {}", self.algorithm
+            )));
+        }
+
+        let salt = hex::decode(&self.salt)
+            .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, Some(32))
+            .map_err(|e| crate::utils::Error::CryptoError(e.to_string()))?;
+
+        let mut key = Zeroizing::new([0u8; 32]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, key.as_mut())
+            .map_err(|e| crate::utils::Error::CryptoError(e.to_string()))?;
+
+        Ok(key)
+    }
+}
+
+/// Plaintext sealed inside the keystore envelope
+#[derive(Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+struct KeystoreSecrets {
+    secret_key: String,
+    dh_keys: Vec<DHKeyEntry>,
+}
+
+#[derive(Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+struct DHKeyEntry {
+    game: String,
+    secret_key: String,
+}
+
+/// Decrypted keystore: the participant's identity key and one DH key per game
+pub struct Keystore {
+    keypair: KeyPair,
+    dh_keys: BTreeMap<String, DHKeyExchange>,
+}
+
+impl Keystore {
+    pub fn new(keypair: KeyPair) -> Self {
+        Self {
+            keypair,
+            dh_keys: BTreeMap::new(),
+        }
+    }
+
+    pub fn keypair(&self) -> &KeyPair {
+        &self.keypair
+    }
+
+    pub fn dh_key(&self, game: &str) -> Option<&DHKeyExchange> {
+        self.dh_keys.get(game)
+    }
+
+    /// Store the DH key used in `game`, replacing any previous one
+    pub fn insert_dh_key(&mut self, game: &str, dh_keypair: DHKeyExchange) {
+        self.dh_keys.insert(game.to_string(), dh_keypair);
+    }
+
+    pub fn games(&self) -> impl Iterator<Item = &str> {
+        self.dh_keys.keys().map(String::as_str)
+    }
+
+    /// Encrypt under `passphrase` and atomically replace the file at `path`
+    pub fn save(&self, path: &Path, passphrase: &str) -> crate::utils::Result<()> {
+        let (public_hex, secret_hex) = self.keypair.to_hex_strings();
+        let secrets = KeystoreSecrets {
+            secret_key: secret_hex,
+            dh_keys: self.dh_keys.iter()
+                .map(|(game, dh_keypair)| DHKeyEntry {
+                    game: game.clone(),
+                    secret_key: hex::encode(dh_keypair.secret_key()),
+                })
+                .collect(),
+        };
+        let plaintext = Zeroizing::new(serde_json::to_vec(&secrets)
+            .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?);
+
+        let kdf = KdfParams::generate();
+        let key = kdf.derive_key(passphrase)?;
+        let envelope = encrypt_data(&plaintext, &key, &associated_data(&public_hex))?;
+
+        let file = KeystoreFile {
+            version: KEYSTORE_VERSION,
+            public_key: public_hex,
+            kdf,
+            ciphertext: hex::encode(envelope),
+        };
+        let file_json = serde_json::to_vec_pretty(&file)
+            .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, file_json)
+            .map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
+        std::fs::rename(&tmp_path, path)
+            .map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    /// Read and decrypt the keystore at `path`.
+    ///
+    /// A wrong passphrase surfaces as `Error::AuthenticationFailed`.
+    pub fn load(path: &Path, passphrase: &str) -> crate::utils::Result<Self> {
+        let file = read_keystore_file(path)?;
+        if file.version != KEYSTORE_VERSION {
+            return Err(crate::utils::Error::FileError(format!(
+                "Unsupported keystore version {}", file.version
+            )));
+        }
+
+        let key = file.kdf.derive_key(passphrase)?;
+        let envelope = hex::decode(&file.ciphertext)
+            .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+        let plaintext = Zeroizing::new(decrypt_data(&envelope, &key, &associated_data(&file.public_key))?);
+
+        let secrets: KeystoreSecrets = serde_json::from_slice(&plaintext)
+            .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+
+        let keypair = KeyPair::from_hex_strings(&file.public_key, &secrets.secret_key)?;
+        let mut dh_keys = BTreeMap::new();
+        for entry in &secrets.dh_keys {
+            let secret_bytes = Zeroizing::new(hex::decode(&entry.secret_key)
+                .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?);
+            dh_keys.insert(entry.game.clone(), DHKeyExchange::from_secret_bytes(&secret_bytes)?);
+        }
+
+        Ok(Self { keypair, dh_keys })
+    }
+
+    /// Public key of the keystore at `path`, without decrypting it
+    pub fn read_public_key(path: &Path) -> crate::utils::Result<String> {
+        Ok(read_keystore_file(path)?.public_key)
+    }
+
+    /// Read a plaintext `public_hex:secret_hex` keypair file and its sibling
+    /// `.dh` file, as written by earlier versions
+    pub fn from_legacy_files(path: &Path) -> crate::utils::Result<Self> {
+        let data = Zeroizing::new(std::fs::read_to_string(path)
+            .map_err(|e| crate::utils::Error::FileError(e.to_string()))?);
+        let (public_hex, secret_hex) = parse_legacy_keypair(&data)
+            .ok_or_else(|| crate::utils::Error::FileError("Invalid keypair file format".to_string()))?;
+
+        let mut keystore = Self::new(KeyPair::from_hex_strings(public_hex, secret_hex)?);
+
+        let dh_path = legacy_dh_path(path);
+        if dh_path.exists() {
+            let hex_data = Zeroizing::new(std::fs::read_to_string(&dh_path)
+                .map_err(|e| crate::utils::Error::FileError(e.to_string()))?);
+            let secret_bytes = Zeroizing::new(hex::decode(hex_data.trim())
+                .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?);
+            keystore.insert_dh_key(DEFAULT_GAME, DHKeyExchange::from_secret_bytes(&secret_bytes)?);
+        }
+
+        Ok(keystore)
+    }
+
+    /// Encrypt a legacy plaintext keypair file in place and delete its `.dh` file
+    pub fn migrate_legacy(path: &Path, passphrase: &str) -> crate::utils::Result<Self> {
+        let keystore = Self::from_legacy_files(path)?;
+        keystore.save(path, passphrase)?;
+
+        let dh_path = legacy_dh_path(path);
+        if dh_path.exists() {
+            std::fs::remove_file(&dh_path)
+                .map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
+        }
+
+        Ok(keystore)
+    }
+}
+
+impl fmt::Debug for Keystore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keystore")
+            .field("keypair", &self.keypair.to_string())
+            .field("games", &self.dh_keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+fn read_keystore_file(path: &Path) -> crate::utils::Result<KeystoreFile> {
+    let data = std::fs::read(path)
+        .map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
+
+    serde_json::from_slice(&data).map_err(|e| {
+        let is_legacy = std::str::from_utf8(&data).ok().and_then(parse_legacy_keypair).is_some();
+        if is_legacy {
+            crate::utils::Error::FileError(format!(
+                "{} is an unencrypted keypair file, run `zkretctl keystore migrate` first",
+                path.display()
+            ))
+        } else {
+            crate::utils::Error::SerializationError(e.to_string())
+        }
+    })
+}
+
+fn parse_legacy_keypair(data: &str) -> Option<(&str, &str)> {
+    let (public_hex, secret_hex) = data.trim().split_once(':')?;
+    let is_hex = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_hexdigit());
+    (is_hex(public_hex) && is_hex(secret_hex)).then_some((public_hex, secret_hex))
+}
+
+fn legacy_dh_path(path: &Path) -> PathBuf {
+    path.with_extension("dh")
+}
+
+fn associated_data(public_hex: &str) -> Vec<u8> {
+    let mut data = KEYSTORE_DOMAIN.to_vec();
+    data.extend_from_slice(public_hex.as_bytes());
+    data
+}
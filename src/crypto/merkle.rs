@@ -0,0 +1,98 @@
+use super::circuits::poseidon_hash;
+use ark_bn254::Fr;
+use ark_ff::{BigInteger, PrimeField, Zero};
+
+/// Depth of the ENTER-set Merkle tree, fixed because Groth16 circuits have a fixed shape
+pub const MERKLE_DEPTH: usize = 10;
+
+/// Maximum number of participants the ENTER set can hold
+pub const MAX_LEAVES: usize = 1 << MERKLE_DEPTH;
+
+/// Authentication path from a leaf to the root.
+///
+/// `is_right[i]` is true when the node at level `i` is the right child.
+#[derive(Debug, Clone)]
+pub struct MerklePath {
+    pub siblings: Vec<Fr>,
+    pub is_right: Vec<bool>,
+}
+
+/// Poseidon Merkle tree over the identity commitments of every ENTER record
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    leaves: Vec<Fr>,
+    // levels[0] holds the padded leaves, the last level holds the root
+    levels: Vec<Vec<Fr>>,
+}
+
+impl MerkleTree {
+    /// Build the tree from identity commitments.
+    ///
+    /// Leaves are sorted and deduplicated so every client derives the same root
+    /// regardless of the order in which ENTER records were fetched. Unused
+    /// leaves are filled with zero.
+    pub fn new(commitments: &[Fr]) -> crate::utils::Result<Self> {
+        let mut leaves = commitments.to_vec();
+        leaves.sort_by_key(|leaf| leaf.into_bigint().to_bytes_be());
+        leaves.dedup();
+
+        if leaves.len() > MAX_LEAVES {
+            return Err(crate::utils::Error::CryptoError(format!(
+                "ENTER set holds at most {} participants, got {}",
+                MAX_LEAVES,
+                leaves.len()
+            )));
+        }
+
+        let mut level = leaves.clone();
+        level.resize(MAX_LEAVES, Fr::zero());
+
+        let mut levels = vec![level];
+        for _ in 0..MERKLE_DEPTH {
+            let next = levels.last().unwrap()
+                .chunks(2)
+                .map(|pair| poseidon_hash(&[pair[0], pair[1]]))
+                .collect();
+            levels.push(next);
+        }
+
+        Ok(Self { leaves, levels })
+    }
+
+    pub fn root(&self) -> Fr {
+        self.levels[MERKLE_DEPTH][0]
+    }
+
+    /// Position of a commitment among the sorted leaves
+    pub fn index_of(&self, commitment: &Fr) -> Option<usize> {
+        self.leaves.iter().position(|leaf| leaf == commitment)
+    }
+
+    pub fn path(&self, index: usize) -> crate::utils::Result<MerklePath> {
+        if index >= self.leaves.len() {
+            return Err(crate::utils::Error::CryptoError(
+                "Merkle leaf index out of range".to_string()
+            ));
+        }
+
+        let mut siblings = Vec::with_capacity(MERKLE_DEPTH);
+        let mut is_right = Vec::with_capacity(MERKLE_DEPTH);
+        let mut position = index;
+
+        for level in &self.levels[..MERKLE_DEPTH] {
+            siblings.push(level[position ^ 1]);
+            is_right.push(position & 1 == 1);
+            position >>= 1;
+        }
+
+        Ok(MerklePath { siblings, is_right })
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+}
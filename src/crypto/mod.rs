@@ -1,4 +1,19 @@
+pub mod ceremony;
+pub mod circuits;
+pub mod diffie_hellman;
+pub mod encryption;
 pub mod keypair;
+pub mod keystore;
+pub mod merkle;
+pub mod params;
+pub mod powers_of_tau;
+pub mod public_inputs;
 pub mod zk_proofs;
+pub use diffie_hellman::DHKeyExchange;
+pub use encryption::{decrypt_data, encrypt_data};
 pub use keypair::KeyPair;
-pub use zk_proofs::ZKSystem;
+pub use keystore::Keystore;
+pub use merkle::MerkleTree;
+pub use params::ParamsManifest;
+pub use public_inputs::PublicInputs;
+pub use zk_proofs::{ProofType, ZKProof, ZKProofSystem};
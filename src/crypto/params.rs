@@ -0,0 +1,210 @@
+use super::zk_proofs::ProofType;
+use ark_bn254::Bn254;
+use ark_groth16::{ProvingKey, VerifyingKey};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Name of the manifest written next to the key files
+pub const MANIFEST_FILE: &str = "params.json";
+
+/// Current parameter format, bumped whenever a circuit changes shape
+pub const PARAMS_VERSION: u32 = 4;
+
+/// Describes a parameter directory: one proving and verifying key per circuit,
+/// each with the SHA3-256 hash of its file contents.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParamsManifest {
+    pub version: u32,
+    /// Hash over every verifying key hash, identifying the parameter set as a whole
+    pub params_hash: String,
+    pub circuits: Vec<CircuitParams>,
+}
+
+/// Manifest together with the proving and verifying keys it describes
+pub type LoadedParams = (
+    ParamsManifest,
+    HashMap<ProofType, ProvingKey<Bn254>>,
+    HashMap<ProofType, VerifyingKey<Bn254>>,
+);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CircuitParams {
+    pub proof_type: ProofType,
+    pub proving_key_file: String,
+    pub proving_key_hash: String,
+    pub verifying_key_file: String,
+    pub verifying_key_hash: String,
+    pub num_public_inputs: usize,
+}
+
+impl ParamsManifest {
+    pub fn circuit(&self, proof_type: ProofType) -> Option<&CircuitParams> {
+        self.circuits.iter().find(|c| c.proof_type == proof_type)
+    }
+}
+
+pub fn content_hash(data: &[u8]) -> String {
+    hex::encode(Sha3_256::digest(data))
+}
+
+/// Serialize keys into `dir` and write the manifest describing them
+pub fn write_params(
+    dir: &Path,
+    proving_keys: &HashMap<ProofType, ProvingKey<Bn254>>,
+    verifying_keys: &HashMap<ProofType, VerifyingKey<Bn254>>,
+) -> crate::utils::Result<ParamsManifest> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
+
+    let mut circuits = Vec::new();
+    for proof_type in ProofType::ALL {
+        let proving_key = proving_keys.get(&proof_type)
+            .ok_or_else(|| crate::utils::Error::CryptoError(format!("Missing proving key for {:?}", proof_type)))?;
+        let verifying_key = verifying_keys.get(&proof_type)
+            .ok_or_else(|| crate::utils::Error::CryptoError(format!("Missing verifying key for {:?}", proof_type)))?;
+
+        let proving_key_file = format!("{}.pk", proof_type.name());
+        let verifying_key_file = format!("{}.vk", proof_type.name());
+        let proving_key_hash = write_key(&dir.join(&proving_key_file), proving_key)?;
+        let verifying_key_hash = write_key(&dir.join(&verifying_key_file), verifying_key)?;
+
+        circuits.push(CircuitParams {
+            proof_type,
+            proving_key_file,
+            proving_key_hash,
+            verifying_key_file,
+            verifying_key_hash,
+            num_public_inputs: verifying_key.gamma_abc_g1.len() - 1,
+        });
+    }
+
+    let manifest = ParamsManifest {
+        version: PARAMS_VERSION,
+        params_hash: params_hash(&circuits),
+        circuits,
+    };
+
+    let manifest_json = serde_json::to_vec_pretty(&manifest)
+        .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+    std::fs::write(dir.join(MANIFEST_FILE), manifest_json)
+        .map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
+
+    Ok(manifest)
+}
+
+/// Read the manifest in `dir` and check every key file against its recorded hash
+pub fn inspect_params(dir: &Path) -> crate::utils::Result<ParamsManifest> {
+    let manifest = read_manifest(dir)?;
+
+    for circuit in &manifest.circuits {
+        read_verified(&dir.join(&circuit.proving_key_file), &circuit.proving_key_hash)?;
+        read_verified(&dir.join(&circuit.verifying_key_file), &circuit.verifying_key_hash)?;
+    }
+
+    Ok(manifest)
+}
+
+/// Load and deserialize every key listed in the manifest in `dir`. Each file
+/// is read once, so the bytes that are deserialized are the ones hashed.
+pub fn load_params(dir: &Path) -> crate::utils::Result<LoadedParams> {
+    let manifest = read_manifest(dir)?;
+    let mut proving_keys = HashMap::new();
+    let mut verifying_keys = HashMap::new();
+
+    for proof_type in ProofType::ALL {
+        let circuit = manifest.circuit(proof_type)
+            .ok_or_else(|| crate::utils::Error::CryptoError(format!("Parameters for {:?} not found", proof_type)))?;
+
+        let proving_key: ProvingKey<Bn254> =
+            read_key(&dir.join(&circuit.proving_key_file), &circuit.proving_key_hash)?;
+        let verifying_key: VerifyingKey<Bn254> =
+            read_key(&dir.join(&circuit.verifying_key_file), &circuit.verifying_key_hash)?;
+
+        proving_keys.insert(proof_type, proving_key);
+        verifying_keys.insert(proof_type, verifying_key);
+    }
+
+    Ok((manifest, proving_keys, verifying_keys))
+}
+
+/// Parse the manifest in `dir` and check its version and parameter set hash
+fn read_manifest(dir: &Path) -> crate::utils::Result<ParamsManifest> {
+    let manifest_json = std::fs::read(dir.join(MANIFEST_FILE))
+        .map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
+    let manifest: ParamsManifest = serde_json::from_slice(&manifest_json)
+        .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+
+    if manifest.version != PARAMS_VERSION {
+        return Err(crate::utils::Error::CryptoError(format!(
+            "Unsupported parameter version {}", manifest.version
+        )));
+    }
+
+    if manifest.params_hash != params_hash(&manifest.circuits) {
+        return Err(crate::utils::Error::CryptoError(
+            "Parameter set hash does not match its verifying keys".to_string()
+        ));
+    }
+
+    Ok(manifest)
+}
+
+/// The `params_hash` a manifest of `verifying_keys` would carry
+pub fn verifying_keys_hash(verifying_keys: &HashMap<ProofType, VerifyingKey<Bn254>>) -> crate::utils::Result<String> {
+    let mut hasher = Sha3_256::new();
+    for proof_type in ProofType::ALL {
+        let verifying_key = verifying_keys.get(&proof_type)
+            .ok_or_else(|| crate::utils::Error::CryptoError(format!("Missing verifying key for {:?}", proof_type)))?;
+        let mut data = Vec::new();
+        verifying_key.serialize_compressed(&mut data)
+            .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+
+        hasher.update(proof_type.name().as_bytes());
+        hasher.update(content_hash(&data).as_bytes());
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn params_hash(circuits: &[CircuitParams]) -> String {
+    let mut hasher = Sha3_256::new();
+    for circuit in circuits {
+        hasher.update(circuit.proof_type.name().as_bytes());
+        hasher.update(circuit.verifying_key_hash.as_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
+
+fn write_key<K: CanonicalSerialize>(path: &Path, key: &K) -> crate::utils::Result<String> {
+    let mut data = Vec::new();
+    key.serialize_compressed(&mut data)
+        .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+
+    std::fs::write(path, &data)
+        .map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
+
+    Ok(content_hash(&data))
+}
+
+fn read_key<K: CanonicalDeserialize>(path: &Path, expected_hash: &str) -> crate::utils::Result<K> {
+    let data = read_verified(path, expected_hash)?;
+
+    K::deserialize_compressed(data.as_slice())
+        .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))
+}
+
+/// Contents of `path`, provided they hash to `expected`
+fn read_verified(path: &Path, expected: &str) -> crate::utils::Result<Vec<u8>> {
+    let data = std::fs::read(path)
+        .map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
+
+    if content_hash(&data) != expected {
+        return Err(crate::utils::Error::CryptoError(format!(
+            "Hash mismatch for {}", path.display()
+        )));
+    }
+
+    Ok(data)
+}
@@ -0,0 +1,368 @@
+//! Phase 1 of the trusted setup: powers of a secret `τ`, together with `α`
+//! and `β`, that no single party knows.
+//!
+//! Contributors take turns multiplying the three secrets by random factors of
+//! their own and throwing the factors away, so the final secrets are unknown
+//! as long as one contributor was honest. Each contribution proves knowledge
+//! of its factors, that it was applied to the previous powers, and that the
+//! result still consists of powers of a single `τ`.
+//!
+//! `derive_proving_key` turns the powers into Groth16 keys for any circuit
+//! whose evaluation domain fits, with `γ = δ = 1`. Phase 2 then moves `δ`.
+
+use super::ceremony::{from_bytes, hash_to_g2, to_bytes};
+use ark_bn254::{Bn254, Fr, G1Affine, G1Projective, G2Affine, G2Projective};
+use ark_ec::pairing::Pairing;
+use ark_ec::{AffineRepr, CurveGroup, VariableBaseMSM};
+use ark_ff::{One, UniformRand, Zero};
+use ark_groth16::{ProvingKey, VerifyingKey};
+use ark_poly::domain::DomainCoeff;
+use ark_poly::{EvaluationDomain, GeneralEvaluationDomain};
+use ark_relations::r1cs::{
+    ConstraintSynthesizer, ConstraintSystem, ConstraintSystemRef, Matrix, OptimizationGoal, SynthesisMode,
+};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use rand::rngs::OsRng;
+use rand::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+
+/// Powers of `τ` and their `α` and `β` multiples, enough for evaluation
+/// domains of up to `size()` elements
+#[derive(Debug, Clone, PartialEq, Eq, CanonicalSerialize, CanonicalDeserialize)]
+pub struct PowersOfTau {
+    /// `τ^i·G1` for `i < 2·size - 1`
+    pub tau_g1: Vec<G1Affine>,
+    /// `τ^i·G2` for `i < size`
+    pub tau_g2: Vec<G2Affine>,
+    /// `α·τ^i·G1` for `i < size`
+    pub alpha_tau_g1: Vec<G1Affine>,
+    /// `β·τ^i·G1` for `i < size`
+    pub beta_tau_g1: Vec<G1Affine>,
+    pub beta_g2: G2Affine,
+}
+
+/// Proof of knowledge of a secret `x`: a random `s` in G1, `s·x`, and `r·x`
+/// where `r` is hashed into G2 from the context, `s` and `s·x`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnowledgeProof {
+    pub s_g1: Vec<u8>,
+    pub s_x_g1: Vec<u8>,
+    pub r_x_g2: Vec<u8>,
+}
+
+/// One contributor's update to the powers of tau
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PowersContribution {
+    pub tau: KnowledgeProof,
+    pub alpha: KnowledgeProof,
+    pub beta: KnowledgeProof,
+}
+
+impl PowersOfTau {
+    /// The powers for `τ = α = β = 1` that phase 1 starts from. `size` must be
+    /// a power of two, at least 2.
+    pub fn new(size: usize) -> crate::utils::Result<Self> {
+        if size < 2 || !size.is_power_of_two() {
+            return Err(crate::utils::Error::CryptoError(
+                "Powers of tau need a power-of-two size of at least 2".to_string()
+            ));
+        }
+
+        let (g1, g2) = (G1Affine::generator(), G2Affine::generator());
+        Ok(Self {
+            tau_g1: vec![g1; 2 * size - 1],
+            tau_g2: vec![g2; size],
+            alpha_tau_g1: vec![g1; size],
+            beta_tau_g1: vec![g1; size],
+            beta_g2: g2,
+        })
+    }
+
+    /// Largest evaluation domain the powers are enough for
+    pub fn size(&self) -> usize {
+        self.tau_g2.len()
+    }
+
+    /// Multiply `τ`, `α` and `β` by fresh random factors, which are dropped on
+    /// return. The proofs are bound to `context`.
+    pub fn contribute<R: RngCore + CryptoRng>(
+        &self,
+        context: &[u8],
+        rng: &mut R,
+    ) -> crate::utils::Result<(Self, PowersContribution)> {
+        let [tau, alpha, beta] = [(); 3].map(|_| nonzero_scalar(rng));
+
+        let mut powers = Vec::with_capacity(self.tau_g1.len());
+        let mut power = Fr::one();
+        for _ in 0..self.tau_g1.len() {
+            powers.push(power);
+            power *= tau;
+        }
+        let alpha_powers: Vec<Fr> = powers.iter().map(|power| alpha * power).collect();
+        let beta_powers: Vec<Fr> = powers.iter().map(|power| beta * power).collect();
+
+        let updated = Self {
+            tau_g1: scale_each(&self.tau_g1, &powers),
+            tau_g2: scale_each(&self.tau_g2, &powers),
+            alpha_tau_g1: scale_each(&self.alpha_tau_g1, &alpha_powers),
+            beta_tau_g1: scale_each(&self.beta_tau_g1, &beta_powers),
+            beta_g2: (self.beta_g2 * beta).into_affine(),
+        };
+        let contribution = PowersContribution {
+            tau: KnowledgeProof::new(&secret_context(context, b"tau"), tau, rng)?,
+            alpha: KnowledgeProof::new(&secret_context(context, b"alpha"), alpha, rng)?,
+            beta: KnowledgeProof::new(&secret_context(context, b"beta"), beta, rng)?,
+        };
+
+        Ok((updated, contribution))
+    }
+
+    /// Whether the powers have the expected lengths, start at the generators
+    /// and consist of powers of a single `τ` with consistent `α` and `β`
+    pub fn is_well_formed(&self) -> crate::utils::Result<bool> {
+        let size = self.size();
+        if size < 2
+            || !size.is_power_of_two()
+            || self.tau_g1.len() != 2 * size - 1
+            || self.alpha_tau_g1.len() != size
+            || self.beta_tau_g1.len() != size
+        {
+            return Ok(false);
+        }
+
+        let (g1, g2) = (G1Affine::generator(), G2Affine::generator());
+        if self.tau_g1[0] != g1 || self.tau_g2[0] != g2 {
+            return Ok(false);
+        }
+        if self.tau_g1[1].is_zero() || self.alpha_tau_g1[0].is_zero() || self.beta_tau_g1[0].is_zero() {
+            return Ok(false);
+        }
+
+        // Neighbouring elements differ by τ: e(Σρᵢ·Pᵢ, τ·G2) == e(Σρᵢ·Pᵢ₊₁, G2)
+        let tau_g2 = self.tau_g2[1];
+        for points in [&self.tau_g1, &self.alpha_tau_g1, &self.beta_tau_g1] {
+            let (lower, upper) = random_neighbour_sums::<G1Affine>(points)?;
+            if !pairings_equal(lower, tau_g2, upper, g2) {
+                return Ok(false);
+            }
+        }
+        let (lower, upper) = random_neighbour_sums::<G2Affine>(&self.tau_g2)?;
+        if !pairings_equal(self.tau_g1[1], lower, g1, upper) {
+            return Ok(false);
+        }
+
+        Ok(pairings_equal(self.beta_tau_g1[0], g2, g1, self.beta_g2))
+    }
+
+    /// Groth16 keys for `circuit` with `γ = δ = 1`, laid out as ark-groth16's
+    /// generator and prover expect them
+    pub fn derive_proving_key<C: ConstraintSynthesizer<Fr>>(
+        &self,
+        circuit: C,
+    ) -> crate::utils::Result<ProvingKey<Bn254>> {
+        let cs = synthesize(circuit)?;
+        let matrices = cs.to_matrices()
+            .ok_or_else(|| crate::utils::Error::CryptoError("Circuit has no constraint matrices".to_string()))?;
+
+        let num_inputs = cs.num_instance_variables();
+        let num_constraints = cs.num_constraints();
+        let num_variables = num_inputs + cs.num_witness_variables();
+        let domain = circuit_domain(&cs)?;
+        let m = domain.size();
+        if m > self.size() {
+            return Err(crate::utils::Error::CryptoError(format!(
+                "The circuit needs powers of tau of size {}, these have {}", m, self.size()
+            )));
+        }
+
+        // Lagrange basis of the domain at τ, the inverse FFT of the powers
+        let lagrange_g1 = lagrange_basis(&domain, &self.tau_g1[..m]);
+        let lagrange_g2 = lagrange_basis(&domain, &self.tau_g2[..m]);
+        let alpha_lagrange = lagrange_basis(&domain, &self.alpha_tau_g1[..m]);
+        let beta_lagrange = lagrange_basis(&domain, &self.beta_tau_g1[..m]);
+
+        // Public inputs are also bound by rows past the constraints, as in
+        // libsnark's reduction that ark-groth16 uses
+        let mut a_columns = columns(&matrices.a, num_variables);
+        for (input, (rows, coeffs)) in a_columns.iter_mut().enumerate().take(num_inputs) {
+            rows.push(num_constraints + input);
+            coeffs.push(Fr::one());
+        }
+        let b_columns = columns(&matrices.b, num_variables);
+        let c_columns = columns(&matrices.c, num_variables);
+
+        let a_query: Vec<G1Projective> = a_columns.iter().map(|a| evaluate(&lagrange_g1, a)).collect();
+        let b_g1_query: Vec<G1Projective> = b_columns.iter().map(|b| evaluate(&lagrange_g1, b)).collect();
+        let b_g2_query: Vec<G2Projective> = b_columns.iter().map(|b| evaluate(&lagrange_g2, b)).collect();
+        // β·aⱼ(τ) + α·bⱼ(τ) + cⱼ(τ), split into the γ and L queries below
+        let combined: Vec<G1Projective> = a_columns.iter().zip(&b_columns).zip(&c_columns)
+            .map(|((a, b), c)| evaluate(&beta_lagrange, a) + evaluate(&alpha_lagrange, b) + evaluate(&lagrange_g1, c))
+            .collect();
+
+        // τ^i·t(τ) for the vanishing polynomial t(x) = x^m - 1, as many as
+        // the generator would produce for the unpadded domain size
+        let h_query: Vec<G1Projective> = (0..num_constraints + num_inputs - 1)
+            .map(|i| self.tau_g1[m + i].into_group() - self.tau_g1[i])
+            .collect();
+
+        let mut combined = G1Projective::normalize_batch(&combined);
+        let l_query = combined.split_off(num_inputs);
+
+        Ok(ProvingKey {
+            vk: VerifyingKey {
+                alpha_g1: self.alpha_tau_g1[0],
+                beta_g2: self.beta_g2,
+                gamma_g2: G2Affine::generator(),
+                delta_g2: G2Affine::generator(),
+                gamma_abc_g1: combined,
+            },
+            beta_g1: self.beta_tau_g1[0],
+            delta_g1: G1Affine::generator(),
+            a_query: G1Projective::normalize_batch(&a_query),
+            b_g1_query: G1Projective::normalize_batch(&b_g1_query),
+            b_g2_query: G2Projective::normalize_batch(&b_g2_query),
+            h_query: G1Projective::normalize_batch(&h_query),
+            l_query,
+        })
+    }
+}
+
+impl KnowledgeProof {
+    fn new<R: RngCore + CryptoRng>(context: &[u8], x: Fr, rng: &mut R) -> crate::utils::Result<Self> {
+        let s_g1 = (G1Affine::generator() * nonzero_scalar(rng)).into_affine();
+        let s_x_g1 = (s_g1 * x).into_affine();
+        let r_x_g2 = (hash_to_g2(context, &s_g1, &s_x_g1)? * x).into_affine();
+
+        Ok(Self {
+            s_g1: to_bytes(&s_g1)?,
+            s_x_g1: to_bytes(&s_x_g1)?,
+            r_x_g2: to_bytes(&r_x_g2)?,
+        })
+    }
+
+    /// `r` and `r·x` if the proof holds for `context`
+    fn check(&self, context: &[u8]) -> crate::utils::Result<Option<(G2Affine, G2Affine)>> {
+        let s_g1: G1Affine = from_bytes(&self.s_g1)?;
+        let s_x_g1: G1Affine = from_bytes(&self.s_x_g1)?;
+        let r_x_g2: G2Affine = from_bytes(&self.r_x_g2)?;
+        if s_g1.is_zero() || s_x_g1.is_zero() {
+            return Ok(None);
+        }
+
+        // e(s, r·x) == e(s·x, r)
+        let r_g2 = hash_to_g2(context, &s_g1, &s_x_g1)?;
+        Ok(pairings_equal(s_g1, r_x_g2, s_x_g1, r_g2).then_some((r_g2, r_x_g2)))
+    }
+}
+
+/// Check that `after` is well formed and is `before` updated by the secrets
+/// proven in `contribution`
+pub fn verify_contribution(
+    before: &PowersOfTau,
+    after: &PowersOfTau,
+    contribution: &PowersContribution,
+    context: &[u8],
+) -> crate::utils::Result<bool> {
+    if after.size() != before.size() || !after.is_well_formed()? {
+        return Ok(false);
+    }
+
+    // Each element moved by the proven factor: e(after, r) == e(before, r·x)
+    let updates = [
+        (&contribution.tau, &b"tau"[..], before.tau_g1[1], after.tau_g1[1]),
+        (&contribution.alpha, &b"alpha"[..], before.alpha_tau_g1[0], after.alpha_tau_g1[0]),
+        (&contribution.beta, &b"beta"[..], before.beta_tau_g1[0], after.beta_tau_g1[0]),
+    ];
+    for (proof, label, old, new) in updates {
+        match proof.check(&secret_context(context, label))? {
+            Some((r_g2, r_x_g2)) if pairings_equal(new, r_g2, old, r_x_g2) => {}
+            _ => return Ok(false),
+        }
+    }
+
+    Ok(true)
+}
+
+/// Size of the evaluation domain `circuit` needs from the powers of tau
+pub fn required_size<C: ConstraintSynthesizer<Fr>>(circuit: C) -> crate::utils::Result<usize> {
+    Ok(circuit_domain(&synthesize(circuit)?)?.size())
+}
+
+// Synthesized the way ark-groth16's generator does it, so the matrices match
+// the ones its prover builds
+fn synthesize<C: ConstraintSynthesizer<Fr>>(circuit: C) -> crate::utils::Result<ConstraintSystemRef<Fr>> {
+    let cs = ConstraintSystem::new_ref();
+    cs.set_optimization_goal(OptimizationGoal::Constraints);
+    cs.set_mode(SynthesisMode::Setup);
+    circuit.generate_constraints(cs.clone())
+        .map_err(|e| crate::utils::Error::CryptoError(e.to_string()))?;
+    cs.finalize();
+    Ok(cs)
+}
+
+fn circuit_domain(cs: &ConstraintSystemRef<Fr>) -> crate::utils::Result<GeneralEvaluationDomain<Fr>> {
+    GeneralEvaluationDomain::new(cs.num_constraints() + cs.num_instance_variables())
+        .ok_or_else(|| crate::utils::Error::CryptoError("Circuit is too large for an evaluation domain".to_string()))
+}
+
+fn lagrange_basis<G>(domain: &GeneralEvaluationDomain<Fr>, powers: &[G]) -> Vec<G>
+where
+    G: AffineRepr<ScalarField = Fr>,
+    G::Group: DomainCoeff<Fr>,
+{
+    let mut points: Vec<G::Group> = powers.iter().map(|point| point.into_group()).collect();
+    domain.ifft_in_place(&mut points);
+    G::Group::normalize_batch(&points)
+}
+
+/// Rows and coefficients of every variable in `matrix`
+fn columns(matrix: &Matrix<Fr>, num_variables: usize) -> Vec<(Vec<usize>, Vec<Fr>)> {
+    let mut columns = vec![(Vec::new(), Vec::new()); num_variables];
+    for (row, terms) in matrix.iter().enumerate() {
+        for (coeff, variable) in terms {
+            columns[*variable].0.push(row);
+            columns[*variable].1.push(*coeff);
+        }
+    }
+    columns
+}
+
+/// `Σ coeff·basis[row]` over one column
+fn evaluate<G: AffineRepr<ScalarField = Fr>>(basis: &[G], (rows, coeffs): &(Vec<usize>, Vec<Fr>)) -> G::Group {
+    let points: Vec<G> = rows.iter().map(|row| basis[*row]).collect();
+    G::Group::msm_unchecked(&points, coeffs)
+}
+
+fn scale_each<G: AffineRepr<ScalarField = Fr>>(points: &[G], scalars: &[Fr]) -> Vec<G> {
+    let scaled: Vec<G::Group> = points.iter().zip(scalars).map(|(point, scalar)| *point * scalar).collect();
+    G::Group::normalize_batch(&scaled)
+}
+
+/// `Σρᵢ·Pᵢ` and `Σρᵢ·Pᵢ₊₁` for random `ρᵢ`
+fn random_neighbour_sums<G: AffineRepr<ScalarField = Fr>>(points: &[G]) -> crate::utils::Result<(G, G)> {
+    let mut rng = OsRng;
+    let challenges: Vec<Fr> = (1..points.len()).map(|_| Fr::rand(&mut rng)).collect();
+    let sum = |points: &[G]| G::Group::msm(points, &challenges)
+        .map(|sum| sum.into_affine())
+        .map_err(|_| crate::utils::Error::CryptoError("MSM length mismatch".to_string()));
+
+    Ok((sum(&points[..points.len() - 1])?, sum(&points[1..])?))
+}
+
+/// e(a1, b1) == e(a2, b2)
+fn pairings_equal(a1: G1Affine, b1: G2Affine, a2: G1Affine, b2: G2Affine) -> bool {
+    Bn254::multi_pairing([a1, -a2], [b1, b2]).is_zero()
+}
+
+fn secret_context(context: &[u8], label: &[u8]) -> Vec<u8> {
+    [context, b"/", label].concat()
+}
+
+fn nonzero_scalar<R: RngCore + CryptoRng>(rng: &mut R) -> Fr {
+    loop {
+        let scalar = Fr::rand(rng);
+        if !scalar.is_zero() {
+            return scalar;
+        }
+    }
+}
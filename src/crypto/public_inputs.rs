@@ -0,0 +1,293 @@
+//! Encoding of Groth16 public inputs.
+//!
+//! Version 3, which external verifiers need to reproduce exactly:
+//!
+//! * Every public input is a BN254 `Fr` element, carried as 32 bytes of its
+//!   canonical little-endian representation (value < r).
+//! * 32-byte keys (ed25519 and X25519 public keys) are packed into two limbs:
+//!   `lo = bytes[0..16]` and `hi = bytes[16..32]`, each read as a little-endian
+//!   128-bit integer. Both limbs are always below r.
+//! * Arbitrary byte strings (the REVEAL payload) are hashed with SHA3-256 over
+//!   `domain || bytes` and the digest, read little-endian, is reduced mod r.
+//! * Every proof ends with the game input: the 32-byte game id hashed into
+//!   the field the same way, so a proof only verifies for the game it was
+//!   made for.
+//!
+//! The order of inputs per proof type is given by the `to_field_elements`
+//! implementations below and matches the allocation order in `circuits`.
+
+use super::zk_proofs::ProofType;
+use ark_bn254::Fr;
+use ark_ff::{BigInteger, PrimeField};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+
+/// Current public-input encoding version
+pub const PUBLIC_INPUTS_VERSION: u8 = 3;
+
+/// Domain separator used when hashing a REVEAL payload into the field
+const PAYLOAD_DOMAIN: &[u8] = b"zkret-santa/payload/v1";
+
+/// Domain separator used when hashing a game id into the field
+const GAME_DOMAIN: &[u8] = b"zkret-santa/game/v1";
+
+/// Canonical little-endian encoding of a field element
+pub fn field_to_bytes(value: &Fr) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(&value.into_bigint().to_bytes_le());
+    bytes
+}
+
+/// Decode a canonical little-endian field element, rejecting values >= r
+pub fn field_from_bytes(bytes: &[u8]) -> crate::utils::Result<Fr> {
+    if bytes.len() != 32 {
+        return Err(crate::utils::Error::SerializationError(format!(
+            "Expected a 32-byte field element, got {} bytes",
+            bytes.len()
+        )));
+    }
+
+    let value = Fr::from_le_bytes_mod_order(bytes);
+    if field_to_bytes(&value)[..] != *bytes {
+        return Err(crate::utils::Error::SerializationError(
+            "Field element is not canonically encoded".to_string()
+        ));
+    }
+
+    Ok(value)
+}
+
+/// Split a 32-byte key into its two 128-bit limbs
+pub fn pack_key(key: &[u8]) -> crate::utils::Result<[Fr; 2]> {
+    if key.len() != 32 {
+        return Err(crate::utils::Error::CryptoError(format!(
+            "Expected a 32-byte key, got {} bytes",
+            key.len()
+        )));
+    }
+
+    Ok([
+        Fr::from_le_bytes_mod_order(&key[..16]),
+        Fr::from_le_bytes_mod_order(&key[16..]),
+    ])
+}
+
+/// Rebuild a 32-byte key from its limbs, rejecting limbs wider than 128 bits
+pub fn unpack_key(limbs: [Fr; 2]) -> crate::utils::Result<[u8; 32]> {
+    let mut key = [0u8; 32];
+
+    for (i, limb) in limbs.iter().enumerate() {
+        let bytes = field_to_bytes(limb);
+        if bytes[16..].iter().any(|b| *b != 0) {
+            return Err(crate::utils::Error::SerializationError(
+                "Key limb exceeds 128 bits".to_string()
+            ));
+        }
+        key[i * 16..(i + 1) * 16].copy_from_slice(&bytes[..16]);
+    }
+
+    Ok(key)
+}
+
+/// Hash an arbitrary byte string into the field under a domain separator
+pub fn hash_to_field(domain: &[u8], data: &[u8]) -> Fr {
+    let mut hasher = Sha3_256::new();
+    hasher.update(domain);
+    hasher.update(data);
+
+    Fr::from_le_bytes_mod_order(&hasher.finalize())
+}
+
+/// Digest of an encrypted REVEAL payload
+pub fn payload_digest(payload: &[u8]) -> Fr {
+    hash_to_field(PAYLOAD_DOMAIN, payload)
+}
+
+/// Game input of every proof made for `game_id`
+pub fn game_field(game_id: &[u8]) -> Fr {
+    hash_to_field(GAME_DOMAIN, game_id)
+}
+
+/// Versioned, encoded public inputs as carried inside a `ZKProof`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublicInputs {
+    pub version: u8,
+    pub elements: Vec<[u8; 32]>,
+}
+
+impl PublicInputs {
+    pub fn from_field_elements(elements: &[Fr]) -> Self {
+        Self {
+            version: PUBLIC_INPUTS_VERSION,
+            elements: elements.iter().map(field_to_bytes).collect(),
+        }
+    }
+
+    /// Decode into field elements, checking the version and expected arity
+    pub fn to_field_elements(&self, proof_type: ProofType) -> crate::utils::Result<Vec<Fr>> {
+        if self.version != PUBLIC_INPUTS_VERSION {
+            return Err(crate::utils::Error::SerializationError(format!(
+                "Unsupported public input version {}", self.version
+            )));
+        }
+
+        if self.elements.len() != proof_type.num_public_inputs() {
+            return Err(arity_error(proof_type, self.elements.len()));
+        }
+
+        self.elements.iter().map(|bytes| field_from_bytes(bytes)).collect()
+    }
+}
+
+fn check_arity(proof_type: ProofType, elements: &[Fr]) -> crate::utils::Result<()> {
+    if elements.len() != proof_type.num_public_inputs() {
+        return Err(arity_error(proof_type, elements.len()));
+    }
+    Ok(())
+}
+
+fn arity_error(proof_type: ProofType, actual: usize) -> crate::utils::Error {
+    crate::utils::Error::SerializationError(format!(
+        "{:?} proofs take {} public inputs, got {}",
+        proof_type, proof_type.num_public_inputs(), actual
+    ))
+}
+
+/// Public inputs of an ENTER proof
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnterInputs {
+    pub public_key: [u8; 32],
+    pub commitment: Fr,
+    pub nullifier: Fr,
+    pub game: Fr,
+}
+
+impl EnterInputs {
+    pub fn to_field_elements(&self) -> crate::utils::Result<Vec<Fr>> {
+        let public_key = pack_key(&self.public_key)?;
+        Ok(vec![public_key[0], public_key[1], self.commitment, self.nullifier, self.game])
+    }
+
+    pub fn from_field_elements(elements: &[Fr]) -> crate::utils::Result<Self> {
+        check_arity(ProofType::EnterPhase, elements)?;
+        Ok(Self {
+            public_key: unpack_key([elements[0], elements[1]])?,
+            commitment: elements[2],
+            nullifier: elements[3],
+            game: elements[4],
+        })
+    }
+}
+
+/// Public inputs of a CHOICE proof
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChoiceInputs {
+    pub merkle_root: Fr,
+    pub nullifier: Fr,
+    pub chosen_public_key: [u8; 32],
+    pub dh_public_key: [u8; 32],
+    pub game: Fr,
+}
+
+impl ChoiceInputs {
+    pub fn to_field_elements(&self) -> crate::utils::Result<Vec<Fr>> {
+        let chosen = pack_key(&self.chosen_public_key)?;
+        let dh = pack_key(&self.dh_public_key)?;
+        Ok(vec![self.merkle_root, self.nullifier, chosen[0], chosen[1], dh[0], dh[1], self.game])
+    }
+
+    pub fn from_field_elements(elements: &[Fr]) -> crate::utils::Result<Self> {
+        check_arity(ProofType::ChoicePhase, elements)?;
+        Ok(Self {
+            merkle_root: elements[0],
+            nullifier: elements[1],
+            chosen_public_key: unpack_key([elements[2], elements[3]])?,
+            dh_public_key: unpack_key([elements[4], elements[5]])?,
+            game: elements[6],
+        })
+    }
+}
+
+/// Public inputs of a RECOVERY proof
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoveryInputs {
+    pub commitment: Fr,
+    pub public_key: [u8; 32],
+    pub nullifier: Fr,
+    pub chosen_public_key: [u8; 32],
+    pub dh_public_key: [u8; 32],
+    pub game: Fr,
+}
+
+impl RecoveryInputs {
+    pub fn to_field_elements(&self) -> crate::utils::Result<Vec<Fr>> {
+        let public_key = pack_key(&self.public_key)?;
+        let chosen = pack_key(&self.chosen_public_key)?;
+        let dh = pack_key(&self.dh_public_key)?;
+        Ok(vec![
+            self.commitment,
+            public_key[0],
+            public_key[1],
+            self.nullifier,
+            chosen[0],
+            chosen[1],
+            dh[0],
+            dh[1],
+            self.game,
+        ])
+    }
+
+    pub fn from_field_elements(elements: &[Fr]) -> crate::utils::Result<Self> {
+        check_arity(ProofType::RecoveryPhase, elements)?;
+        Ok(Self {
+            commitment: elements[0],
+            public_key: unpack_key([elements[1], elements[2]])?,
+            nullifier: elements[3],
+            chosen_public_key: unpack_key([elements[4], elements[5]])?,
+            dh_public_key: unpack_key([elements[6], elements[7]])?,
+            game: elements[8],
+        })
+    }
+}
+
+/// Public inputs of a REVEAL proof
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RevealInputs {
+    pub commitment: Fr,
+    pub public_key: [u8; 32],
+    pub santa_dh_public_key: [u8; 32],
+    pub dh_public_key: [u8; 32],
+    pub payload_digest: Fr,
+    pub game: Fr,
+}
+
+impl RevealInputs {
+    pub fn to_field_elements(&self) -> crate::utils::Result<Vec<Fr>> {
+        let public_key = pack_key(&self.public_key)?;
+        let santa_dh = pack_key(&self.santa_dh_public_key)?;
+        let dh = pack_key(&self.dh_public_key)?;
+        Ok(vec![
+            self.commitment,
+            public_key[0],
+            public_key[1],
+            santa_dh[0],
+            santa_dh[1],
+            dh[0],
+            dh[1],
+            self.payload_digest,
+            self.game,
+        ])
+    }
+
+    pub fn from_field_elements(elements: &[Fr]) -> crate::utils::Result<Self> {
+        check_arity(ProofType::RevealPhase, elements)?;
+        Ok(Self {
+            commitment: elements[0],
+            public_key: unpack_key([elements[1], elements[2]])?,
+            santa_dh_public_key: unpack_key([elements[3], elements[4]])?,
+            dh_public_key: unpack_key([elements[5], elements[6]])?,
+            payload_digest: elements[7],
+            game: elements[8],
+        })
+    }
+}
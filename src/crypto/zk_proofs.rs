@@ -1,60 +1,177 @@
-use ark_bn254::{Bn254, Fr};
+use super::circuits::{self, ChoiceCircuit, EnterCircuit, RecoveryCircuit, RevealCircuit};
+use super::merkle::MerkleTree;
+use super::params::{self, ParamsManifest};
+use super::public_inputs::{self, ChoiceInputs, EnterInputs, PublicInputs, RecoveryInputs, RevealInputs};
+use ark_bn254::{Bn254, Fr, G1Affine, G1Projective};
+use ark_ec::pairing::Pairing;
+use ark_ec::{CurveGroup, VariableBaseMSM};
+use ark_ff::{UniformRand, Zero};
 use ark_groth16::{Groth16, Proof, ProvingKey, VerifyingKey};
+use ark_relations::r1cs::ConstraintSynthesizer;
 use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
-use ark_std::rand::rngs::OsRng;
+use ark_snark::SNARK;
+use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::Path;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ZKProof {
     pub proof_data: Vec<u8>,
-    pub public_inputs: Vec<String>,
+    pub public_inputs: PublicInputs,
     pub proof_type: ProofType,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl ZKProof {
+    pub fn enter_inputs(&self) -> crate::utils::Result<EnterInputs> {
+        self.expect_type(ProofType::EnterPhase)?;
+        EnterInputs::from_field_elements(&self.public_inputs.to_field_elements(self.proof_type)?)
+    }
+
+    pub fn choice_inputs(&self) -> crate::utils::Result<ChoiceInputs> {
+        self.expect_type(ProofType::ChoicePhase)?;
+        ChoiceInputs::from_field_elements(&self.public_inputs.to_field_elements(self.proof_type)?)
+    }
+
+    pub fn recovery_inputs(&self) -> crate::utils::Result<RecoveryInputs> {
+        self.expect_type(ProofType::RecoveryPhase)?;
+        RecoveryInputs::from_field_elements(&self.public_inputs.to_field_elements(self.proof_type)?)
+    }
+
+    pub fn reveal_inputs(&self) -> crate::utils::Result<RevealInputs> {
+        self.expect_type(ProofType::RevealPhase)?;
+        RevealInputs::from_field_elements(&self.public_inputs.to_field_elements(self.proof_type)?)
+    }
+
+    fn expect_type(&self, proof_type: ProofType) -> crate::utils::Result<()> {
+        if self.proof_type != proof_type {
+            return Err(crate::utils::Error::CryptoError(format!(
+                "Expected a {:?} proof, got {:?}", proof_type, self.proof_type
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ProofType {
     EnterPhase,
     ChoicePhase,
     RevealPhase,
+    RecoveryPhase,
+}
+
+impl ProofType {
+    pub const ALL: [ProofType; 4] = [
+        ProofType::EnterPhase,
+        ProofType::ChoicePhase,
+        ProofType::RevealPhase,
+        ProofType::RecoveryPhase,
+    ];
+
+    /// Short name used for parameter file names
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProofType::EnterPhase => "enter",
+            ProofType::ChoicePhase => "choice",
+            ProofType::RevealPhase => "reveal",
+            ProofType::RecoveryPhase => "recovery",
+        }
+    }
+
+    /// Number of field elements the circuit exposes as public inputs
+    pub fn num_public_inputs(&self) -> usize {
+        match self {
+            ProofType::EnterPhase => 5,
+            ProofType::ChoicePhase => 7,
+            ProofType::RevealPhase => 9,
+            ProofType::RecoveryPhase => 9,
+        }
+    }
 }
 
 pub struct ZKProofSystem {
     proving_keys: HashMap<ProofType, ProvingKey<Bn254>>,
     verifying_keys: HashMap<ProofType, VerifyingKey<Bn254>>,
+    params_hash: String,
 }
 
 impl ZKProofSystem {
-    
+    /// Run a fresh local setup for every circuit.
+    ///
+    /// Whoever runs it knows the trapdoor and can forge proofs, so shared games
+    /// should load parameters from a setup ceremony via `from_params_dir`.
     pub fn new() -> crate::utils::Result<Self> {
         let mut proving_keys = HashMap::new();
         let mut verifying_keys = HashMap::new();
 
-      
-        for proof_type in [ProofType::EnterPhase, ProofType::ChoicePhase, ProofType::RevealPhase] {
+        for proof_type in ProofType::ALL {
             let (pk, vk) = Self::generate_keys_for_circuit(&proof_type)?;
-            proving_keys.insert(proof_type.clone(), pk);
+            proving_keys.insert(proof_type, pk);
             verifying_keys.insert(proof_type, vk);
         }
 
         Ok(Self {
             proving_keys,
+            params_hash: params::verifying_keys_hash(&verifying_keys)?,
             verifying_keys,
         })
     }
 
-    ///proof for the ENTER phase
+    /// Load a shared parameter set written by `write_params_dir`, checking
+    /// every key file against the hashes in its manifest
+    pub fn from_params_dir(dir: &Path) -> crate::utils::Result<Self> {
+        let (manifest, proving_keys, verifying_keys) = params::load_params(dir)?;
+
+        Ok(Self {
+            proving_keys,
+            verifying_keys,
+            params_hash: manifest.params_hash,
+        })
+    }
+
+    /// Hash identifying the parameter set, as in `ParamsManifest::params_hash`
+    pub fn params_hash(&self) -> &str {
+        &self.params_hash
+    }
+
+    /// Serialize all proving and verifying keys into `dir`
+    pub fn write_params_dir(&self, dir: &Path) -> crate::utils::Result<ParamsManifest> {
+        params::write_params(dir, &self.proving_keys, &self.verifying_keys)
+    }
+
+    ///proof for the ENTER phase of `game_id`
     pub fn prove_enter_phase(
         &self,
+        game_id: &[u8],
         public_key: &[u8],
         secret_key: &[u8],
     ) -> crate::utils::Result<ZKProof> {
         let proving_key = self.proving_keys.get(&ProofType::EnterPhase)
             .ok_or_else(|| crate::utils::Error::CryptoError("Enter phase proving key not found".to_string()))?;
 
-        
-        let proof_data = self.generate_proof_data(proving_key, &[public_key, secret_key])?;
-        let public_inputs = vec![hex::encode(public_key)];
+        let identity_secret = circuits::identity_secret(secret_key);
+        let public_key_limbs = public_inputs::pack_key(public_key)?;
+        let commitment = circuits::identity_commitment(identity_secret, public_key_limbs);
+        let game = public_inputs::game_field(game_id);
+        let nullifier = circuits::enter_nullifier(identity_secret, game);
+
+        let circuit = EnterCircuit {
+            identity_secret: Some(identity_secret),
+            public_key: Some(public_key_limbs),
+            commitment: Some(commitment),
+            nullifier: Some(nullifier),
+            game: Some(game),
+        };
+
+        let proof_data = self.generate_proof_data(proving_key, circuit)?;
+        let inputs = EnterInputs {
+            public_key: public_key.try_into().expect("length checked by pack_key"),
+            commitment,
+            nullifier,
+            game,
+        };
+        let public_inputs = PublicInputs::from_field_elements(&inputs.to_field_elements()?);
 
         Ok(ZKProof {
             proof_data,
@@ -64,23 +181,61 @@ impl ZKProofSystem {
     }
 
     ///proof for the CHOICE phase
+    ///
+    /// Shows membership of the chooser in `enter_set` without revealing which
+    /// leaf is theirs. Public inputs are the ENTER-set root, the CHOICE
+    /// nullifier, the chosen public key, the chooser's DH public key and the
+    /// game.
     pub fn prove_choice_phase(
         &self,
+        game_id: &[u8],
         chooser_public_key: &[u8],
-        chosen_public_key: &[u8],
         secret_key: &[u8],
+        chosen_public_key: &[u8],
+        dh_public_key: &[u8],
+        enter_set: &MerkleTree,
     ) -> crate::utils::Result<ZKProof> {
         let proving_key = self.proving_keys.get(&ProofType::ChoicePhase)
             .ok_or_else(|| crate::utils::Error::CryptoError("Choice phase proving key not found".to_string()))?;
 
-        let proof_data = self.generate_proof_data(
-            proving_key,
-            &[chooser_public_key, chosen_public_key, secret_key]
-        )?;
-        let public_inputs = vec![
-            hex::encode(chooser_public_key),
-            hex::encode(chosen_public_key),
-        ];
+        if chooser_public_key == chosen_public_key {
+            return Err(crate::utils::Error::CryptoError("Cannot choose yourself".to_string()));
+        }
+
+        let identity_secret = circuits::identity_secret(secret_key);
+        let chooser_limbs = public_inputs::pack_key(chooser_public_key)?;
+        let chosen_limbs = public_inputs::pack_key(chosen_public_key)?;
+        let dh_limbs = public_inputs::pack_key(dh_public_key)?;
+
+        let commitment = circuits::identity_commitment(identity_secret, chooser_limbs);
+        let index = enter_set.index_of(&commitment)
+            .ok_or_else(|| crate::utils::Error::CryptoError("Chooser is not part of the ENTER set".to_string()))?;
+        let path = enter_set.path(index)?;
+
+        let root = enter_set.root();
+        let game = public_inputs::game_field(game_id);
+        let nullifier = circuits::choice_nullifier(identity_secret, game);
+
+        let circuit = ChoiceCircuit {
+            identity_secret: Some(identity_secret),
+            chooser_public_key: Some(chooser_limbs),
+            path: Some(path),
+            root: Some(root),
+            nullifier: Some(nullifier),
+            chosen_public_key: Some(chosen_limbs),
+            dh_public_key: Some(dh_limbs),
+            game: Some(game),
+        };
+
+        let proof_data = self.generate_proof_data(proving_key, circuit)?;
+        let inputs = ChoiceInputs {
+            merkle_root: root,
+            nullifier,
+            chosen_public_key: chosen_public_key.try_into().expect("length checked by pack_key"),
+            dh_public_key: dh_public_key.try_into().expect("length checked by pack_key"),
+            game,
+        };
+        let public_inputs = PublicInputs::from_field_elements(&inputs.to_field_elements()?);
 
         Ok(ZKProof {
             proof_data,
@@ -89,14 +244,121 @@ impl ZKProofSystem {
         })
     }
 
-    /
+    ///proof for the REVEAL phase
+    ///
+    /// Binds the encrypted payload and both DH keys to the identity commitment
+    /// the revealer published in their ENTER record.
+    pub fn prove_reveal_phase(
+        &self,
+        game_id: &[u8],
+        public_key: &[u8],
+        secret_key: &[u8],
+        santa_dh_public_key: &[u8],
+        dh_public_key: &[u8],
+        encrypted_payload: &[u8],
+    ) -> crate::utils::Result<ZKProof> {
+        let proving_key = self.proving_keys.get(&ProofType::RevealPhase)
+            .ok_or_else(|| crate::utils::Error::CryptoError("Reveal phase proving key not found".to_string()))?;
+
+        let identity_secret = circuits::identity_secret(secret_key);
+        let public_key_limbs = public_inputs::pack_key(public_key)?;
+        let santa_dh_limbs = public_inputs::pack_key(santa_dh_public_key)?;
+        let dh_limbs = public_inputs::pack_key(dh_public_key)?;
+        let commitment = circuits::identity_commitment(identity_secret, public_key_limbs);
+        let payload_digest = public_inputs::payload_digest(encrypted_payload);
+        let game = public_inputs::game_field(game_id);
+
+        let circuit = RevealCircuit {
+            identity_secret: Some(identity_secret),
+            commitment: Some(commitment),
+            public_key: Some(public_key_limbs),
+            santa_dh_public_key: Some(santa_dh_limbs),
+            dh_public_key: Some(dh_limbs),
+            payload_digest: Some(payload_digest),
+            game: Some(game),
+        };
+
+        let proof_data = self.generate_proof_data(proving_key, circuit)?;
+        let inputs = RevealInputs {
+            commitment,
+            public_key: public_key.try_into().expect("length checked by pack_key"),
+            santa_dh_public_key: santa_dh_public_key.try_into().expect("length checked by pack_key"),
+            dh_public_key: dh_public_key.try_into().expect("length checked by pack_key"),
+            payload_digest,
+            game,
+        };
+        let public_inputs = PublicInputs::from_field_elements(&inputs.to_field_elements()?);
+
+        Ok(ZKProof {
+            proof_data,
+            public_inputs,
+            proof_type: ProofType::RevealPhase,
+        })
+    }
+
+    ///proof for the RECOVERY round
+    ///
+    /// Shows that the player with `public_key` owns their ENTER commitment and
+    /// spends their CHOICE nullifier on `chosen_public_key`.
+    pub fn prove_recovery_phase(
+        &self,
+        game_id: &[u8],
+        public_key: &[u8],
+        secret_key: &[u8],
+        chosen_public_key: &[u8],
+        dh_public_key: &[u8],
+    ) -> crate::utils::Result<ZKProof> {
+        let proving_key = self.proving_keys.get(&ProofType::RecoveryPhase)
+            .ok_or_else(|| crate::utils::Error::CryptoError("Recovery phase proving key not found".to_string()))?;
+
+        if public_key == chosen_public_key {
+            return Err(crate::utils::Error::CryptoError("Cannot choose yourself".to_string()));
+        }
+
+        let identity_secret = circuits::identity_secret(secret_key);
+        let public_key_limbs = public_inputs::pack_key(public_key)?;
+        let chosen_limbs = public_inputs::pack_key(chosen_public_key)?;
+        let dh_limbs = public_inputs::pack_key(dh_public_key)?;
+        let commitment = circuits::identity_commitment(identity_secret, public_key_limbs);
+        let game = public_inputs::game_field(game_id);
+        let nullifier = circuits::choice_nullifier(identity_secret, game);
+
+        let circuit = RecoveryCircuit {
+            identity_secret: Some(identity_secret),
+            commitment: Some(commitment),
+            public_key: Some(public_key_limbs),
+            nullifier: Some(nullifier),
+            chosen_public_key: Some(chosen_limbs),
+            dh_public_key: Some(dh_limbs),
+            game: Some(game),
+        };
+
+        let proof_data = self.generate_proof_data(proving_key, circuit)?;
+        let inputs = RecoveryInputs {
+            commitment,
+            public_key: public_key.try_into().expect("length checked by pack_key"),
+            nullifier,
+            chosen_public_key: chosen_public_key.try_into().expect("length checked by pack_key"),
+            dh_public_key: dh_public_key.try_into().expect("length checked by pack_key"),
+            game,
+        };
+        let public_inputs = PublicInputs::from_field_elements(&inputs.to_field_elements()?);
+
+        Ok(ZKProof {
+            proof_data,
+            public_inputs,
+            proof_type: ProofType::RecoveryPhase,
+        })
+    }
+
+    /// Verify a proof against the verifying key for its phase
     pub fn verify_proof(&self, proof: &ZKProof) -> crate::utils::Result<bool> {
         let verifying_key = self.verifying_keys.get(&proof.proof_type)
             .ok_or_else(|| crate::utils::Error::CryptoError("Verifying key not found".to_string()))?;
 
-    
+
         let groth16_proof = self.deserialize_proof(&proof.proof_data)?;
-        let public_inputs = self.parse_public_inputs(&proof.public_inputs)?;
+        let public_inputs = proof.public_inputs.to_field_elements(proof.proof_type)?;
 
         let is_valid = Groth16::<Bn254>::verify(verifying_key, &public_inputs, &groth16_proof)
             .map_err(|e| crate::utils::Error::CryptoError(e.to_string()))?;
@@ -105,28 +367,144 @@ impl ZKProofSystem {
     }
 
 
+    /// Verify many proofs at once.
+    ///
+    /// Proofs are grouped by `ProofType` and each group is checked with a single
+    /// multi-pairing over a random linear combination of the Groth16 equations.
+    /// Groups that fail are bisected to pinpoint the bad proofs. Returns the
+    /// indices (into `proofs`) of every proof that did not verify, including
+    /// ones that could not be decoded.
+    pub fn verify_batch(&self, proofs: &[ZKProof]) -> crate::utils::Result<Vec<usize>> {
+        let mut failed = Vec::new();
+        let mut groups: HashMap<ProofType, Vec<BatchEntry>> = HashMap::new();
+
+        for (index, proof) in proofs.iter().enumerate() {
+            let groth16_proof = self.deserialize_proof(&proof.proof_data);
+            let public_inputs = proof.public_inputs.to_field_elements(proof.proof_type);
+
+            match (groth16_proof, public_inputs) {
+                (Ok(groth16_proof), Ok(public_inputs)) => {
+                    groups.entry(proof.proof_type).or_default().push(BatchEntry {
+                        index,
+                        proof: groth16_proof,
+                        public_inputs,
+                    });
+                }
+                _ => failed.push(index),
+            }
+        }
+
+        for (proof_type, entries) in groups {
+            match self.verifying_keys.get(&proof_type) {
+                Some(verifying_key) => find_batch_failures(verifying_key, &entries, &mut failed)?,
+                None => failed.extend(entries.iter().map(|entry| entry.index)),
+            }
+        }
+
+        failed.sort_unstable();
+        Ok(failed)
+    }
+
     fn generate_keys_for_circuit(
         proof_type: &ProofType,
     ) -> crate::utils::Result<(ProvingKey<Bn254>, VerifyingKey<Bn254>)> {
-        todo!("Implement circuit-specific key generation")
+        match proof_type {
+            ProofType::EnterPhase => Groth16::<Bn254>::circuit_specific_setup(EnterCircuit::blank(), &mut OsRng)
+                .map_err(|e| crate::utils::Error::CryptoError(e.to_string())),
+            ProofType::ChoicePhase => Groth16::<Bn254>::circuit_specific_setup(ChoiceCircuit::blank(), &mut OsRng)
+                .map_err(|e| crate::utils::Error::CryptoError(e.to_string())),
+            ProofType::RevealPhase => Groth16::<Bn254>::circuit_specific_setup(RevealCircuit::blank(), &mut OsRng)
+                .map_err(|e| crate::utils::Error::CryptoError(e.to_string())),
+            ProofType::RecoveryPhase => Groth16::<Bn254>::circuit_specific_setup(RecoveryCircuit::blank(), &mut OsRng)
+                .map_err(|e| crate::utils::Error::CryptoError(e.to_string())),
+        }
     }
 
-    fn generate_proof_data(
+    fn generate_proof_data<C: ConstraintSynthesizer<Fr>>(
         &self,
         proving_key: &ProvingKey<Bn254>,
-        inputs: &[&[u8]],
+        circuit: C,
     ) -> crate::utils::Result<Vec<u8>> {
-        
-        todo!("Implement proof generation")
+        let proof = Groth16::<Bn254>::prove(proving_key, circuit, &mut OsRng)
+            .map_err(|e| crate::utils::Error::CryptoError(e.to_string()))?;
+
+        let mut proof_data = Vec::new();
+        proof.serialize_compressed(&mut proof_data)
+            .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+
+        Ok(proof_data)
     }
 
     fn deserialize_proof(&self, proof_data: &[u8]) -> crate::utils::Result<Proof<Bn254>> {
         Proof::<Bn254>::deserialize_compressed(proof_data)
             .map_err(|e| crate::utils::Error::CryptoError(e.to_string()))
     }
+}
+
+struct BatchEntry {
+    index: usize,
+    proof: Proof<Bn254>,
+    public_inputs: Vec<Fr>,
+}
+
+fn find_batch_failures(
+    verifying_key: &VerifyingKey<Bn254>,
+    entries: &[BatchEntry],
+    failed: &mut Vec<usize>,
+) -> crate::utils::Result<()> {
+    if entries.is_empty() || batch_holds(verifying_key, entries)? {
+        return Ok(());
+    }
+
+    if entries.len() == 1 {
+        failed.push(entries[0].index);
+        return Ok(());
+    }
+
+    let (left, right) = entries.split_at(entries.len() / 2);
+    find_batch_failures(verifying_key, left, failed)?;
+    find_batch_failures(verifying_key, right, failed)
+}
+
+/// Check `Π e(rᵢAᵢ, Bᵢ) = e(Σrᵢ·α, β) · e(Σrᵢ·Lᵢ, γ) · e(Σrᵢ·Cᵢ, δ)` for random `rᵢ`,
+/// where `Lᵢ` is the public-input combination of proof `i`
+fn batch_holds(verifying_key: &VerifyingKey<Bn254>, entries: &[BatchEntry]) -> crate::utils::Result<bool> {
+    let mut rng = OsRng;
+    let gamma_abc = &verifying_key.gamma_abc_g1;
 
-    fn parse_public_inputs(&self, inputs: &[String]) -> crate::utils::Result<Vec<Fr>> {
-        
-        todo!("Implement public input parsing")
+    let mut g1_terms: Vec<G1Affine> = Vec::with_capacity(entries.len() + 3);
+    let mut g2_terms = Vec::with_capacity(entries.len() + 3);
+
+    let mut r_sum = Fr::zero();
+    let mut input_scalars = vec![Fr::zero(); gamma_abc.len()];
+    let mut c_combined = G1Projective::zero();
+
+    for entry in entries {
+        if entry.public_inputs.len() + 1 != gamma_abc.len() {
+            return Ok(false);
+        }
+
+        let r = Fr::rand(&mut rng);
+        r_sum += r;
+        input_scalars[0] += r;
+        for (scalar, input) in input_scalars[1..].iter_mut().zip(&entry.public_inputs) {
+            *scalar += r * input;
+        }
+        c_combined += entry.proof.c * r;
+
+        g1_terms.push((entry.proof.a * r).into_affine());
+        g2_terms.push(entry.proof.b);
     }
+
+    let inputs_combined = G1Projective::msm(gamma_abc, &input_scalars)
+        .map_err(|_| crate::utils::Error::CryptoError("MSM length mismatch".to_string()))?;
+
+    g1_terms.push((verifying_key.alpha_g1 * -r_sum).into_affine());
+    g2_terms.push(verifying_key.beta_g2);
+    g1_terms.push((-inputs_combined).into_affine());
+    g2_terms.push(verifying_key.gamma_g2);
+    g1_terms.push((-c_combined).into_affine());
+    g2_terms.push(verifying_key.delta_g2);
+
+    Ok(Bn254::multi_pairing(g1_terms, g2_terms).is_zero())
 }
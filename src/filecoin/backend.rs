@@ -0,0 +1,109 @@
+use super::{RecordType, StorageRecord};
+use async_trait::async_trait;
+use cid::Cid;
+use multihash::Multihash;
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+
+/// Multicodec code of raw binary blocks
+pub const RAW_CODEC: u64 = 0x55;
+
+/// Multihash code of sha2-256
+pub const SHA2_256_CODE: u64 = 0x12;
+
+/// Content-addressed store for protocol records.
+///
+/// `SecretSantaProtocol` only talks to storage through this trait, so a game can
+/// run against Filecoin, a local directory or memory.
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    /// Store a serialized record and return its metadata
+    async fn put(&mut self, data: Vec<u8>, record_type: RecordType) -> crate::utils::Result<StorageRecord>;
+
+    /// Store a record exported from another backend. Its time and epoch are
+    /// only claims of whoever exported it, so unless the backend can check
+    /// them it stores the record as new.
+    async fn import(&mut self, data: Vec<u8>, record: &StorageRecord) -> crate::utils::Result<StorageRecord> {
+        self.put(data, record.record_type).await
+    }
+
+    /// Fetch the bytes stored under `cid`
+    async fn get(&self, cid: &Cid) -> crate::utils::Result<Vec<u8>>;
+
+    /// List every known record of the given type
+    async fn list(&self, record_type: RecordType) -> crate::utils::Result<Vec<StorageRecord>>;
+
+    /// Pick up records published by other participants and return how many
+    /// were new. Backends that share state directly have nothing to do.
+    async fn sync(&mut self) -> crate::utils::Result<usize> {
+        Ok(0)
+    }
+}
+
+#[async_trait]
+impl<B: StorageBackend + ?Sized> StorageBackend for Box<B> {
+    async fn put(&mut self, data: Vec<u8>, record_type: RecordType) -> crate::utils::Result<StorageRecord> {
+        (**self).put(data, record_type).await
+    }
+
+    async fn import(&mut self, data: Vec<u8>, record: &StorageRecord) -> crate::utils::Result<StorageRecord> {
+        (**self).import(data, record).await
+    }
+
+    async fn get(&self, cid: &Cid) -> crate::utils::Result<Vec<u8>> {
+        (**self).get(cid).await
+    }
+
+    async fn list(&self, record_type: RecordType) -> crate::utils::Result<Vec<StorageRecord>> {
+        (**self).list(record_type).await
+    }
+
+    async fn sync(&mut self) -> crate::utils::Result<usize> {
+        (**self).sync().await
+    }
+}
+
+/// CIDv1 of `data` as a raw block with a sha2-256 multihash
+pub fn compute_cid(data: &[u8]) -> Cid {
+    let digest = Sha256::digest(data);
+    let multihash = Multihash::<64>::wrap(SHA2_256_CODE, &digest)
+        .expect("sha2-256 digest fits in a 64-byte multihash");
+
+    Cid::new_v1(RAW_CODEC, multihash)
+}
+
+/// Check that `data` hashes to the multihash inside `cid`
+pub fn verify_cid(cid: &Cid, data: &[u8]) -> crate::utils::Result<()> {
+    let hash = cid.hash();
+    if hash.code() != SHA2_256_CODE {
+        return Err(crate::utils::Error::IntegrityError(format!(
+            "CID {} uses unsupported multihash code {:#x}", cid, hash.code()
+        )));
+    }
+
+    if hash.digest() != Sha256::digest(data).as_slice() {
+        return Err(crate::utils::Error::IntegrityError(format!(
+            "Content does not match CID {}", cid
+        )));
+    }
+
+    Ok(())
+}
+
+/// Fetch and decode every record of `record_type`
+pub async fn load_records<T, S>(backend: &S, record_type: RecordType) -> crate::utils::Result<Vec<T>>
+where
+    T: DeserializeOwned,
+    S: StorageBackend + ?Sized,
+{
+    let mut records = Vec::new();
+
+    for record in backend.list(record_type).await? {
+        let data = backend.get(&record.content_cid).await?;
+        let record: T = bincode::deserialize(&data)
+            .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+        records.push(record);
+    }
+
+    Ok(records)
+}
@@ -0,0 +1,431 @@
+//! CARv1 export and import of a game transcript.
+//!
+//! The archive holds every game record as a raw block, plus a DAG-JSON root
+//! block (`GameManifest`) listing each record's CID and `RecordType`. The CAR
+//! header is DAG-CBOR `{"roots": [root], "version": 1}`, encoded by hand since
+//! it is the only CBOR the crate needs.
+
+use super::backend::{compute_cid, verify_cid, StorageBackend, SHA2_256_CODE};
+use super::{RecordType, StorageRecord};
+use cid::Cid;
+use multihash::Multihash;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+
+/// Multicodec code of DAG-JSON blocks
+pub const DAG_JSON_CODEC: u64 = 0x0129;
+
+/// Current manifest format
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// Record types that make up a game transcript, in export order
+pub const GAME_RECORD_TYPES: [RecordType; 5] = [
+    RecordType::GameConfig,
+    RecordType::EnterTransaction,
+    RecordType::ChoiceTransaction,
+    RecordType::RecoveryTransaction,
+    RecordType::RevealTransaction,
+];
+
+// Upper bound on a single section, to reject corrupt length prefixes early
+const MAX_SECTION_LEN: u64 = 64 * 1024 * 1024;
+
+/// Root block of an exported game. Fields are declared in sorted order so the
+/// JSON encoding is canonical DAG-JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GameManifest {
+    pub records: Vec<ManifestEntry>,
+    pub version: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub cid: Link,
+    #[serde(default)]
+    pub epoch: Option<u64>,
+    pub id: String,
+    pub record_type: RecordType,
+    pub timestamp: u64,
+}
+
+/// DAG-JSON link, `{"/": "<cid>"}`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Link {
+    #[serde(rename = "/")]
+    pub cid: String,
+}
+
+impl ManifestEntry {
+    fn from_record(record: &StorageRecord) -> Self {
+        Self {
+            cid: Link { cid: record.content_cid.to_string() },
+            epoch: record.epoch,
+            id: record.id.clone(),
+            record_type: record.record_type,
+            timestamp: record.timestamp,
+        }
+    }
+
+    pub fn content_cid(&self) -> crate::utils::Result<Cid> {
+        Cid::try_from(self.cid.cid.as_str())
+            .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))
+    }
+
+    /// The `StorageRecord` this entry was exported from
+    pub fn to_record(&self) -> crate::utils::Result<StorageRecord> {
+        Ok(StorageRecord {
+            id: self.id.clone(),
+            content_cid: self.content_cid()?,
+            timestamp: self.timestamp,
+            record_type: self.record_type,
+            epoch: self.epoch,
+        })
+    }
+}
+
+/// Parsed CAR file: its root and every block, in file order
+#[derive(Debug, Clone)]
+pub struct CarFile {
+    pub root: Cid,
+    pub blocks: Vec<(Cid, Vec<u8>)>,
+}
+
+impl CarFile {
+    pub fn block(&self, cid: &Cid) -> Option<&[u8]> {
+        self.blocks.iter()
+            .find(|(block_cid, _)| block_cid == cid)
+            .map(|(_, data)| data.as_slice())
+    }
+
+    /// Decode the root block as a game manifest
+    pub fn manifest(&self) -> crate::utils::Result<GameManifest> {
+        let root_block = self.block(&self.root)
+            .ok_or_else(|| crate::utils::Error::SerializationError("CAR root block is missing".to_string()))?;
+
+        let manifest: GameManifest = serde_json::from_slice(root_block)
+            .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+
+        if manifest.version != MANIFEST_VERSION {
+            return Err(crate::utils::Error::SerializationError(format!(
+                "Unsupported game manifest version {}", manifest.version
+            )));
+        }
+
+        Ok(manifest)
+    }
+}
+
+/// Write the game records in `backend` that `keep` accepts, given each record
+/// and its content, to `writer` as a CARv1 file and return the root CID. A
+/// backend may hold records of several games, so `keep` selects one of them.
+pub async fn export_car<S, F, W>(backend: &S, keep: F, mut writer: W) -> crate::utils::Result<Cid>
+where
+    S: StorageBackend + ?Sized,
+    F: Fn(&StorageRecord, &[u8]) -> bool,
+    W: Write,
+{
+    let mut records = Vec::new();
+    let mut blocks = Vec::new();
+    for record_type in GAME_RECORD_TYPES {
+        for record in backend.list(record_type).await? {
+            let data = backend.get(&record.content_cid).await?;
+            if keep(&record, &data) {
+                records.push(record);
+                blocks.push(data);
+            }
+        }
+    }
+
+    let manifest = GameManifest {
+        records: records.iter().map(ManifestEntry::from_record).collect(),
+        version: MANIFEST_VERSION,
+    };
+    let root_block = serde_json::to_vec(&manifest)
+        .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+    let root = dag_json_cid(&root_block);
+
+    write_section(&mut writer, &encode_header(&root))?;
+    write_block(&mut writer, &root, &root_block)?;
+    for (record, data) in records.iter().zip(&blocks) {
+        write_block(&mut writer, &record.content_cid, data)?;
+    }
+
+    writer.flush().map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
+    Ok(root)
+}
+
+/// Write `data` as a CARv1 file holding one raw block rooted at its CID, the
+/// form a Lotus node imports without re-chunking it into a different CID
+pub fn write_block_car<W: Write>(mut writer: W, data: &[u8]) -> crate::utils::Result<Cid> {
+    let cid = compute_cid(data);
+
+    write_section(&mut writer, &encode_header(&cid))?;
+    write_block(&mut writer, &cid, data)?;
+
+    writer.flush().map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
+    Ok(cid)
+}
+
+/// Read a CARv1 file, checking every block against its CID
+pub fn read_car<R: Read>(mut reader: R) -> crate::utils::Result<CarFile> {
+    let header = read_section(&mut reader)?
+        .ok_or_else(|| crate::utils::Error::SerializationError("Empty CAR file".to_string()))?;
+    let root = decode_header(&header)?;
+
+    let mut blocks = Vec::new();
+    while let Some(section) = read_section(&mut reader)? {
+        let mut cursor = std::io::Cursor::new(section.as_slice());
+        let cid = Cid::read_bytes(&mut cursor)
+            .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+        let data = section[cursor.position() as usize..].to_vec();
+
+        verify_cid(&cid, &data)?;
+        blocks.push((cid, data));
+    }
+
+    Ok(CarFile { root, blocks })
+}
+
+/// Store every record listed in a CAR file's manifest into `backend`. The
+/// times in the manifest are not trusted; see `StorageBackend::import`.
+pub async fn import_car<S, R>(backend: &mut S, reader: R) -> crate::utils::Result<Vec<StorageRecord>>
+where
+    S: StorageBackend + ?Sized,
+    R: Read,
+{
+    let car = read_car(reader)?;
+    let manifest = car.manifest()?;
+
+    let mut imported = Vec::new();
+    for entry in &manifest.records {
+        let cid = entry.content_cid()?;
+        let data = car.block(&cid)
+            .ok_or_else(|| crate::utils::Error::SerializationError(format!("Block {} missing from CAR", cid)))?;
+
+        let record = backend.import(data.to_vec(), &entry.to_record()?).await?;
+        if record.content_cid != cid {
+            return Err(crate::utils::Error::IntegrityError(format!(
+                "Backend stored {} as {}", cid, record.content_cid
+            )));
+        }
+        imported.push(record);
+    }
+
+    Ok(imported)
+}
+
+fn dag_json_cid(data: &[u8]) -> Cid {
+    let multihash = Multihash::<64>::wrap(SHA2_256_CODE, &Sha256::digest(data))
+        .expect("sha2-256 digest fits in a 64-byte multihash");
+    Cid::new_v1(DAG_JSON_CODEC, multihash)
+}
+
+fn write_block<W: Write>(writer: &mut W, cid: &Cid, data: &[u8]) -> crate::utils::Result<()> {
+    let mut section = cid.to_bytes();
+    section.extend_from_slice(data);
+    write_section(writer, &section)
+}
+
+fn write_section<W: Write>(writer: &mut W, section: &[u8]) -> crate::utils::Result<()> {
+    let mut prefix = Vec::new();
+    write_varint(&mut prefix, section.len() as u64);
+
+    writer.write_all(&prefix)
+        .and_then(|_| writer.write_all(section))
+        .map_err(|e| crate::utils::Error::FileError(e.to_string()))
+}
+
+/// Read one length-prefixed section, or `None` at a clean end of file
+fn read_section<R: Read>(reader: &mut R) -> crate::utils::Result<Option<Vec<u8>>> {
+    let len = match read_varint(reader)? {
+        Some(len) => len,
+        None => return Ok(None),
+    };
+
+    if len > MAX_SECTION_LEN {
+        return Err(crate::utils::Error::SerializationError(format!(
+            "CAR section of {} bytes exceeds the limit", len
+        )));
+    }
+
+    let mut section = vec![0u8; len as usize];
+    reader.read_exact(&mut section)
+        .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+
+    Ok(Some(section))
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> crate::utils::Result<Option<u64>> {
+    let mut value = 0u64;
+
+    for i in 0..10 {
+        let mut byte = [0u8; 1];
+        match reader.read(&mut byte) {
+            Ok(0) if i == 0 => return Ok(None),
+            Ok(0) => {
+                return Err(crate::utils::Error::SerializationError("Truncated varint".to_string()));
+            }
+            Ok(_) => {}
+            Err(e) => return Err(crate::utils::Error::FileError(e.to_string())),
+        }
+
+        value |= u64::from(byte[0] & 0x7f) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+
+    Err(crate::utils::Error::SerializationError("Varint too long".to_string()))
+}
+
+// DAG-CBOR major types used by the header
+const CBOR_UINT: u8 = 0;
+const CBOR_BYTES: u8 = 2;
+const CBOR_TEXT: u8 = 3;
+const CBOR_ARRAY: u8 = 4;
+const CBOR_MAP: u8 = 5;
+const CBOR_TAG: u8 = 6;
+
+// CBOR tag for IPLD links
+const CID_TAG: u64 = 42;
+
+fn encode_header(root: &Cid) -> Vec<u8> {
+    let mut out = Vec::new();
+    cbor_head(&mut out, CBOR_MAP, 2);
+
+    // DAG-CBOR orders map keys by length, so "roots" precedes "version"
+    cbor_text(&mut out, "roots");
+    cbor_head(&mut out, CBOR_ARRAY, 1);
+    cbor_head(&mut out, CBOR_TAG, CID_TAG);
+    let cid_bytes = root.to_bytes();
+    cbor_head(&mut out, CBOR_BYTES, cid_bytes.len() as u64 + 1);
+    out.push(0x00); // multibase identity prefix
+    out.extend_from_slice(&cid_bytes);
+
+    cbor_text(&mut out, "version");
+    cbor_head(&mut out, CBOR_UINT, 1);
+
+    out
+}
+
+fn decode_header(header: &[u8]) -> crate::utils::Result<Cid> {
+    let mut cbor = CborReader { data: header, pos: 0 };
+    let mut roots = Vec::new();
+    let mut version = None;
+
+    let entries = cbor.expect(CBOR_MAP)?;
+    for _ in 0..entries {
+        match cbor.text()? {
+            "roots" => {
+                for _ in 0..cbor.expect(CBOR_ARRAY)? {
+                    if cbor.expect(CBOR_TAG)? != CID_TAG {
+                        return Err(header_error("root is not a CID"));
+                    }
+                    let bytes = cbor.bytes()?;
+                    if bytes.first() != Some(&0x00) {
+                        return Err(header_error("CID is missing its multibase prefix"));
+                    }
+                    let cid = Cid::try_from(&bytes[1..])
+                        .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+                    roots.push(cid);
+                }
+            }
+            "version" => version = Some(cbor.expect(CBOR_UINT)?),
+            other => return Err(header_error(&format!("unexpected key {}", other))),
+        }
+    }
+
+    if version != Some(1) {
+        return Err(header_error("only CARv1 is supported"));
+    }
+
+    match roots.as_slice() {
+        [root] => Ok(*root),
+        _ => Err(header_error("expected exactly one root")),
+    }
+}
+
+fn cbor_head(out: &mut Vec<u8>, major: u8, value: u64) {
+    let major = major << 5;
+    if value < 24 {
+        out.push(major | value as u8);
+    } else if value <= u8::MAX as u64 {
+        out.push(major | 24);
+        out.push(value as u8);
+    } else if value <= u16::MAX as u64 {
+        out.push(major | 25);
+        out.extend_from_slice(&(value as u16).to_be_bytes());
+    } else if value <= u32::MAX as u64 {
+        out.push(major | 26);
+        out.extend_from_slice(&(value as u32).to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+fn cbor_text(out: &mut Vec<u8>, text: &str) {
+    cbor_head(out, CBOR_TEXT, text.len() as u64);
+    out.extend_from_slice(text.as_bytes());
+}
+
+fn header_error(reason: &str) -> crate::utils::Error {
+    crate::utils::Error::SerializationError(format!("Invalid CAR header: {}", reason))
+}
+
+struct CborReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> CborReader<'a> {
+    fn take(&mut self, len: usize) -> crate::utils::Result<&'a [u8]> {
+        let end = self.pos.checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| header_error("truncated"))?;
+        let slice = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn head(&mut self) -> crate::utils::Result<(u8, u64)> {
+        let initial = self.take(1)?[0];
+        let major = initial >> 5;
+        let value = match initial & 0x1f {
+            info @ 0..=23 => info as u64,
+            24 => self.take(1)?[0] as u64,
+            25 => u16::from_be_bytes(self.take(2)?.try_into().unwrap()) as u64,
+            26 => u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as u64,
+            27 => u64::from_be_bytes(self.take(8)?.try_into().unwrap()),
+            _ => return Err(header_error("indefinite lengths are not allowed")),
+        };
+        Ok((major, value))
+    }
+
+    fn expect(&mut self, major: u8) -> crate::utils::Result<u64> {
+        match self.head()? {
+            (actual, value) if actual == major => Ok(value),
+            (actual, _) => Err(header_error(&format!("expected major type {}, found {}", major, actual))),
+        }
+    }
+
+    fn bytes(&mut self) -> crate::utils::Result<&'a [u8]> {
+        let len = self.expect(CBOR_BYTES)?;
+        self.take(len as usize)
+    }
+
+    fn text(&mut self) -> crate::utils::Result<&'a str> {
+        let len = self.expect(CBOR_TEXT)?;
+        std::str::from_utf8(self.take(len as usize)?)
+            .map_err(|_| header_error("map key is not UTF-8"))
+    }
+}
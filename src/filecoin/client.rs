@@ -0,0 +1,389 @@
+//! Typed JSON-RPC client for the Lotus methods the game uses.
+//!
+//! Every call is a JSON-RPC 2.0 POST to the node's `/rpc/v1` endpoint with an
+//! optional bearer token. Failures are reported as `LotusError`, which keeps
+//! timeouts, HTTP failures and node-side RPC errors apart.
+//!
+//! `ClientImport` and `ClientRetrieve` exchange files through paths on the
+//! node, so those two calls need a node that shares the local filesystem.
+
+use super::car::{write_block_car, Link};
+use super::deals::{DealClient, DealProposal, DealState, MinerAsk};
+use async_trait::async_trait;
+use base64::Engine;
+use cid::Cid;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Timeout applied to each request unless configured otherwise
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(thiserror::Error, Debug)]
+pub enum LotusError {
+    #[error("Lotus request timed out")]
+    Timeout,
+    #[error("Lotus transport error: {0}")]
+    Transport(String),
+    #[error("Lotus returned HTTP {status}: {body}")]
+    Http { status: u16, body: String },
+    #[error("Lotus RPC error {code}: {message}")]
+    Rpc { code: i64, message: String },
+    #[error("Unexpected Lotus response: {0}")]
+    Decode(String),
+}
+
+impl From<LotusError> for crate::utils::Error {
+    fn from(e: LotusError) -> Self {
+        crate::utils::Error::StorageError(e.to_string())
+    }
+}
+
+impl From<reqwest::Error> for LotusError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            LotusError::Timeout
+        } else if e.is_decode() {
+            LotusError::Decode(e.to_string())
+        } else {
+            LotusError::Transport(e.to_string())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct TipSet {
+    pub cids: Vec<Link>,
+    pub height: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct StorageAsk {
+    /// attoFIL per GiB per epoch, as a decimal string
+    pub price: String,
+    pub min_piece_size: u64,
+    pub max_piece_size: u64,
+    pub miner: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DealInfo {
+    pub proposal_cid: Link,
+    /// Numeric storage market state, see `client_get_deal_status`
+    pub state: u64,
+    #[serde(default)]
+    pub message: String,
+}
+
+/// On-chain message with its params decoded from base64
+#[derive(Debug, Clone)]
+pub struct ChainMessage {
+    pub from: String,
+    pub to: String,
+    pub params: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    result: Option<Value>,
+    error: Option<RpcErrorBody>,
+}
+
+#[derive(Deserialize)]
+struct RpcErrorBody {
+    code: i64,
+    message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct LotusClient {
+    http: reqwest::Client,
+    endpoint: String,
+    auth_token: Option<String>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl LotusClient {
+    /// Client for the RPC endpoint at `endpoint`, e.g. `http://127.0.0.1:1234/rpc/v1`
+    pub fn new(endpoint: &str, auth_token: Option<&str>, timeout: Duration) -> Result<Self, LotusError> {
+        let http = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()?;
+
+        Ok(Self {
+            http,
+            endpoint: endpoint.to_string(),
+            auth_token: auth_token.map(str::to_string),
+            next_id: Arc::new(AtomicU64::new(1)),
+        })
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// Call `Filecoin.<method>` with positional `params`
+    pub async fn call<R: DeserializeOwned>(&self, method: &str, params: Value) -> Result<R, LotusError> {
+        let body = json!({
+            "jsonrpc": "2.0",
+            "method": format!("Filecoin.{}", method),
+            "params": params,
+            "id": self.next_id.fetch_add(1, Ordering::Relaxed),
+        });
+
+        let mut request = self.http.post(&self.endpoint).json(&body);
+        if let Some(token) = &self.auth_token {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(LotusError::Http {
+                status: status.as_u16(),
+                body: response.text().await.unwrap_or_default(),
+            });
+        }
+
+        let response: RpcResponse = response.json().await?;
+        if let Some(error) = response.error {
+            return Err(LotusError::Rpc { code: error.code, message: error.message });
+        }
+
+        serde_json::from_value(response.result.unwrap_or(Value::Null))
+            .map_err(|e| LotusError::Decode(format!("{}: {}", method, e)))
+    }
+
+    pub async fn chain_head(&self) -> Result<TipSet, LotusError> {
+        self.call("ChainHead", json!([])).await
+    }
+
+    /// Network name, `mainnet` on mainnet
+    pub async fn state_network_name(&self) -> Result<String, LotusError> {
+        self.call("StateNetworkName", json!([])).await
+    }
+
+    pub async fn wallet_default_address(&self) -> Result<String, LotusError> {
+        self.call("WalletDefaultAddress", json!([])).await
+    }
+
+    /// Import `data` as a single raw block and return the root CID the node reports
+    pub async fn client_import(&self, data: &[u8]) -> Result<Cid, LotusError> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct ImportRes {
+            root: Link,
+        }
+
+        let path = transfer_path("import.car");
+        let file = std::fs::File::create(&path)
+            .map_err(|e| LotusError::Transport(e.to_string()))?;
+        write_block_car(file, data)
+            .map_err(|e| LotusError::Transport(e.to_string()))?;
+
+        let result = self.call::<ImportRes>("ClientImport", json!([{ "Path": path, "IsCAR": true }])).await;
+        let _ = std::fs::remove_file(&path);
+
+        parse_link(&result?.root)
+    }
+
+    /// Fetch the content of `root` through a retrieval deal with the first
+    /// miner offering it
+    pub async fn client_retrieve(&self, root: &Cid) -> Result<Vec<u8>, LotusError> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct QueryOffer {
+            #[serde(default)]
+            err: String,
+            size: u64,
+            min_price: String,
+            unseal_price: String,
+            payment_interval: u64,
+            payment_interval_increase: u64,
+            miner: String,
+            miner_peer: Value,
+        }
+
+        let offers: Option<Vec<QueryOffer>> = self.call("ClientFindData", json!([link(root), null])).await?;
+        let offer = offers.unwrap_or_default()
+            .into_iter()
+            .find(|offer| offer.err.is_empty())
+            .ok_or_else(|| LotusError::Rpc { code: 0, message: format!("No retrieval offer for {}", root) })?;
+
+        let order = json!({
+            "Root": link(root),
+            "Piece": null,
+            "Size": offer.size,
+            "Total": offer.min_price,
+            "UnsealPrice": offer.unseal_price,
+            "PaymentInterval": offer.payment_interval,
+            "PaymentIntervalIncrease": offer.payment_interval_increase,
+            "Client": self.wallet_default_address().await?,
+            "Miner": offer.miner,
+            "MinerPeer": offer.miner_peer,
+        });
+
+        let path = transfer_path("retrieve");
+        let result = self.call::<Value>("ClientRetrieve", json!([order, { "Path": path, "IsCAR": false }])).await
+            .and_then(|_| std::fs::read(&path).map_err(|e| LotusError::Transport(e.to_string())));
+        let _ = std::fs::remove_file(&path);
+
+        result
+    }
+
+    /// Libp2p peer id of `miner`
+    pub async fn state_miner_peer_id(&self, miner: &str) -> Result<String, LotusError> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct MinerInfo {
+            peer_id: Option<String>,
+        }
+
+        let info: MinerInfo = self.call("StateMinerInfo", json!([miner, null])).await?;
+        info.peer_id
+            .ok_or_else(|| LotusError::Decode(format!("Miner {} has no peer id", miner)))
+    }
+
+    pub async fn client_query_ask(&self, peer_id: &str, miner: &str) -> Result<StorageAsk, LotusError> {
+        self.call("ClientQueryAsk", json!([peer_id, miner])).await
+    }
+
+    /// Propose a deal paid from `wallet` and return the proposal CID
+    pub async fn client_start_deal(&self, proposal: &DealProposal, wallet: &str) -> Result<Cid, LotusError> {
+        let params = json!({
+            "Data": { "TransferType": "graphsync", "Root": link(&proposal.root) },
+            "Wallet": wallet,
+            "Miner": proposal.miner,
+            "EpochPrice": proposal.price_per_epoch.to_string(),
+            "MinBlocksDuration": proposal.duration_epochs,
+            "FastRetrieval": true,
+            "VerifiedDeal": false,
+        });
+
+        let proposal_cid: Link = self.call("ClientStartDeal", json!([params])).await?;
+        parse_link(&proposal_cid)
+    }
+
+    pub async fn client_get_deal_info(&self, proposal_cid: &Cid) -> Result<DealInfo, LotusError> {
+        self.call("ClientGetDealInfo", json!([link(proposal_cid)])).await
+    }
+
+    /// Name of a numeric storage market state, e.g. `StorageDealActive`
+    pub async fn client_get_deal_status(&self, state: u64) -> Result<String, LotusError> {
+        self.call("ClientGetDealStatus", json!([state])).await
+    }
+
+    /// Sign a zero-value message from the node's default wallet to `to` and
+    /// push it to the message pool. Returns the signed message CID.
+    pub async fn mpool_push_message(&self, to: &str, params: &[u8]) -> Result<Cid, LotusError> {
+        #[derive(Deserialize)]
+        struct SignedMessage {
+            #[serde(rename = "CID")]
+            cid: Link,
+        }
+
+        let message = json!({
+            "Version": 0,
+            "To": to,
+            "From": self.wallet_default_address().await?,
+            "Nonce": 0,
+            "Value": "0",
+            "GasLimit": 0,
+            "GasFeeCap": "0",
+            "GasPremium": "0",
+            "Method": 0,
+            "Params": base64::engine::general_purpose::STANDARD.encode(params),
+        });
+
+        let signed: SignedMessage = self.call("MpoolPushMessage", json!([message, null])).await?;
+        parse_link(&signed.cid)
+    }
+
+    /// CIDs of the messages sent to `to` from epoch `from_epoch` up to the
+    /// chain head
+    pub async fn state_list_messages(&self, to: &str, from_epoch: u64) -> Result<Vec<Cid>, LotusError> {
+        let links: Option<Vec<Link>> = self.call("StateListMessages", json!([{ "To": to }, [], from_epoch])).await?;
+        links.unwrap_or_default().iter().map(parse_link).collect()
+    }
+
+    /// Epoch of the tipset that executed the message `cid`, `None` while it
+    /// is not on chain
+    pub async fn state_search_msg(&self, cid: &Cid) -> Result<Option<u64>, LotusError> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct MsgLookup {
+            height: u64,
+        }
+
+        let lookup: Option<MsgLookup> = self.call("StateSearchMsg", json!([[], link(cid), -1, true])).await?;
+        Ok(lookup.map(|lookup| lookup.height))
+    }
+
+    pub async fn chain_get_message(&self, cid: &Cid) -> Result<ChainMessage, LotusError> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct Message {
+            from: String,
+            to: String,
+            params: Option<String>,
+        }
+
+        let message: Message = self.call("ChainGetMessage", json!([link(cid)])).await?;
+        let params = base64::engine::general_purpose::STANDARD
+            .decode(message.params.unwrap_or_default())
+            .map_err(|e| LotusError::Decode(e.to_string()))?;
+
+        Ok(ChainMessage { from: message.from, to: message.to, params })
+    }
+}
+
+#[async_trait]
+impl DealClient for LotusClient {
+    async fn query_ask(&self, miner: &str) -> crate::utils::Result<MinerAsk> {
+        let peer_id = self.state_miner_peer_id(miner).await?;
+        let ask = self.client_query_ask(&peer_id, miner).await?;
+        let price = ask.price.parse()
+            .map_err(|_| LotusError::Decode(format!("Ask price {:?} of {}", ask.price, miner)))?;
+
+        Ok(MinerAsk {
+            miner: miner.to_string(),
+            price_per_gib_epoch: price,
+            min_piece_size: ask.min_piece_size,
+            max_piece_size: ask.max_piece_size,
+        })
+    }
+
+    async fn start_deal(&self, proposal: &DealProposal) -> crate::utils::Result<String> {
+        let wallet = self.wallet_default_address().await?;
+        Ok(self.client_start_deal(proposal, &wallet).await?.to_string())
+    }
+
+    async fn deal_state(&self, proposal_cid: &str) -> crate::utils::Result<DealState> {
+        let proposal_cid = Cid::try_from(proposal_cid)
+            .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+        let info = self.client_get_deal_info(&proposal_cid).await?;
+        let name = self.client_get_deal_status(info.state).await?;
+
+        Ok(DealState::from_lotus(&name, &info.message))
+    }
+}
+
+fn link(cid: &Cid) -> Link {
+    Link { cid: cid.to_string() }
+}
+
+fn parse_link(link: &Link) -> Result<Cid, LotusError> {
+    Cid::try_from(link.cid.as_str()).map_err(|e| LotusError::Decode(e.to_string()))
+}
+
+// Unique path for a file exchanged with the node
+fn transfer_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("zkret-{}-{}", uuid::Uuid::new_v4(), name))
+}
@@ -0,0 +1,321 @@
+//! Storage deal lifecycle.
+//!
+//! Storing a record proposes a deal and returns immediately with a
+//! `PendingDeal`. The `DealManager` persists every deal in a JSON file and
+//! polls the ones that are not final yet with exponential backoff, so deal
+//! progress survives restarts and never blocks a protocol phase.
+
+use async_trait::async_trait;
+use cid::Cid;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Bytes in a GiB, the unit miner ask prices are quoted in
+const GIB: u128 = 1 << 30;
+
+/// Shortest deal duration Lotus accepts, about 180 days of 30s epochs
+pub const MIN_DEAL_DURATION: u64 = 518_400;
+
+/// Price and size terms a miner currently accepts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MinerAsk {
+    pub miner: String,
+    /// attoFIL per GiB per epoch
+    pub price_per_gib_epoch: u128,
+    pub min_piece_size: u64,
+    pub max_piece_size: u64,
+}
+
+/// Terms sent to the chosen miner
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DealProposal {
+    pub root: Cid,
+    pub miner: String,
+    /// attoFIL per epoch for the whole piece
+    pub price_per_epoch: u128,
+    pub duration_epochs: u64,
+}
+
+/// Deal status as reported by the node
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DealState {
+    Proposed,
+    /// Accepted but not yet active, with the node's state name
+    InProgress(String),
+    Active,
+    Failed(String),
+    Expired,
+}
+
+impl DealState {
+    /// Map a Lotus storage deal state name such as `StorageDealActive`
+    pub fn from_lotus(name: &str, message: &str) -> Self {
+        match name {
+            "StorageDealProposalNotFound" | "StorageDealUnknown" => DealState::Proposed,
+            "StorageDealActive" => DealState::Active,
+            "StorageDealExpired" | "StorageDealSlashed" => DealState::Expired,
+            "StorageDealError" | "StorageDealFailing" | "StorageDealProposalRejected"
+            | "StorageDealRejecting" => {
+                let reason = if message.is_empty() { name } else { message };
+                DealState::Failed(reason.to_string())
+            }
+            other => DealState::InProgress(other.to_string()),
+        }
+    }
+
+    /// Whether the deal will not change state any more
+    pub fn is_final(&self) -> bool {
+        matches!(self, DealState::Active | DealState::Failed(_) | DealState::Expired)
+    }
+}
+
+impl std::fmt::Display for DealState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DealState::Proposed => write!(f, "proposed"),
+            DealState::InProgress(state) => write!(f, "in progress ({})", state),
+            DealState::Active => write!(f, "active"),
+            DealState::Failed(reason) => write!(f, "failed: {}", reason),
+            DealState::Expired => write!(f, "expired"),
+        }
+    }
+}
+
+/// Node operations the deal manager needs
+#[async_trait]
+pub trait DealClient: Send + Sync {
+    async fn query_ask(&self, miner: &str) -> crate::utils::Result<MinerAsk>;
+
+    /// Propose a deal and return the proposal CID
+    async fn start_deal(&self, proposal: &DealProposal) -> crate::utils::Result<String>;
+
+    async fn deal_state(&self, proposal_cid: &str) -> crate::utils::Result<DealState>;
+}
+
+#[derive(Debug, Clone)]
+pub struct DealConfig {
+    /// Miners to ask, in order of preference when prices tie
+    pub miners: Vec<String>,
+    /// Highest accepted price in attoFIL per GiB per epoch, `None` for no limit
+    pub max_price_per_gib_epoch: Option<u128>,
+    pub duration_epochs: u64,
+    /// First polling delay in seconds, doubled after every poll
+    pub poll_initial_secs: u64,
+    /// Upper bound for the polling delay in seconds
+    pub poll_max_secs: u64,
+}
+
+impl Default for DealConfig {
+    fn default() -> Self {
+        Self {
+            miners: Vec::new(),
+            max_price_per_gib_epoch: None,
+            duration_epochs: MIN_DEAL_DURATION,
+            poll_initial_secs: 30,
+            poll_max_secs: 3600,
+        }
+    }
+}
+
+/// A proposed deal and its polling state, as persisted in the deals file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingDeal {
+    pub record_cid: String,
+    pub proposal_cid: String,
+    pub miner: String,
+    pub price_per_epoch: u128,
+    pub duration_epochs: u64,
+    pub state: DealState,
+    /// Number of status polls so far
+    pub attempts: u32,
+    /// Unix time of the next poll
+    pub next_poll_at: u64,
+    pub submitted_at: u64,
+    pub last_error: Option<String>,
+}
+
+pub struct DealManager {
+    path: PathBuf,
+    config: DealConfig,
+    deals: Vec<PendingDeal>,
+}
+
+impl DealManager {
+    /// Load the deals persisted at `path`, if any
+    pub fn open(path: &Path, config: DealConfig) -> crate::utils::Result<Self> {
+        let deals = match std::fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(crate::utils::Error::FileError(e.to_string())),
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            config,
+            deals,
+        })
+    }
+
+    pub fn config(&self) -> &DealConfig {
+        &self.config
+    }
+
+    pub fn deals(&self) -> &[PendingDeal] {
+        &self.deals
+    }
+
+    /// Most recent deal for the record stored under `record_cid`
+    pub fn deal_for(&self, record_cid: &Cid) -> Option<&PendingDeal> {
+        let record_cid = record_cid.to_string();
+        self.deals.iter().rev().find(|deal| deal.record_cid == record_cid)
+    }
+
+    /// Pick a miner, propose a deal for `root` and persist it without waiting
+    /// for the miner to accept
+    pub async fn submit<C>(&mut self, client: &C, root: &Cid, piece_size: u64) -> crate::utils::Result<PendingDeal>
+    where
+        C: DealClient + ?Sized,
+    {
+        let mut asks = Vec::new();
+        for miner in &self.config.miners {
+            // An unreachable miner is simply not a candidate
+            if let Ok(ask) = client.query_ask(miner).await {
+                asks.push(ask);
+            }
+        }
+
+        let ask = select_miner(&asks, piece_size, &self.config)
+            .ok_or_else(|| crate::utils::Error::StorageError(format!(
+                "No configured miner accepts a {} byte piece within the price limit", piece_size
+            )))?;
+
+        let proposal = DealProposal {
+            root: *root,
+            miner: ask.miner.clone(),
+            price_per_epoch: piece_price_per_epoch(ask.price_per_gib_epoch, piece_size.max(ask.min_piece_size)),
+            duration_epochs: self.config.duration_epochs,
+        };
+        let proposal_cid = client.start_deal(&proposal).await?;
+
+        let now = unix_now();
+        let deal = PendingDeal {
+            record_cid: root.to_string(),
+            proposal_cid,
+            miner: proposal.miner,
+            price_per_epoch: proposal.price_per_epoch,
+            duration_epochs: proposal.duration_epochs,
+            state: DealState::Proposed,
+            attempts: 0,
+            next_poll_at: now + backoff_delay(0, &self.config),
+            submitted_at: now,
+            last_error: None,
+        };
+
+        self.deals.push(deal.clone());
+        self.save()?;
+        Ok(deal)
+    }
+
+    /// Persist a deal for `root` that could not be proposed, so the record
+    /// shows up as not stored instead of being silently dropped
+    pub fn record_failure(&mut self, root: &Cid, reason: &str) -> crate::utils::Result<PendingDeal> {
+        let now = unix_now();
+        let deal = PendingDeal {
+            record_cid: root.to_string(),
+            proposal_cid: String::new(),
+            miner: String::new(),
+            price_per_epoch: 0,
+            duration_epochs: self.config.duration_epochs,
+            state: DealState::Failed(reason.to_string()),
+            attempts: 0,
+            next_poll_at: now,
+            submitted_at: now,
+            last_error: Some(reason.to_string()),
+        };
+
+        self.deals.push(deal.clone());
+        self.save()?;
+        Ok(deal)
+    }
+
+    /// Poll every non-final deal that is due at `now` and persist the result.
+    /// Returns the number of deals whose state changed.
+    pub async fn poll<C>(&mut self, client: &C, now: u64) -> crate::utils::Result<usize>
+    where
+        C: DealClient + ?Sized,
+    {
+        let mut changed = 0;
+
+        for deal in self.deals.iter_mut() {
+            if deal.state.is_final() || deal.next_poll_at > now {
+                continue;
+            }
+
+            match client.deal_state(&deal.proposal_cid).await {
+                Ok(state) => {
+                    if state != deal.state {
+                        changed += 1;
+                    }
+                    deal.state = state;
+                    deal.last_error = None;
+                }
+                Err(e) => deal.last_error = Some(e.to_string()),
+            }
+
+            deal.attempts += 1;
+            deal.next_poll_at = now + backoff_delay(deal.attempts, &self.config);
+        }
+
+        self.save()?;
+        Ok(changed)
+    }
+
+    fn save(&self) -> crate::utils::Result<()> {
+        let data = serde_json::to_vec_pretty(&self.deals)
+            .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+
+        let tmp_path = self.path.with_extension("tmp");
+        std::fs::write(&tmp_path, data)
+            .map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
+        std::fs::rename(&tmp_path, &self.path)
+            .map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
+
+        Ok(())
+    }
+}
+
+/// Ask that stores a piece of `piece_size` bytes for the least attoFIL per
+/// epoch within the price limit, preferring earlier miners on ties. Pieces
+/// below a miner's minimum are padded up to it and paid for at that size, so
+/// only the maximum excludes a miner.
+pub fn select_miner<'a>(asks: &'a [MinerAsk], piece_size: u64, config: &DealConfig) -> Option<&'a MinerAsk> {
+    asks.iter()
+        .filter(|ask| piece_size <= ask.max_piece_size)
+        .filter(|ask| config.max_price_per_gib_epoch.is_none_or(|max| ask.price_per_gib_epoch <= max))
+        .min_by_key(|ask| ask.price_per_gib_epoch * piece_size.max(ask.min_piece_size) as u128)
+}
+
+/// Seconds to wait before the poll after `attempts` earlier ones
+pub fn backoff_delay(attempts: u32, config: &DealConfig) -> u64 {
+    let factor = 1u64.checked_shl(attempts).unwrap_or(u64::MAX);
+    config.poll_initial_secs.saturating_mul(factor).min(config.poll_max_secs)
+}
+
+/// Padded piece size of `payload_len` bytes: fr32 padding adds one bit in
+/// 255, and pieces are a power of two of at least 128 bytes
+pub fn padded_piece_size(payload_len: u64) -> u64 {
+    let padded = payload_len + payload_len.div_ceil(127);
+    padded.max(128).next_power_of_two()
+}
+
+fn piece_price_per_epoch(price_per_gib_epoch: u128, piece_size: u64) -> u128 {
+    (price_per_gib_epoch * piece_size as u128).div_ceil(GIB)
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
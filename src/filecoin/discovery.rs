@@ -0,0 +1,137 @@
+//! Discovery of game records through on-chain announcements.
+//!
+//! Every game has a well-known address derived from its identifier. Whoever
+//! stores a record sends a zero-value message to that address whose params
+//! carry an `Announcement` (record type and CID). Syncing a game lists the
+//! messages sent to the address and fetches every record not yet indexed.
+//!
+//! Game configs are also announced to a single discovery address shared by
+//! all games, which is what listing games syncs.
+//!
+//! The address is a secp256k1-style (`f1`) address over a hash, so nobody holds
+//! its key and funds sent to it are burnt; announcements carry no value.
+
+use super::RecordType;
+use blake2::digest::{Update, VariableOutput};
+use blake2::Blake2bVar;
+use cid::Cid;
+use serde::{Deserialize, Serialize};
+
+/// Current announcement format
+pub const ANNOUNCEMENT_VERSION: u8 = 1;
+
+const GAME_ADDRESS_DOMAIN: &[u8] = b"zkret-santa/game-address/v1";
+const DISCOVERY_ADDRESS_DOMAIN: &[u8] = b"zkret-santa/discovery-address/v1";
+
+// Filecoin address protocol of secp256k1 (hash-based) addresses
+const SECP256K1_PROTOCOL: u8 = 1;
+
+/// Filecoin network the game address is rendered for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+}
+
+impl Network {
+    fn prefix(self) -> char {
+        match self {
+            Network::Mainnet => 'f',
+            Network::Testnet => 't',
+        }
+    }
+}
+
+/// Payload of an announcement message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Announcement {
+    pub version: u8,
+    pub record_type: RecordType,
+    pub cid: String,
+}
+
+impl Announcement {
+    pub fn new(record_type: RecordType, cid: &Cid) -> Self {
+        Self {
+            version: ANNOUNCEMENT_VERSION,
+            record_type,
+            cid: cid.to_string(),
+        }
+    }
+
+    /// Encode as message params
+    pub fn to_params(&self) -> crate::utils::Result<Vec<u8>> {
+        serde_json::to_vec(self)
+            .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))
+    }
+
+    /// Decode message params, returning `None` for messages that are not
+    /// announcements of a supported version
+    pub fn from_params(params: &[u8]) -> Option<Self> {
+        serde_json::from_slice::<Self>(params)
+            .ok()
+            .filter(|announcement| announcement.version == ANNOUNCEMENT_VERSION)
+    }
+
+    pub fn content_cid(&self) -> crate::utils::Result<Cid> {
+        Cid::try_from(self.cid.as_str())
+            .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))
+    }
+}
+
+/// Well-known address that announcements for `game` are sent to
+pub fn game_address(game: &[u8], network: Network) -> String {
+    let mut seed = GAME_ADDRESS_DOMAIN.to_vec();
+    seed.extend_from_slice(game);
+    hash_address(&seed, network)
+}
+
+/// Well-known address that the config of every game is announced to
+pub fn discovery_address(network: Network) -> String {
+    hash_address(DISCOVERY_ADDRESS_DOMAIN, network)
+}
+
+fn hash_address(seed: &[u8], network: Network) -> String {
+    let payload = blake2b(seed, 20);
+
+    let mut checksum_input = vec![SECP256K1_PROTOCOL];
+    checksum_input.extend_from_slice(&payload);
+    let checksum = blake2b(&checksum_input, 4);
+
+    let mut encoded = payload;
+    encoded.extend_from_slice(&checksum);
+
+    format!("{}{}{}", network.prefix(), SECP256K1_PROTOCOL, base32_lower(&encoded))
+}
+
+fn blake2b(data: &[u8], len: usize) -> Vec<u8> {
+    let mut hasher = Blake2bVar::new(len).expect("valid blake2b output length");
+    hasher.update(data);
+
+    let mut out = vec![0u8; len];
+    hasher.finalize_variable(&mut out).expect("output buffer matches the requested length");
+    out
+}
+
+/// RFC 4648 base32, lowercase and unpadded, as used by Filecoin addresses
+fn base32_lower(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+    let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+    let mut buffer = 0u16;
+    let mut bits = 0;
+
+    for byte in data {
+        buffer = (buffer << 8) | u16::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    out
+}
@@ -0,0 +1,201 @@
+use super::{RecordType, StorageRecord};
+use fs2::FileExt;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// Append-only index of `StorageRecord`s, one JSON object per line.
+///
+/// The file is re-read on every query, so records written by other processes
+/// (or in an earlier run) are always visible. Writers take an exclusive lock on
+/// a sibling `.lock` file and readers a shared one. The ids of announcement
+/// messages a sync has finished with are kept in a sibling `.processed` file.
+#[derive(Debug, Clone)]
+pub struct RecordIndex {
+    path: PathBuf,
+}
+
+impl RecordIndex {
+    pub fn open(path: &Path) -> crate::utils::Result<Self> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| crate::utils::Error::StorageError(e.to_string()))?;
+        }
+
+        Ok(Self { path: path.to_path_buf() })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Every record in the index, in insertion order
+    pub fn records(&self) -> crate::utils::Result<Vec<StorageRecord>> {
+        let _lock = self.lock(false)?;
+        self.read()
+    }
+
+    pub fn list(&self, record_type: RecordType) -> crate::utils::Result<Vec<StorageRecord>> {
+        Ok(self.records()?.into_iter()
+            .filter(|record| record.record_type == record_type)
+            .collect())
+    }
+
+    /// Append `record` unless a record with the same CID and type is already
+    /// indexed, and return whichever record ends up in the index
+    pub fn insert(&self, record: StorageRecord) -> crate::utils::Result<StorageRecord> {
+        let _lock = self.lock(true)?;
+
+        if let Some(existing) = self.read()?.into_iter().find(|existing| same_content(existing, &record)) {
+            return Ok(existing);
+        }
+
+        let mut index_file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| crate::utils::Error::StorageError(e.to_string()))?;
+        index_file.write_all(&encode_line(&record)?)
+            .and_then(|_| index_file.sync_data())
+            .map_err(|e| crate::utils::Error::StorageError(e.to_string()))?;
+
+        Ok(record)
+    }
+
+    /// Replace the whole index with `records`, dropping duplicates
+    pub fn rebuild<I>(&self, records: I) -> crate::utils::Result<usize>
+    where
+        I: IntoIterator<Item = StorageRecord>,
+    {
+        let _lock = self.lock(true)?;
+
+        let mut unique: Vec<StorageRecord> = Vec::new();
+        for record in records {
+            if !unique.iter().any(|existing| same_content(existing, &record)) {
+                unique.push(record);
+            }
+        }
+
+        self.write(&unique)?;
+
+        // Announcements of records that did not make it into the new index
+        // must be synced again
+        match std::fs::remove_file(self.processed_path()) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(crate::utils::Error::StorageError(e.to_string()));
+            }
+            _ => {}
+        }
+
+        Ok(unique.len())
+    }
+
+    /// Ids of the announcement messages a sync has finished with
+    pub fn processed_messages(&self) -> crate::utils::Result<HashSet<String>> {
+        let _lock = self.lock(false)?;
+
+        match std::fs::read_to_string(self.processed_path()) {
+            Ok(contents) => Ok(contents.lines().map(str::to_string).collect()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashSet::new()),
+            Err(e) => Err(crate::utils::Error::StorageError(e.to_string())),
+        }
+    }
+
+    /// Remember that a sync has finished with the message `message_id`
+    pub fn mark_processed(&self, message_id: &str) -> crate::utils::Result<()> {
+        let _lock = self.lock(true)?;
+
+        let mut processed_file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.processed_path())
+            .map_err(|e| crate::utils::Error::StorageError(e.to_string()))?;
+        writeln!(processed_file, "{}", message_id)
+            .and_then(|_| processed_file.sync_data())
+            .map_err(|e| crate::utils::Error::StorageError(e.to_string()))
+    }
+
+    /// Set the announcement epoch of the indexed record with the content of
+    /// `record`
+    pub fn set_epoch(&self, record: &StorageRecord, epoch: u64) -> crate::utils::Result<()> {
+        let _lock = self.lock(true)?;
+
+        let mut records = self.read()?;
+        for existing in records.iter_mut().filter(|existing| same_content(existing, record)) {
+            existing.epoch = Some(epoch);
+        }
+
+        self.write(&records)
+    }
+
+    fn write(&self, records: &[StorageRecord]) -> crate::utils::Result<()> {
+        let mut contents = Vec::new();
+        for record in records {
+            contents.extend(encode_line(record)?);
+        }
+
+        let tmp_path = self.path.with_extension("rebuild");
+        std::fs::write(&tmp_path, contents)
+            .map_err(|e| crate::utils::Error::StorageError(e.to_string()))?;
+        std::fs::rename(&tmp_path, &self.path)
+            .map_err(|e| crate::utils::Error::StorageError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    fn processed_path(&self) -> PathBuf {
+        self.path.with_extension("processed")
+    }
+
+    fn read(&self) -> crate::utils::Result<Vec<StorageRecord>> {
+        let index_file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(crate::utils::Error::StorageError(e.to_string())),
+        };
+
+        let mut records = Vec::new();
+        for line in BufReader::new(index_file).lines() {
+            let line = line.map_err(|e| crate::utils::Error::StorageError(e.to_string()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: StorageRecord = serde_json::from_str(&line)
+                .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+            records.push(record);
+        }
+
+        Ok(records)
+    }
+
+    fn lock(&self, exclusive: bool) -> crate::utils::Result<File> {
+        let lock_file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.path.with_extension("lock"))
+            .map_err(|e| crate::utils::Error::StorageError(e.to_string()))?;
+
+        let locked = if exclusive {
+            lock_file.lock_exclusive()
+        } else {
+            lock_file.lock_shared()
+        };
+        locked.map_err(|e| crate::utils::Error::StorageError(e.to_string()))?;
+
+        // The lock is released when the returned file is dropped
+        Ok(lock_file)
+    }
+}
+
+fn same_content(a: &StorageRecord, b: &StorageRecord) -> bool {
+    a.content_cid == b.content_cid && a.record_type == b.record_type
+}
+
+fn encode_line(record: &StorageRecord) -> crate::utils::Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(record)
+        .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+    line.push(b'\n');
+    Ok(line)
+}
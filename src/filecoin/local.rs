@@ -0,0 +1,110 @@
+use super::backend::{compute_cid, verify_cid, StorageBackend};
+use super::index::RecordIndex;
+use super::{RecordType, StorageRecord};
+use crate::utils::clock::{system_clock, Clock};
+use async_trait::async_trait;
+use cid::Cid;
+use std::path::{Path, PathBuf};
+
+const BLOCKS_DIR: &str = "blocks";
+
+/// Name of the record index inside a local storage directory
+pub const INDEX_FILE: &str = "index.jsonl";
+
+/// Content-addressed backend in a local directory.
+///
+/// Each record is written to `blocks/<cid>` and listed in a `RecordIndex` at
+/// `index.jsonl`. The index is locked on every access, so several `zkretctl`
+/// processes can share a directory. Records are stamped with the time they
+/// were stored, so the directory is only as trustworthy as its writers.
+#[derive(Clone)]
+pub struct LocalStorage {
+    dir: PathBuf,
+    index: RecordIndex,
+    clock: Clock,
+}
+
+impl LocalStorage {
+    /// Open `dir`, creating the layout if it does not exist yet
+    pub fn open(dir: &Path) -> crate::utils::Result<Self> {
+        std::fs::create_dir_all(dir.join(BLOCKS_DIR))
+            .map_err(|e| crate::utils::Error::StorageError(e.to_string()))?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            index: RecordIndex::open(&dir.join(INDEX_FILE))?,
+            clock: system_clock(),
+        })
+    }
+
+    /// Replace the system clock, e.g. to simulate a game in tests
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn index(&self) -> &RecordIndex {
+        &self.index
+    }
+
+    fn block_path(&self, cid: &Cid) -> PathBuf {
+        self.dir.join(BLOCKS_DIR).join(cid.to_string())
+    }
+
+    fn write_block(&self, cid: &Cid, data: &[u8]) -> crate::utils::Result<()> {
+        let block_path = self.block_path(cid);
+        if block_path.exists() {
+            return Ok(());
+        }
+
+        // Write to a temporary name first so readers never see a partial block
+        let tmp_path = block_path.with_extension(format!("tmp-{}", std::process::id()));
+        std::fs::write(&tmp_path, data)
+            .map_err(|e| crate::utils::Error::StorageError(e.to_string()))?;
+        std::fs::rename(&tmp_path, &block_path)
+            .map_err(|e| crate::utils::Error::StorageError(e.to_string()))?;
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for LocalStorage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("LocalStorage")
+            .field("dir", &self.dir)
+            .field("index", &self.index)
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LocalStorage {
+    async fn put(&mut self, data: Vec<u8>, record_type: RecordType) -> crate::utils::Result<StorageRecord> {
+        let cid = compute_cid(&data);
+        self.write_block(&cid, &data)?;
+
+        // Storing the same content twice yields the existing record
+        self.index.insert(StorageRecord {
+            id: uuid::Uuid::new_v4().to_string(),
+            content_cid: cid,
+            timestamp: (self.clock)(),
+            record_type,
+            epoch: None,
+        })
+    }
+
+    async fn get(&self, cid: &Cid) -> crate::utils::Result<Vec<u8>> {
+        let data = std::fs::read(self.block_path(cid))
+            .map_err(|e| crate::utils::Error::StorageError(format!("Block {}: {}", cid, e)))?;
+
+        verify_cid(cid, &data)?;
+        Ok(data)
+    }
+
+    async fn list(&self, record_type: RecordType) -> crate::utils::Result<Vec<StorageRecord>> {
+        self.index.list(record_type)
+    }
+}
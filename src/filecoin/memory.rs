@@ -0,0 +1,73 @@
+use super::backend::{compute_cid, StorageBackend};
+use super::{RecordType, StorageRecord};
+use crate::utils::clock::{system_clock, Clock};
+use async_trait::async_trait;
+use cid::Cid;
+use std::collections::HashMap;
+
+/// Backend keeping every record in memory, for tests and single-process games.
+/// Records are stamped with the time they were stored.
+pub struct MemoryStorage {
+    blocks: HashMap<Cid, Vec<u8>>,
+    records: Vec<StorageRecord>,
+    clock: Clock,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self {
+            blocks: HashMap::new(),
+            records: Vec::new(),
+            clock: system_clock(),
+        }
+    }
+
+    /// Replace the system clock, e.g. to simulate a game in tests
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+}
+
+impl Default for MemoryStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for MemoryStorage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("MemoryStorage")
+            .field("records", &self.records)
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MemoryStorage {
+    async fn put(&mut self, data: Vec<u8>, record_type: RecordType) -> crate::utils::Result<StorageRecord> {
+        let record = StorageRecord {
+            id: uuid::Uuid::new_v4().to_string(),
+            content_cid: compute_cid(&data),
+            timestamp: (self.clock)(),
+            record_type,
+            epoch: None,
+        };
+
+        self.blocks.insert(record.content_cid, data);
+        self.records.push(record.clone());
+        Ok(record)
+    }
+
+    async fn get(&self, cid: &Cid) -> crate::utils::Result<Vec<u8>> {
+        self.blocks.get(cid)
+            .cloned()
+            .ok_or_else(|| crate::utils::Error::StorageError(format!("Block {} not found", cid)))
+    }
+
+    async fn list(&self, record_type: RecordType) -> crate::utils::Result<Vec<StorageRecord>> {
+        Ok(self.records.iter()
+            .filter(|record| record.record_type == record_type)
+            .cloned()
+            .collect())
+    }
+}
@@ -0,0 +1,392 @@
+//! In-process mock of the Lotus RPC surface used by `LotusClient`.
+//!
+//! The mock keeps imported blocks, deals and pushed messages in memory and
+//! serves them over HTTP, so `FilecoinStorage` can be exercised end to end in
+//! tests or played against locally with the `mock-lotus` binary. Deals move
+//! through acceptance and sealing on a timer and can be configured to fail.
+//! Pushed messages are included right away, at the epoch of the wall clock.
+
+use super::backend::compute_cid;
+use super::car::{read_car, Link};
+use crate::protocol::game::{CALIBRATION_GENESIS, EPOCH_DURATION_SECS};
+use crate::utils::clock::unix_now;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use cid::Cid;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Storage market deal states reported by the mock, numbered as in Lotus
+const DEAL_CHECK_FOR_ACCEPTANCE: u64 = 13;
+const DEAL_SEALING: u64 = 5;
+const DEAL_ACTIVE: u64 = 7;
+const DEAL_ERROR: u64 = 26;
+
+#[derive(Debug, Clone)]
+pub struct MockMiner {
+    pub address: String,
+    pub peer_id: String,
+    /// attoFIL per GiB per epoch
+    pub price: u128,
+    pub min_piece_size: u64,
+    pub max_piece_size: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct MockConfig {
+    /// Name returned by `StateNetworkName`
+    pub network: String,
+    /// Unix time of epoch 0 of the mock chain
+    pub genesis_timestamp: u64,
+    pub wallet: String,
+    pub miners: Vec<MockMiner>,
+    /// Bearer token required on every request, if any
+    pub auth_token: Option<String>,
+    /// Delay before every response, to exercise client timeouts
+    pub response_delay: Duration,
+    /// Time a deal waits for acceptance before sealing starts
+    pub accept_delay: Duration,
+    /// Time a deal spends sealing before it becomes active
+    pub seal_delay: Duration,
+    /// Fail every deal with this message once it would have been accepted
+    pub deal_failure: Option<String>,
+    /// Offer no retrievals, as if no miner held the data
+    pub fail_retrievals: bool,
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        Self {
+            network: "calibrationnet".to_string(),
+            genesis_timestamp: CALIBRATION_GENESIS,
+            wallet: "t1mockwallet".to_string(),
+            miners: vec![MockMiner {
+                address: "t01000".to_string(),
+                peer_id: "12D3KooWMockMiner".to_string(),
+                price: 0,
+                min_piece_size: 256,
+                max_piece_size: 32 << 30,
+            }],
+            auth_token: None,
+            response_delay: Duration::ZERO,
+            accept_delay: Duration::ZERO,
+            seal_delay: Duration::ZERO,
+            deal_failure: None,
+            fail_retrievals: false,
+        }
+    }
+}
+
+struct MockDeal {
+    started: Instant,
+}
+
+struct MockMessage {
+    cid: Cid,
+    body: Value,
+    height: u64,
+}
+
+#[derive(Default)]
+struct MockState {
+    blocks: HashMap<Cid, Vec<u8>>,
+    deals: HashMap<Cid, MockDeal>,
+    messages: Vec<MockMessage>,
+    next_id: u64,
+    // Requests served, by method
+    calls: HashMap<String, usize>,
+}
+
+struct Shared {
+    config: Mutex<MockConfig>,
+    state: Mutex<MockState>,
+}
+
+/// A running mock node, stopped when dropped
+pub struct MockLotus {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    server: tokio::task::JoinHandle<()>,
+}
+
+impl MockLotus {
+    /// Start a mock on a free local port
+    pub async fn start(config: MockConfig) -> crate::utils::Result<Self> {
+        Self::bind("127.0.0.1:0".parse().unwrap(), config).await
+    }
+
+    pub async fn bind(addr: SocketAddr, config: MockConfig) -> crate::utils::Result<Self> {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .map_err(|e| crate::utils::Error::StorageError(e.to_string()))?;
+        let addr = listener.local_addr()
+            .map_err(|e| crate::utils::Error::StorageError(e.to_string()))?;
+
+        let shared = Arc::new(Shared {
+            config: Mutex::new(config),
+            state: Mutex::new(MockState::default()),
+        });
+        let app = Router::new()
+            .route("/rpc/v0", post(handle_rpc))
+            .route("/rpc/v1", post(handle_rpc))
+            .with_state(shared.clone());
+
+        let server = tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+
+        Ok(Self { addr, shared, server })
+    }
+
+    /// RPC endpoint to pass to `LotusClient::new`
+    pub fn url(&self) -> String {
+        format!("http://{}/rpc/v1", self.addr)
+    }
+
+    /// Change the behaviour of the running mock
+    pub fn configure(&self, update: impl FnOnce(&mut MockConfig)) {
+        update(&mut self.shared.config.lock().unwrap());
+    }
+
+    /// Number of messages pushed so far
+    pub fn message_count(&self) -> usize {
+        self.shared.state.lock().unwrap().messages.len()
+    }
+
+    /// Number of requests served for the RPC `method`, without the
+    /// `Filecoin.` prefix
+    pub fn call_count(&self, method: &str) -> usize {
+        self.shared.state.lock().unwrap().calls.get(method).copied().unwrap_or_default()
+    }
+
+    /// Wait until the server stops, which only happens on error
+    pub async fn run(mut self) {
+        let _ = (&mut self.server).await;
+    }
+}
+
+impl Drop for MockLotus {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+async fn handle_rpc(State(shared): State<Arc<Shared>>, headers: HeaderMap, Json(request): Json<Value>) -> Response {
+    let config = shared.config.lock().unwrap().clone();
+
+    if let Some(token) = &config.auth_token {
+        let expected = format!("Bearer {}", token);
+        let authorized = headers.get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value == expected);
+        if !authorized {
+            return (StatusCode::UNAUTHORIZED, "missing or invalid bearer token").into_response();
+        }
+    }
+
+    tokio::time::sleep(config.response_delay).await;
+
+    let id = request["id"].clone();
+    let method = request["method"].as_str().unwrap_or_default().trim_start_matches("Filecoin.");
+    let params = request["params"].as_array().cloned().unwrap_or_default();
+
+    let body = match dispatch(&shared, &config, method, &params) {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } }),
+    };
+    Json(body).into_response()
+}
+
+type RpcResult = Result<Value, (i64, String)>;
+
+fn dispatch(shared: &Shared, config: &MockConfig, method: &str, params: &[Value]) -> RpcResult {
+    let mut state = shared.state.lock().unwrap();
+    *state.calls.entry(method.to_string()).or_default() += 1;
+
+    match method {
+        "ChainHead" => Ok(json!({ "Cids": [], "Height": current_epoch(config) })),
+        "StateNetworkName" => Ok(json!(config.network)),
+        "WalletDefaultAddress" => Ok(json!(config.wallet)),
+        "ClientImport" => client_import(&mut state, param(params, 0)?),
+        "StateMinerInfo" => {
+            let miner = find_miner(config, param(params, 0)?.as_str().unwrap_or_default())?;
+            Ok(json!({ "PeerId": miner.peer_id, "Owner": miner.address }))
+        }
+        "ClientQueryAsk" => {
+            let miner = find_miner(config, param(params, 1)?.as_str().unwrap_or_default())?;
+            Ok(json!({
+                "Price": miner.price.to_string(),
+                "VerifiedPrice": "0",
+                "MinPieceSize": miner.min_piece_size,
+                "MaxPieceSize": miner.max_piece_size,
+                "Miner": miner.address,
+            }))
+        }
+        "ClientStartDeal" => client_start_deal(&mut state, config, param(params, 0)?),
+        "ClientGetDealInfo" => {
+            let proposal_cid = parse_link(param(params, 0)?)?;
+            let deal = state.deals.get(&proposal_cid)
+                .ok_or_else(|| (1, format!("deal {} not found", proposal_cid)))?;
+            let (deal_state, message) = deal_state(config, deal);
+
+            Ok(json!({ "ProposalCid": link(&proposal_cid), "State": deal_state, "Message": message }))
+        }
+        "ClientGetDealStatus" => {
+            let name = match param(params, 0)?.as_u64() {
+                Some(DEAL_CHECK_FOR_ACCEPTANCE) => "StorageDealCheckForAcceptance",
+                Some(DEAL_SEALING) => "StorageDealSealing",
+                Some(DEAL_ACTIVE) => "StorageDealActive",
+                Some(DEAL_ERROR) => "StorageDealError",
+                _ => "StorageDealUnknown",
+            };
+            Ok(json!(name))
+        }
+        "ClientFindData" => {
+            let root = parse_link(param(params, 0)?)?;
+            let block = match state.blocks.get(&root) {
+                Some(block) if !config.fail_retrievals => block,
+                _ => return Ok(json!([])),
+            };
+            let miner = config.miners.first().ok_or_else(|| (1, "no miners".to_string()))?;
+
+            Ok(json!([{
+                "Err": "",
+                "Root": link(&root),
+                "Piece": null,
+                "Size": block.len(),
+                "MinPrice": "0",
+                "UnsealPrice": "0",
+                "PaymentInterval": 1 << 20,
+                "PaymentIntervalIncrease": 1 << 20,
+                "Miner": miner.address,
+                "MinerPeer": { "Address": miner.address, "ID": miner.peer_id, "PieceCID": null },
+            }]))
+        }
+        "ClientRetrieve" => {
+            let root = parse_link(&param(params, 0)?["Root"])?;
+            let path = param(params, 1)?["Path"].as_str().unwrap_or_default().to_string();
+            let block = state.blocks.get(&root)
+                .filter(|_| !config.fail_retrievals)
+                .ok_or_else(|| (1, format!("no retrieval offer for {}", root)))?;
+
+            std::fs::write(&path, block).map_err(|e| (1, e.to_string()))?;
+            Ok(Value::Null)
+        }
+        "MpoolPushMessage" => {
+            let mut message = param(params, 0)?.clone();
+            message["Nonce"] = json!(state.messages.len());
+            let cid = compute_cid(message.to_string().as_bytes());
+
+            state.messages.push(MockMessage { cid, body: message.clone(), height: current_epoch(config) });
+            Ok(json!({ "Message": message, "Signature": { "Type": 1, "Data": "" }, "CID": link(&cid) }))
+        }
+        "StateListMessages" => {
+            let to = param(params, 0)?["To"].as_str().unwrap_or_default().to_string();
+            let from_epoch = param(params, 2)?.as_u64().unwrap_or_default();
+            let cids: Vec<Link> = state.messages.iter()
+                .filter(|message| message.body["To"] == to && message.height >= from_epoch)
+                .map(|message| link(&message.cid))
+                .collect();
+            Ok(json!(cids))
+        }
+        "StateSearchMsg" => {
+            let cid = parse_link(param(params, 1)?)?;
+            let lookup = state.messages.iter()
+                .find(|message| message.cid == cid)
+                .map(|message| json!({
+                    "Message": link(&message.cid),
+                    "Receipt": { "ExitCode": 0, "Return": null, "GasUsed": 0 },
+                    "ReturnDec": null,
+                    "TipSet": [],
+                    "Height": message.height,
+                }));
+            Ok(lookup.unwrap_or(Value::Null))
+        }
+        "ChainGetMessage" => {
+            let cid = parse_link(param(params, 0)?)?;
+            state.messages.iter()
+                .find(|message| message.cid == cid)
+                .map(|message| message.body.clone())
+                .ok_or_else(|| (1, format!("message {} not found", cid)))
+        }
+        _ => Err((-32601, format!("method 'Filecoin.{}' not found", method))),
+    }
+}
+
+fn client_import(state: &mut MockState, file_ref: &Value) -> RpcResult {
+    let path = file_ref["Path"].as_str().unwrap_or_default();
+    let data = std::fs::read(path).map_err(|e| (1, e.to_string()))?;
+
+    let root = if file_ref["IsCAR"].as_bool().unwrap_or(false) {
+        let car = read_car(data.as_slice()).map_err(|e| (1, e.to_string()))?;
+        state.blocks.extend(car.blocks);
+        car.root
+    } else {
+        let cid = compute_cid(&data);
+        state.blocks.insert(cid, data);
+        cid
+    };
+
+    state.next_id += 1;
+    Ok(json!({ "Root": link(&root), "ImportID": state.next_id }))
+}
+
+fn client_start_deal(state: &mut MockState, config: &MockConfig, params: &Value) -> RpcResult {
+    let root = parse_link(&params["Data"]["Root"])?;
+    if !state.blocks.contains_key(&root) {
+        return Err((1, format!("{} has not been imported", root)));
+    }
+    find_miner(config, params["Miner"].as_str().unwrap_or_default())?;
+
+    state.next_id += 1;
+    let proposal_cid = compute_cid(format!("{}/{}", params, state.next_id).as_bytes());
+    state.deals.insert(proposal_cid, MockDeal { started: Instant::now() });
+
+    Ok(json!(link(&proposal_cid)))
+}
+
+fn deal_state(config: &MockConfig, deal: &MockDeal) -> (u64, String) {
+    let elapsed = deal.started.elapsed();
+
+    if elapsed < config.accept_delay {
+        return (DEAL_CHECK_FOR_ACCEPTANCE, String::new());
+    }
+    if let Some(failure) = &config.deal_failure {
+        return (DEAL_ERROR, failure.clone());
+    }
+    if elapsed < config.accept_delay + config.seal_delay {
+        (DEAL_SEALING, String::new())
+    } else {
+        (DEAL_ACTIVE, String::new())
+    }
+}
+
+fn current_epoch(config: &MockConfig) -> u64 {
+    unix_now().saturating_sub(config.genesis_timestamp) / EPOCH_DURATION_SECS
+}
+
+fn find_miner<'a>(config: &'a MockConfig, address: &str) -> Result<&'a MockMiner, (i64, String)> {
+    config.miners.iter()
+        .find(|miner| miner.address == address)
+        .ok_or_else(|| (1, format!("actor {} not found", address)))
+}
+
+fn param(params: &[Value], index: usize) -> Result<&Value, (i64, String)> {
+    params.get(index).ok_or_else(|| (-32602, format!("missing parameter {}", index)))
+}
+
+fn link(cid: &Cid) -> Link {
+    Link { cid: cid.to_string() }
+}
+
+fn parse_link(value: &Value) -> Result<Cid, (i64, String)> {
+    value["/"].as_str()
+        .and_then(|cid| Cid::try_from(cid).ok())
+        .ok_or_else(|| (-32602, format!("invalid CID link {}", value)))
+}
@@ -1,2 +1,22 @@
+pub mod backend;
+pub mod car;
+pub mod client;
+pub mod deals;
+pub mod discovery;
+pub mod index;
+pub mod local;
+pub mod memory;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod retrieval;
 pub mod storage;
-pub use storage::{FilecoinStorage, StorageRecord};
+pub use backend::StorageBackend;
+pub use client::{LotusClient, LotusError};
+pub use deals::{DealConfig, DealManager, DealState, PendingDeal};
+pub use index::RecordIndex;
+pub use local::LocalStorage;
+pub use memory::MemoryStorage;
+#[cfg(feature = "mock")]
+pub use mock::{MockConfig, MockLotus};
+pub use retrieval::{RetrievalSource, Retrieved, Retriever};
+pub use storage::{FilecoinStorage, RecordType, SkippedAnnouncement, StorageRecord, SyncReport};
@@ -1,3 +1,161 @@
-pub fn retrieve_data(_cid: &str) -> Vec<u8> {
-    vec![]
+//! Content retrieval from an ordered list of sources.
+//!
+//! A `Retriever` tries each source in turn: a local block cache, trustless
+//! IPFS gateways, and finally a paid Lotus retrieval deal. Whatever a source
+//! returns is checked against the CID, so a misbehaving gateway is skipped
+//! like an unreachable one. Blocks served by any other source are written to
+//! the cache, so each record is fetched from the network at most once.
+
+use super::backend::verify_cid;
+use super::client::LotusClient;
+use cid::Cid;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Public trustless gateway used when none is configured
+pub const DEFAULT_GATEWAY: &str = "https://trustless-gateway.link";
+
+#[derive(Debug, Clone)]
+pub enum RetrievalSource {
+    /// Directory of blocks named by CID
+    Cache(PathBuf),
+    /// Base URL of a trustless IPFS gateway
+    Gateway(String),
+    Lotus(LotusClient),
+}
+
+impl std::fmt::Display for RetrievalSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RetrievalSource::Cache(dir) => write!(f, "cache {}", dir.display()),
+            RetrievalSource::Gateway(url) => write!(f, "gateway {}", url),
+            RetrievalSource::Lotus(client) => write!(f, "lotus {}", client.endpoint()),
+        }
+    }
+}
+
+/// Content of a CID and the source that served it
+#[derive(Debug, Clone)]
+pub struct Retrieved {
+    pub data: Vec<u8>,
+    pub source: RetrievalSource,
+}
+
+#[derive(Debug, Clone)]
+pub struct Retriever {
+    sources: Vec<RetrievalSource>,
+    http: reqwest::Client,
+}
+
+impl Retriever {
+    /// Try `sources` in order, with `timeout` for each gateway request
+    pub fn new(sources: Vec<RetrievalSource>, timeout: Duration) -> crate::utils::Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| crate::utils::Error::StorageError(e.to_string()))?;
+
+        Ok(Self { sources, http })
+    }
+
+    pub fn sources(&self) -> &[RetrievalSource] {
+        &self.sources
+    }
+
+    /// Fetch the content of `cid` from the first source that has it intact.
+    /// Fails with `IntegrityError` if none did and some source served other
+    /// bytes.
+    pub async fn retrieve(&self, cid: &Cid) -> crate::utils::Result<Retrieved> {
+        let mut failures = Vec::new();
+        let mut tampered = false;
+
+        for source in &self.sources {
+            let data = match self.fetch(source, cid).await {
+                Ok(Some(data)) => data,
+                Ok(None) => continue,
+                Err(e) => {
+                    failures.push(format!("{}: {}", source, e));
+                    continue;
+                }
+            };
+
+            if let Err(e) = verify_cid(cid, &data) {
+                failures.push(format!("{}: {}", source, e));
+                tampered = true;
+                continue;
+            }
+
+            if !matches!(source, RetrievalSource::Cache(_)) {
+                self.cache_block(cid, &data)?;
+            }
+            return Ok(Retrieved { data, source: source.clone() });
+        }
+
+        let message = format!(
+            "No source could serve {} ({})",
+            cid,
+            if failures.is_empty() { "not found".to_string() } else { failures.join("; ") }
+        );
+        Err(if tampered {
+            crate::utils::Error::IntegrityError(message)
+        } else {
+            crate::utils::Error::StorageError(message)
+        })
+    }
+
+    /// Store `data` in every cache source, e.g. after publishing it
+    pub fn cache_block(&self, cid: &Cid, data: &[u8]) -> crate::utils::Result<()> {
+        for source in &self.sources {
+            if let RetrievalSource::Cache(dir) = source {
+                write_cached(dir, cid, data)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `None` when a cache simply does not hold the block
+    async fn fetch(&self, source: &RetrievalSource, cid: &Cid) -> crate::utils::Result<Option<Vec<u8>>> {
+        match source {
+            RetrievalSource::Cache(dir) => match std::fs::read(dir.join(cid.to_string())) {
+                Ok(data) => Ok(Some(data)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(crate::utils::Error::FileError(e.to_string())),
+            },
+            RetrievalSource::Gateway(url) => self.fetch_gateway(url, cid).await.map(Some),
+            RetrievalSource::Lotus(client) => Ok(Some(client.client_retrieve(cid).await?)),
+        }
+    }
+
+    async fn fetch_gateway(&self, url: &str, cid: &Cid) -> crate::utils::Result<Vec<u8>> {
+        // Ask for the raw block so the bytes can be checked against the CID
+        let response = self.http
+            .get(format!("{}/ipfs/{}?format=raw", url.trim_end_matches('/'), cid))
+            .header(reqwest::header::ACCEPT, "application/vnd.ipld.raw")
+            .send()
+            .await
+            .map_err(|e| crate::utils::Error::StorageError(e.to_string()))?;
+
+        if !response.status().is_success() {
+            return Err(crate::utils::Error::StorageError(format!("HTTP {}", response.status())));
+        }
+
+        let data = response.bytes()
+            .await
+            .map_err(|e| crate::utils::Error::StorageError(e.to_string()))?;
+        Ok(data.to_vec())
+    }
+}
+
+fn write_cached(dir: &Path, cid: &Cid, data: &[u8]) -> crate::utils::Result<()> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
+
+    // Write to a temporary name first so readers never see a partial block
+    let path = dir.join(cid.to_string());
+    let tmp_path = path.with_extension(format!("tmp-{}", std::process::id()));
+    std::fs::write(&tmp_path, data)
+        .map_err(|e| crate::utils::Error::FileError(e.to_string()))?;
+    std::fs::rename(&tmp_path, &path)
+        .map_err(|e| crate::utils::Error::FileError(e.to_string()))
 }
@@ -1,141 +1,392 @@
-use filecoin_client::{Client, StorageDeal};
-use lotus_api::LotusDaemon;
+use super::backend::{compute_cid, StorageBackend};
+use super::client::LotusClient;
+use super::deals::{padded_piece_size, DealManager, PendingDeal};
+use super::discovery::{discovery_address, game_address, Announcement, Network};
+use super::index::RecordIndex;
+use super::retrieval::{Retrieved, Retriever};
+use crate::utils::clock::unix_now;
+use async_trait::async_trait;
 use cid::Cid;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use tokio::time::{Duration, sleep};
+use std::path::Path;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StorageRecord {
     pub id: String,
     pub content_cid: Cid,
+    /// Unix time the backend stored the record
     pub timestamp: u64,
     pub record_type: RecordType,
+    /// Epoch of the block that included the record's announcement, once known
+    #[serde(default)]
+    pub epoch: Option<u64>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Outcome of `FilecoinStorage::sync_game`
+#[derive(Debug, Clone, Default)]
+pub struct SyncReport {
+    /// Records indexed by this sync
+    pub synced: Vec<StorageRecord>,
+    /// Announcements whose content could not be fetched; the next sync tries
+    /// them again
+    pub skipped: Vec<SkippedAnnouncement>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SkippedAnnouncement {
+    pub message_cid: Cid,
+    pub content_cid: Cid,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum RecordType {
     EnterTransaction,
     ChoiceTransaction,
     RevealTransaction,
+    CeremonyContribution,
+    GameConfig,
+    RecoveryTransaction,
+}
+
+impl RecordType {
+    pub const ALL: [RecordType; 6] = [
+        RecordType::GameConfig,
+        RecordType::EnterTransaction,
+        RecordType::ChoiceTransaction,
+        RecordType::RecoveryTransaction,
+        RecordType::RevealTransaction,
+        RecordType::CeremonyContribution,
+    ];
 }
 
 pub struct FilecoinStorage {
-    client: Client,
-    daemon: LotusDaemon,
-    stored_records: HashMap<String, StorageRecord>,
+    client: LotusClient,
+    index: RecordIndex,
+    deals: DealManager,
+    retriever: Retriever,
+    // Address that records of this game are announced to
+    game_address: String,
+    // Address that game configs are additionally announced to
+    discovery_address: String,
+    // Epoch a sync looks back to
+    start_epoch: u64,
 }
 
 impl FilecoinStorage {
-    
-    pub async fn new(lotus_endpoint: &str, auth_token: &str) -> crate::utils::Result<Self> {
-        let client = Client::new(lotus_endpoint, auth_token)
-            .await
-            .map_err(|e| crate::utils::Error::StorageError(e.to_string()))?;
-        
-        let daemon = LotusDaemon::new(lotus_endpoint)
-            .await
-            .map_err(|e| crate::utils::Error::StorageError(e.to_string()))?;
+    /// Store records of `game` through the node behind `client`, keeping the
+    /// record index at `index_path`, tracking storage deals with `deals` and
+    /// reading content through `retriever`
+    pub async fn new(
+        client: LotusClient,
+        index_path: &Path,
+        game: &[u8],
+        deals: DealManager,
+        retriever: Retriever,
+    ) -> crate::utils::Result<Self> {
+        let network = node_network(&client).await?;
+        Self::with_address(client, index_path, network, game_address(game, network), deals, retriever)
+    }
 
+    /// Storage on the discovery address, for listing games and for records
+    /// that belong to no game
+    pub async fn discovery(
+        client: LotusClient,
+        index_path: &Path,
+        deals: DealManager,
+        retriever: Retriever,
+    ) -> crate::utils::Result<Self> {
+        let network = node_network(&client).await?;
+        Self::with_address(client, index_path, network, discovery_address(network), deals, retriever)
+    }
+
+    fn with_address(
+        client: LotusClient,
+        index_path: &Path,
+        network: Network,
+        game_address: String,
+        deals: DealManager,
+        retriever: Retriever,
+    ) -> crate::utils::Result<Self> {
         Ok(Self {
             client,
-            daemon,
-            stored_records: HashMap::new(),
+            index: RecordIndex::open(index_path)?,
+            deals,
+            retriever,
+            game_address,
+            discovery_address: discovery_address(network),
+            start_epoch: 0,
         })
     }
 
-
     pub async fn store_data(
         &mut self,
         data: Vec<u8>,
         record_type: RecordType,
     ) -> crate::utils::Result<StorageRecord> {
-        
-        let cid = self.upload_to_ipfs(data).await?;
-        
-        
-        let deal = self.create_storage_deal(&cid).await?;
-        
-        
-        self.wait_for_deal_confirmation(&deal).await?;
-        
+        let piece_size = padded_piece_size(data.len() as u64);
+        let cid = self.upload_to_ipfs(&data).await?;
+        self.retriever.cache_block(&cid, &data)?;
+
+        // The deal is only proposed here; `poll_deals` follows it up. The
+        // content is already on the node, so a deal that cannot be proposed
+        // is recorded as failed rather than keeping the record from the game
+        if let Err(e) = self.deals.submit(&self.client, &cid, piece_size).await {
+            self.deals.record_failure(&cid, &e.to_string())?;
+        }
+
+        // Let the other participants discover the record
+        let message_cid = self.announce(record_type, &cid).await?;
+
+        // The epoch is filled in by the first sync after the announcement lands
         let record = StorageRecord {
-            id: uuid::Uuid::new_v4().to_string(),
+            id: message_cid.to_string(),
             content_cid: cid,
-            timestamp: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
+            timestamp: unix_now(),
             record_type,
+            epoch: None,
         };
 
-        self.stored_records.insert(record.id.clone(), record.clone());
-        Ok(record)
+        self.index.insert(record)
     }
 
     
+    /// Fetch the content for `cid` and check it against the CID
     pub async fn retrieve_data(&self, cid: &Cid) -> crate::utils::Result<Vec<u8>> {
-        let data = self.client.retrieve_data(cid)
-            .await
-            .map_err(|e| crate::utils::Error::StorageError(e.to_string()))?;
-        
-        Ok(data)
+        Ok(self.retrieve(cid).await?.data)
+    }
+
+    /// Like `retrieve_data`, also reporting which source served the content
+    pub async fn retrieve(&self, cid: &Cid) -> crate::utils::Result<Retrieved> {
+        self.retriever.retrieve(cid).await
     }
 
     
-    pub fn list_records(&self, record_type: Option<RecordType>) -> Vec<&StorageRecord> {
+    pub fn list_records(&self, record_type: Option<RecordType>) -> crate::utils::Result<Vec<StorageRecord>> {
         match record_type {
-            Some(rt) => self.stored_records.values()
-                .filter(|record| std::mem::discriminant(&record.record_type) == std::mem::discriminant(&rt))
-                .collect(),
-            None => self.stored_records.values().collect(),
+            Some(rt) => self.index.list(rt),
+            None => self.index.records(),
         }
     }
 
-    
-    pub async fn get_all_public_keys(&self) -> crate::utils::Result<Vec<Vec<u8>>> {
-        let enter_records = self.list_records(Some(RecordType::EnterTransaction));
-        let mut public_keys = Vec::new();
-
-        for record in enter_records {
-            let data = self.retrieve_data(&record.content_cid).await?;
-            let transaction: crate::protocol::EnterTransaction = bincode::deserialize(&data)
-                .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
-            public_keys.push(transaction.public_key);
-        }
+    pub fn client(&self) -> &LotusClient {
+        &self.client
+    }
 
-        Ok(public_keys)
+    pub fn index(&self) -> &RecordIndex {
+        &self.index
     }
 
-    
-    async fn upload_to_ipfs(&self, data: Vec<u8>) -> crate::utils::Result<Cid> {
-        // Implementation would use IPFS client to upload data
-        todo!("Implement IPFS upload")
+    pub fn game_address(&self) -> &str {
+        &self.game_address
+    }
+
+    /// Look no further back than `epoch` on sync, normally the epoch the game
+    /// was created at. Without it a sync walks the whole chain.
+    pub fn set_start_epoch(&mut self, epoch: u64) {
+        self.start_epoch = epoch;
+    }
+
+    pub fn deals(&self) -> &DealManager {
+        &self.deals
+    }
+
+    /// Poll the deals that are due and return how many changed state
+    pub async fn poll_deals(&mut self) -> crate::utils::Result<usize> {
+        self.deals.poll(&self.client, unix_now()).await
     }
 
-    async fn create_storage_deal(&self, cid: &Cid) -> crate::utils::Result<StorageDeal> {
-        // Implementation would create a storage deal with miners
-        todo!("Implement storage deal creation")
+    /// Deal of the record stored under `cid`, if this node proposed one
+    pub fn deal_for(&self, cid: &Cid) -> Option<&PendingDeal> {
+        self.deals.deal_for(cid)
     }
 
-    async fn wait_for_deal_confirmation(&self, deal: &StorageDeal) -> crate::utils::Result<()> {
-        // Poll deal status until confirmed
-        let mut attempts = 0;
-        const MAX_ATTEMPTS: u32 = 60; // 5 minutes with 5-second intervals
+    /// Pull every record announced to the game address that is not in the
+    /// local index yet. Messages a sync has finished with are not fetched
+    /// again.
+    ///
+    /// Each record gets the epoch its announcement was included at, the
+    /// earliest one if the content was announced more than once. The chain
+    /// fixes that epoch, so it is the record's time rather than anything the
+    /// sender wrote into the record.
+    pub async fn sync_game(&mut self) -> crate::utils::Result<SyncReport> {
+        let message_cids = self.client.state_list_messages(&self.game_address, self.start_epoch).await?;
 
-        while attempts < MAX_ATTEMPTS {
-            let status = self.client.get_deal_status(&deal.deal_id)
-                .await
-                .map_err(|e| crate::utils::Error::StorageError(e.to_string()))?;
+        let processed = self.index.processed_messages()?;
+        let mut known = self.index.records()?;
+        let mut report = SyncReport::default();
 
-            if status.is_confirmed() {
-                return Ok(());
+        for message_cid in message_cids {
+            let message_id = message_cid.to_string();
+            if processed.contains(&message_id) {
+                continue;
             }
 
-            sleep(Duration::from_secs(5)).await;
-            attempts += 1;
+            let message = self.client.chain_get_message(&message_cid).await?;
+
+            // Anyone can send to the game address, so skip anything unexpected
+            let announcement = Announcement::from_params(&message.params)
+                .and_then(|announcement| Some((announcement.content_cid().ok()?, announcement)));
+            let (cid, announcement) = match announcement {
+                Some(announcement) => announcement,
+                None => {
+                    self.index.mark_processed(&message_id)?;
+                    continue;
+                }
+            };
+            let epoch = match self.client.state_search_msg(&message_cid).await? {
+                Some(epoch) => epoch,
+                None => continue,
+            };
+
+            let indexed = known.iter_mut()
+                .find(|record| record.content_cid == cid && record.record_type == announcement.record_type);
+            if let Some(record) = indexed {
+                // Our own records learn their epoch here
+                if record.epoch.is_none_or(|known_epoch| epoch < known_epoch) {
+                    self.index.set_epoch(record, epoch)?;
+                    record.epoch = Some(epoch);
+                }
+                self.index.mark_processed(&message_id)?;
+                continue;
+            }
+
+            // Fetching checks the content against the announced CID. Content
+            // that cannot be fetched yet is picked up by a later sync
+            if let Err(e) = self.retrieve_data(&cid).await {
+                report.skipped.push(SkippedAnnouncement { message_cid, content_cid: cid, reason: e.to_string() });
+                continue;
+            }
+
+            let record = self.index.insert(StorageRecord {
+                id: message_cid.to_string(),
+                content_cid: cid,
+                timestamp: unix_now(),
+                record_type: announcement.record_type,
+                epoch: Some(epoch),
+            })?;
+            self.index.mark_processed(&message_id)?;
+            known.push(record.clone());
+            report.synced.push(record);
+        }
+
+        Ok(report)
+    }
+
+    /// Index a record exported from another node under the epoch of its
+    /// announcement, after checking on chain that its message announced this
+    /// content to the game address. Records that fail the check are stored
+    /// and announced again.
+    pub async fn import_record(&mut self, data: Vec<u8>, record: &StorageRecord) -> crate::utils::Result<StorageRecord> {
+        let cid = compute_cid(&data);
+        let epoch = match self.announcement_epoch(&record.id, record.record_type, &cid).await? {
+            Some(epoch) => epoch,
+            None => return self.store_data(data, record.record_type).await,
+        };
+
+        self.upload_to_ipfs(&data).await?;
+        self.retriever.cache_block(&cid, &data)?;
+
+        let record = self.index.insert(StorageRecord {
+            id: record.id.clone(),
+            content_cid: cid,
+            timestamp: unix_now(),
+            record_type: record.record_type,
+            epoch: Some(epoch),
+        })?;
+        // The content may already be indexed from our own announcement
+        if record.epoch.is_none_or(|known_epoch| epoch < known_epoch) {
+            self.index.set_epoch(&record, epoch)?;
+            return Ok(StorageRecord { epoch: Some(epoch), ..record });
+        }
+        Ok(record)
+    }
+
+    /// Epoch of message `id` if it is on chain and announces `cid` as a
+    /// `record_type` record to the game address
+    async fn announcement_epoch(
+        &self,
+        id: &str,
+        record_type: RecordType,
+        cid: &Cid,
+    ) -> crate::utils::Result<Option<u64>> {
+        let message_cid = match Cid::try_from(id) {
+            Ok(message_cid) => message_cid,
+            Err(_) => return Ok(None),
+        };
+        let message = match self.client.chain_get_message(&message_cid).await {
+            Ok(message) => message,
+            Err(_) => return Ok(None),
+        };
+
+        let announces = message.to == self.game_address
+            && Announcement::from_params(&message.params).is_some_and(|announcement| {
+                announcement.record_type == record_type && announcement.content_cid().is_ok_and(|announced| announced == *cid)
+            });
+        if !announces {
+            return Ok(None);
+        }
+
+        Ok(self.client.state_search_msg(&message_cid).await?)
+    }
+
+    /// Announce `cid` to the game address and return the message CID
+    async fn announce(&self, record_type: RecordType, cid: &Cid) -> crate::utils::Result<Cid> {
+        let params = Announcement::new(record_type, cid).to_params()?;
+
+        let message_cid = self.client.mpool_push_message(&self.game_address, &params).await?;
+
+        // Configs must also be found by players who do not know the game yet
+        if record_type == RecordType::GameConfig && self.game_address != self.discovery_address {
+            self.client.mpool_push_message(&self.discovery_address, &params).await?;
         }
 
-        Err(crate::utils::Error::StorageError("Deal confirmation timeout".to_string()))
+        Ok(message_cid)
     }
+
+    
+    async fn upload_to_ipfs(&self, data: &[u8]) -> crate::utils::Result<Cid> {
+        // Compute the CID locally so the node cannot substitute other content
+        let cid = compute_cid(data);
+
+        let remote_cid = self.client.client_import(data).await?;
+
+        if remote_cid != cid {
+            return Err(crate::utils::Error::IntegrityError(format!(
+                "Node returned CID {} for content with CID {}", remote_cid, cid
+            )));
+        }
+
+        Ok(cid)
+    }
+}
+
+#[async_trait]
+impl StorageBackend for FilecoinStorage {
+    async fn put(&mut self, data: Vec<u8>, record_type: RecordType) -> crate::utils::Result<StorageRecord> {
+        self.store_data(data, record_type).await
+    }
+
+    async fn get(&self, cid: &Cid) -> crate::utils::Result<Vec<u8>> {
+        self.retrieve_data(cid).await
+    }
+
+    async fn list(&self, record_type: RecordType) -> crate::utils::Result<Vec<StorageRecord>> {
+        self.list_records(Some(record_type))
+    }
+
+    async fn import(&mut self, data: Vec<u8>, record: &StorageRecord) -> crate::utils::Result<StorageRecord> {
+        self.import_record(data, record).await
+    }
+
+    async fn sync(&mut self) -> crate::utils::Result<usize> {
+        Ok(self.sync_game().await?.synced.len())
+    }
+}
+
+async fn node_network(client: &LotusClient) -> crate::utils::Result<Network> {
+    Ok(match client.state_network_name().await?.as_str() {
+        "mainnet" => Network::Mainnet,
+        _ => Network::Testnet,
+    })
 }
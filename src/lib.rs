@@ -6,8 +6,8 @@ pub mod protocol;
 pub mod utils;
 
 pub use crypto::{KeyPair, ZKProof, DHKeyExchange};
-pub use filecoin::{FilecoinStorage, StorageClient};
-pub use protocol::{SecretSantaProtocol, Phase, ProtocolState};
+pub use filecoin::{FilecoinStorage, MemoryStorage, StorageBackend};
+pub use protocol::{GameId, SecretSantaProtocol, Phase, ProtocolState};
 pub use utils::{Error, Result};
 
 /// Re-export commonly used types
@@ -1,5 +1,10 @@
-mod cli;
+use clap::Parser;
+use zkret_santa_filecoin::cli::{execute_command, Cli};
 
-fn main() {
-    cli::run();
+#[tokio::main]
+async fn main() {
+    if let Err(e) = execute_command(Cli::parse()).await {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,321 @@
+//! Game identity and schedule.
+//!
+//! An organizer starts a game by publishing a signed `GameConfig` record. The
+//! `GameId` is the SHA-256 hash of that record, i.e. the digest inside its
+//! CID, so the id commits to the whole configuration and its signature. Every
+//! transaction and proof of the game carries the id, which keeps games that
+//! share a storage backend apart.
+//!
+//! The config fixes a deadline for each phase, so the phase of a game is a
+//! function of time alone and a transaction is only valid inside its window.
+
+use super::phases::Phase;
+use super::transaction::Transaction;
+use crate::crypto::keypair::verify_signature;
+use crate::crypto::merkle::MAX_LEAVES;
+use crate::crypto::KeyPair;
+use crate::filecoin::{RecordType, StorageBackend};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+/// Domain separator of the organizer's signature over a config
+const GAME_CONFIG_DOMAIN: &[u8] = b"zkret-santa/game-config/v1";
+
+/// Filecoin block time
+pub const EPOCH_DURATION_SECS: u64 = 30;
+
+/// Unix time of Filecoin mainnet genesis (epoch 0)
+pub const MAINNET_GENESIS: u64 = 1_598_306_400;
+
+/// Unix time of calibration network genesis (epoch 0)
+pub const CALIBRATION_GENESIS: u64 = 1_667_326_380;
+
+// Phase lengths used by `GameConfig::new`
+const DEFAULT_PHASE_SECS: u64 = 7 * 24 * 60 * 60;
+const DEFAULT_RECOVERY_SECS: u64 = 2 * 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct GameId(pub [u8; 32]);
+
+impl GameId {
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for GameId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl FromStr for GameId {
+    type Err = crate::utils::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s)
+            .map_err(|e| crate::utils::Error::InvalidInput(format!("Invalid game id: {}", e)))?;
+        let bytes: [u8; 32] = bytes.try_into()
+            .map_err(|_| crate::utils::Error::InvalidInput("Game ids are 32 bytes".to_string()))?;
+
+        Ok(GameId(bytes))
+    }
+}
+
+/// End of a phase, as a unix timestamp or a Filecoin epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Deadline {
+    Timestamp(u64),
+    Epoch(u64),
+}
+
+impl Deadline {
+    /// Unix time of the deadline on a chain that started at `genesis`, or
+    /// `None` if it does not fit in a u64
+    pub fn timestamp(&self, genesis: u64) -> Option<u64> {
+        match self {
+            Deadline::Timestamp(timestamp) => Some(*timestamp),
+            Deadline::Epoch(epoch) => epoch.checked_mul(EPOCH_DURATION_SECS)
+                .and_then(|secs| genesis.checked_add(secs)),
+        }
+    }
+}
+
+impl fmt::Display for Deadline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Deadline::Timestamp(timestamp) => write!(f, "{}", timestamp),
+            Deadline::Epoch(epoch) => write!(f, "epoch:{}", epoch),
+        }
+    }
+}
+
+/// Parses unix timestamps (`1765000000`) and epochs (`epoch:5400000`)
+impl FromStr for Deadline {
+    type Err = crate::utils::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |value: &str| value.parse::<u64>()
+            .map_err(|e| crate::utils::Error::InvalidInput(format!("Invalid deadline {}: {}", s, e)));
+
+        match s.strip_prefix("epoch:") {
+            Some(epoch) => Ok(Deadline::Epoch(parse(epoch)?)),
+            None => Ok(Deadline::Timestamp(parse(s)?)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GameConfig {
+    pub name: String,
+    /// ed25519 public key of the organizer who signs the config
+    pub organizer: Vec<u8>,
+    pub created_at: u64,
+    pub min_participants: u32,
+    pub max_participants: u32,
+    /// ENTER records are accepted until this deadline
+    pub enter_deadline: Deadline,
+    /// CHOICE records are accepted from the ENTER deadline until this one
+    pub choice_deadline: Deadline,
+    /// RECOVERY records are accepted from the CHOICE deadline until this one
+    pub recovery_deadline: Deadline,
+    /// REVEAL records are accepted from the RECOVERY deadline until this one
+    pub reveal_deadline: Deadline,
+    /// Free-form gift budget shown to participants
+    pub budget: String,
+    /// Unix time of epoch 0 on the chain epoch deadlines count on
+    pub genesis_timestamp: u64,
+    /// `params_hash` of the parameter set every proof in the game is made with
+    pub params_hash: String,
+}
+
+impl GameConfig {
+    /// Config with a week per phase and two days of recovery starting now,
+    /// played with the parameter set `params_hash`; adjust the fields before
+    /// signing
+    pub fn new(name: &str, organizer: &KeyPair, params_hash: &str) -> Self {
+        let created_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        Self {
+            name: name.to_string(),
+            organizer: organizer.public_key.as_bytes().to_vec(),
+            created_at,
+            min_participants: 3,
+            max_participants: MAX_LEAVES as u32,
+            enter_deadline: Deadline::Timestamp(created_at + DEFAULT_PHASE_SECS),
+            choice_deadline: Deadline::Timestamp(created_at + 2 * DEFAULT_PHASE_SECS),
+            recovery_deadline: Deadline::Timestamp(created_at + 2 * DEFAULT_PHASE_SECS + DEFAULT_RECOVERY_SECS),
+            reveal_deadline: Deadline::Timestamp(created_at + 3 * DEFAULT_PHASE_SECS + DEFAULT_RECOVERY_SECS),
+            budget: String::new(),
+            genesis_timestamp: MAINNET_GENESIS,
+            params_hash: params_hash.to_string(),
+        }
+    }
+
+    /// Unix times of the ENTER, CHOICE, RECOVERY and REVEAL deadlines; a
+    /// deadline past the end of time reads as `u64::MAX`, which `validate`
+    /// rejects
+    pub fn deadlines(&self) -> [u64; 4] {
+        [self.enter_deadline, self.choice_deadline, self.recovery_deadline, self.reveal_deadline]
+            .map(|deadline| deadline.timestamp(self.genesis_timestamp).unwrap_or(u64::MAX))
+    }
+
+    /// Unix time of `epoch` on the chain the game is played on
+    pub fn epoch_time(&self, epoch: u64) -> u64 {
+        Deadline::Epoch(epoch).timestamp(self.genesis_timestamp).unwrap_or(u64::MAX)
+    }
+
+    /// Epoch of the chain the game is played on at its creation
+    pub fn creation_epoch(&self) -> u64 {
+        self.created_at.saturating_sub(self.genesis_timestamp) / EPOCH_DURATION_SECS
+    }
+
+    /// Phase the game is in at unix time `time`
+    pub fn phase_at(&self, time: u64) -> Phase {
+        let [enter, choice, recovery, reveal] = self.deadlines();
+        if time < self.created_at {
+            Phase::Setup
+        } else if time < enter {
+            Phase::Enter
+        } else if time < choice {
+            Phase::Choice
+        } else if time < recovery {
+            Phase::Recovery
+        } else if time < reveal {
+            Phase::Reveal
+        } else {
+            Phase::Complete
+        }
+    }
+
+    /// Check the participant limits and that the deadlines are in order
+    pub fn validate(&self) -> crate::utils::Result<()> {
+        if self.min_participants < 2 {
+            return Err(invalid_config("A game needs at least 2 participants"));
+        }
+        if self.min_participants > self.max_participants {
+            return Err(invalid_config("Minimum participants exceed the maximum"));
+        }
+        if self.max_participants as usize > MAX_LEAVES {
+            return Err(invalid_config(&format!("A game holds at most {} participants", MAX_LEAVES)));
+        }
+        if hex::decode(&self.params_hash).map_or(true, |hash| hash.len() != 32) {
+            return Err(invalid_config("The parameter set hash must be 32 bytes of hex"));
+        }
+
+        let deadlines = [self.enter_deadline, self.choice_deadline, self.recovery_deadline, self.reveal_deadline];
+        if deadlines.iter().any(|deadline| deadline.timestamp(self.genesis_timestamp).is_none()) {
+            return Err(invalid_config("A deadline lies too far in the future"));
+        }
+        let [enter, choice, recovery, reveal] = self.deadlines();
+        if !(self.created_at < enter && enter < choice && choice < recovery && recovery < reveal) {
+            return Err(invalid_config("Deadlines must follow creation and each other"));
+        }
+
+        Ok(())
+    }
+
+    /// Sign the config with the organizer's key
+    pub fn sign(self, organizer: &KeyPair) -> crate::utils::Result<SignedGameConfig> {
+        if organizer.public_key.as_bytes()[..] != self.organizer[..] {
+            return Err(crate::utils::Error::ProtocolError(
+                "Only the organizer can sign a game config".to_string()
+            ));
+        }
+        self.validate()?;
+
+        let signature = organizer.sign(&self.signing_message()?).to_bytes().to_vec();
+        Ok(SignedGameConfig { config: self, signature })
+    }
+
+    fn signing_message(&self) -> crate::utils::Result<Vec<u8>> {
+        let mut message = GAME_CONFIG_DOMAIN.to_vec();
+        message.extend(bincode::serialize(self)
+            .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?);
+        Ok(message)
+    }
+}
+
+/// Content of a `GameConfig` record
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedGameConfig {
+    pub config: GameConfig,
+    pub signature: Vec<u8>,
+}
+
+impl SignedGameConfig {
+    pub fn encode(&self) -> crate::utils::Result<Vec<u8>> {
+        bincode::serialize(self).map_err(|e| crate::utils::Error::SerializationError(e.to_string()))
+    }
+
+    /// Decode a record and check the organizer's signature and the config
+    pub fn decode(data: &[u8]) -> crate::utils::Result<Self> {
+        let signed: Self = bincode::deserialize(data)
+            .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+
+        if !verify_signature(&signed.config.organizer, &signed.config.signing_message()?, &signed.signature) {
+            return Err(crate::utils::Error::ProtocolError(
+                "Game config signature does not verify".to_string()
+            ));
+        }
+        signed.config.validate()?;
+
+        Ok(signed)
+    }
+
+    pub fn id(&self) -> crate::utils::Result<GameId> {
+        Ok(GameId(Sha256::digest(self.encode()?).into()))
+    }
+}
+
+/// Every game with a valid config record in `storage`, oldest first
+pub async fn list_games<S: StorageBackend + ?Sized>(
+    storage: &S,
+) -> crate::utils::Result<Vec<(GameId, SignedGameConfig)>> {
+    let mut games = Vec::new();
+
+    for record in storage.list(RecordType::GameConfig).await? {
+        let data = storage.get(&record.content_cid).await?;
+        // Anyone can publish a config, so skip the ones that do not verify
+        if let Ok(signed) = SignedGameConfig::decode(&data) {
+            games.push((signed.id()?, signed));
+        }
+    }
+
+    games.sort_by_key(|(_, signed)| signed.config.created_at);
+    Ok(games)
+}
+
+/// The config record of `game`
+pub async fn load_game<S: StorageBackend + ?Sized>(
+    storage: &S,
+    game: &GameId,
+) -> crate::utils::Result<SignedGameConfig> {
+    list_games(storage).await?
+        .into_iter()
+        .find(|(id, _)| id == game)
+        .map(|(_, signed)| signed)
+        .ok_or_else(|| crate::utils::Error::ProtocolError(format!(
+            "Game {} not found, sync storage or check the id", game
+        )))
+}
+
+/// Whether the content `data` of a `record_type` record belongs to `game`
+pub fn is_game_record(record_type: RecordType, data: &[u8], game: &GameId) -> bool {
+    match record_type {
+        RecordType::GameConfig => SignedGameConfig::decode(data)
+            .and_then(|signed| signed.id())
+            .is_ok_and(|id| id == *game),
+        _ => Transaction::decode(record_type, data)
+            .is_ok_and(|transaction| transaction.game_id() == game),
+    }
+}
+
+fn invalid_config(message: &str) -> crate::utils::Error {
+    crate::utils::Error::InvalidInput(format!("Invalid game config: {}", message))
+}
@@ -1,113 +1,119 @@
-use crate::crypto::{KeyPair, ZKProof, ZKProofSystem};
-use crate::filecoin::{FilecoinStorage, RecordType};
+use super::game::{load_game, GameId, SignedGameConfig};
+use super::state::{Choice, ProtocolState};
+use super::transaction::{ChoiceTransaction, EnterTransaction, RecoveryTransaction, RevealTransaction, Transaction};
+use crate::crypto::public_inputs::{field_to_bytes, game_field, payload_digest};
+use crate::crypto::circuits::{choice_nullifier, identity_secret};
+use crate::crypto::{KeyPair, ZKProofSystem};
+use crate::filecoin::{RecordType, StorageBackend};
+use crate::utils::clock::{system_clock, Clock};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub enum Phase {
+    #[default]
     Setup,
     Enter,
     Choice,
+    Recovery,
     Reveal,
     Complete,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct EnterTransaction {
-    pub public_key: Vec<u8>,
-    pub zk_proof: ZKProof,
-    pub timestamp: u64,
+pub struct SecretSantaProtocol<S: StorageBackend> {
+    storage: S,
+    zk_system: ZKProofSystem,
+    state: ProtocolState,
+    clock: Clock,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ChoiceTransaction {
-    pub chosen_public_key: Vec<u8>,
-    pub chooser_dh_public_key: Vec<u8>,
-    pub zk_proof: ZKProof,
-    pub timestamp: u64,
-}
+impl<S: StorageBackend> SecretSantaProtocol<S> {
+    /// Initialize a Secret Santa protocol instance for `game` from the records
+    /// in `storage`
+    ///
+    /// `zk_system` must hold the parameter set named in the game's config,
+    /// see `ZKProofSystem::from_params_dir`.
+    pub async fn new(storage: S, zk_system: ZKProofSystem, game: GameId) -> crate::utils::Result<Self> {
+        let config = load_game(&storage, &game).await?;
+        if config.config.params_hash != zk_system.params_hash() {
+            return Err(crate::utils::Error::ProtocolError(format!(
+                "Game {} is played with parameter set {}, but the loaded parameters are {}; \
+                 use the parameter directory the organizer published",
+                game, config.config.params_hash, zk_system.params_hash()
+            )));
+        }
+        let mut protocol = Self {
+            storage,
+            zk_system,
+            state: ProtocolState::new(config)?,
+            clock: system_clock(),
+        };
+        protocol.refresh().await?;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct RevealTransaction {
-    pub public_key: Vec<u8>,
-    pub encrypted_identity: Vec<u8>,
-    pub dh_public_key: Vec<u8>,
-    pub signature: Vec<u8>,
-    pub timestamp: u64,
-}
+        Ok(protocol)
+    }
 
-pub struct SecretSantaProtocol {
-    storage: FilecoinStorage,
-    zk_system: ZKProofSystem,
-    current_phase: Phase,
-    participants: HashMap<Vec<u8>, ParticipantState>,
-}
+    /// Publish the config record of a new game and initialize a protocol
+    /// instance for it
+    pub async fn create_game(
+        mut storage: S,
+        zk_system: ZKProofSystem,
+        config: SignedGameConfig,
+    ) -> crate::utils::Result<Self> {
+        let game = config.id()?;
+        storage.put(config.encode()?, RecordType::GameConfig).await?;
+
+        Self::new(storage, zk_system, game).await
+    }
 
-#[derive(Debug, Clone)]
-struct ParticipantState {
-    public_key: Vec<u8>,
-    has_entered: bool,
-    has_chosen: bool,
-    chosen_by: Option<Vec<u8>>,
-    has_revealed: bool,
-}
+    /// Rebuild the game state from every record in storage
+    pub async fn refresh(&mut self) -> crate::utils::Result<&ProtocolState> {
+        let mut records = Vec::new();
+        for record_type in Transaction::RECORD_TYPES {
+            for record in self.storage.list(record_type).await? {
+                let data = self.storage.get(&record.content_cid).await?;
+                records.push((record, data));
+            }
+        }
 
-impl SecretSantaProtocol {
-    /// Initialize a new Secret Santa protocol instance
-    pub async fn new(storage: FilecoinStorage) -> crate::utils::Result<Self> {
-        let zk_system = ZKProofSystem::new()?;
-        
-        Ok(Self {
-            storage,
-            zk_system,
-            current_phase: Phase::Setup,
-            participants: HashMap::new(),
-        })
+        let now = (self.clock)();
+        self.state = ProtocolState::replay(&self.zk_system, self.state.config().clone(), records, now)?;
+        Ok(&self.state)
+    }
+
+    /// Replace the system clock, e.g. to simulate a game in tests. It sets the
+    /// current phase and checks new records before they are published; stored
+    /// records are judged by the time their backend gives them.
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+        self.state.phase = self.state.phase_at((self.clock)());
     }
 
     /// Execute ENTER phase - participant registers their public key
     pub async fn enter_phase(&mut self, keypair: &KeyPair) -> crate::utils::Result<()> {
-        if !matches!(self.current_phase, Phase::Setup | Phase::Enter) {
-            return Err(crate::utils::Error::ProtocolError(
-                "ENTER phase not available in current state".to_string()
-            ));
-        }
+        self.refresh().await?;
 
         // Generate zero-knowledge proof for ENTER phase
         let zk_proof = self.zk_system.prove_enter_phase(
+            self.game_id().as_bytes(),
             keypair.public_key.as_bytes(),
-            keypair.secret_key.as_bytes(),
+            keypair.secret_bytes(),
         )?;
 
-        // Create ENTER transaction
+        let inputs = zk_proof.enter_inputs()?;
+        let nullifier = field_to_bytes(&inputs.nullifier).to_vec();
+        let message = EnterTransaction::signing_message(
+            self.game_id(), &field_to_bytes(&inputs.commitment), &nullifier,
+        );
+
         let enter_tx = EnterTransaction {
+            game_id: *self.game_id(),
             public_key: keypair.public_key.as_bytes().to_vec(),
+            nullifier,
             zk_proof,
-            timestamp: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
+            signature: keypair.sign(&message).to_bytes().to_vec(),
         };
 
-        // Serialize and store transaction
-        let tx_data = bincode::serialize(&enter_tx)
-            .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
-
-        let _record = self.storage.store_data(tx_data, RecordType::EnterTransaction).await?;
-
-        // Update participant state
-        let participant_state = ParticipantState {
-            public_key: keypair.public_key.as_bytes().to_vec(),
-            has_entered: true,
-            has_chosen: false,
-            chosen_by: None,
-            has_revealed: false,
-        };
-
-        self.participants.insert(keypair.public_key.as_bytes().to_vec(), participant_state);
-        self.current_phase = Phase::Enter;
-
-        Ok(())
+        self.publish(Transaction::Enter(enter_tx)).await
     }
 
     /// Execute CHOICE phase - participant chooses another participant
@@ -117,148 +123,276 @@ impl SecretSantaProtocol {
         chosen_public_key: &[u8],
         dh_keypair: &crate::crypto::DHKeyExchange,
     ) -> crate::utils::Result<()> {
-        if !matches!(self.current_phase, Phase::Enter | Phase::Choice) {
+        self.refresh().await?;
+
+        let chooser_pk: &[u8] = chooser_keypair.public_key.as_bytes();
+        if self.state.participant(chooser_pk).is_none() {
             return Err(crate::utils::Error::ProtocolError(
-                "CHOICE phase not available in current state".to_string()
+                "Must complete ENTER phase before CHOICE phase".to_string()
             ));
         }
 
-        // Verify chooser has completed ENTER phase
-        let chooser_pk = chooser_keypair.public_key.as_bytes();
-        if !self.participants.get(chooser_pk)
-            .map(|p| p.has_entered)
-            .unwrap_or(false) {
+        if self.state.is_last_unchosen(chooser_pk) {
             return Err(crate::utils::Error::ProtocolError(
-                "Must complete ENTER phase before CHOICE phase".to_string()
+                "Only you are left to choose, run the recovery round once the CHOICE deadline passes".to_string()
             ));
         }
 
-        // Verify chosen participant exists and hasn't been chosen
-        let all_public_keys = self.storage.get_all_public_keys().await?;
-        if !all_public_keys.contains(&chosen_public_key.to_vec()) {
+        if chooser_pk == chosen_public_key {
+            return Err(crate::utils::Error::ProtocolError(
+                "Cannot choose yourself".to_string()
+            ));
+        }
+
+        if self.state.participant(chosen_public_key).is_none() {
             return Err(crate::utils::Error::ProtocolError(
                 "Chosen participant not found".to_string()
             ));
         }
 
-        // Generate zero-knowledge proof for CHOICE phase
+        if self.state.choice_by(&self.choice_nullifier(chooser_keypair)).is_some() {
+            return Err(crate::utils::Error::ProtocolError(
+                "You have already chosen in this game".to_string()
+            ));
+        }
+
+        // Generate zero-knowledge proof of membership in the current ENTER set
         let zk_proof = self.zk_system.prove_choice_phase(
+            self.game_id().as_bytes(),
             chooser_pk,
+            chooser_keypair.secret_bytes(),
             chosen_public_key,
-            chooser_keypair.secret_key.as_bytes(),
+            &dh_keypair.public_key(),
+            &self.state.enter_set()?,
         )?;
 
-        // Create CHOICE transaction
+        let choice_inputs = zk_proof.choice_inputs()?;
         let choice_tx = ChoiceTransaction {
+            game_id: *self.game_id(),
+            merkle_root: field_to_bytes(&choice_inputs.merkle_root).to_vec(),
+            nullifier: field_to_bytes(&choice_inputs.nullifier).to_vec(),
             chosen_public_key: chosen_public_key.to_vec(),
             chooser_dh_public_key: dh_keypair.public_key().to_vec(),
             zk_proof,
-            timestamp: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
         };
 
-        // Serialize and store transaction
-        let tx_data = bincode::serialize(&choice_tx)
-            .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
-
-        let _record = self.storage.store_data(tx_data, RecordType::ChoiceTransaction).await?;
+        self.publish(Transaction::Choice(choice_tx)).await
+    }
 
-        // Update participant states
-        if let Some(chooser_state) = self.participants.get_mut(chooser_pk) {
-            chooser_state.has_chosen = true;
-        }
+    /// Refresh and return the accepted CHOICE of `keypair`.
+    ///
+    /// Fails with the rejection reason if their CHOICE lost to an earlier
+    /// one, in which case they should choose again.
+    pub async fn confirm_choice(&mut self, keypair: &KeyPair) -> crate::utils::Result<Choice> {
+        self.refresh().await?;
 
-        if let Some(chosen_state) = self.participants.get_mut(chosen_public_key) {
-            chosen_state.chosen_by = Some(chooser_pk.to_vec());
+        let nullifier = self.choice_nullifier(keypair);
+        if let Some(choice) = self.state.choice_by(&nullifier) {
+            return Ok(choice.clone());
         }
 
-        self.current_phase = Phase::Choice;
-        Ok(())
+        Err(crate::utils::Error::ProtocolError(match self.state.rejection_for(&nullifier) {
+            Some(rejection) => format!("Your CHOICE was rejected: {}", rejection.reason),
+            None => "You have not chosen yet".to_string(),
+        }))
     }
 
-    /// Execute REVEAL phase - participant reveals identity to their Secret Santa
-    pub async fn reveal_phase(
+    /// Execute the RECOVERY round - a player who has not chosen picks one of
+    /// `get_recovery_choices` after the CHOICE deadline
+    pub async fn recovery_phase(
         &mut self,
         keypair: &KeyPair,
-        identity_info: &str,
+        chosen_public_key: &[u8],
         dh_keypair: &crate::crypto::DHKeyExchange,
-        santa_dh_public_key: &[u8],
     ) -> crate::utils::Result<()> {
-        if !matches!(self.current_phase, Phase::Choice | Phase::Reveal) {
+        self.refresh().await?;
+
+        let public_key: &[u8] = keypair.public_key.as_bytes();
+        if self.state.participant(public_key).is_none() {
             return Err(crate::utils::Error::ProtocolError(
-                "REVEAL phase not available in current state".to_string()
+                "Must complete ENTER phase before the recovery round".to_string()
             ));
         }
 
-        let participant_pk = keypair.public_key.as_bytes();
-        
-        // Verify participant has been chosen
-        let participant_state = self.participants.get(participant_pk)
-            .ok_or_else(|| crate::utils::Error::ProtocolError(
-                "Participant not found".to_string()
-            ))?;
+        let nullifier = self.choice_nullifier(keypair);
+        if self.state.choice_by(&nullifier).is_some() {
+            return Err(crate::utils::Error::ProtocolError(
+                "You have already chosen in this game".to_string()
+            ));
+        }
+
+        if !self.state.recovery_choices(public_key).iter().any(|candidate| candidate == chosen_public_key) {
+            return Err(crate::utils::Error::ProtocolError(
+                "Chosen participant is not open in the recovery round".to_string()
+            ));
+        }
+
+        let zk_proof = self.zk_system.prove_recovery_phase(
+            self.game_id().as_bytes(),
+            public_key,
+            keypair.secret_bytes(),
+            chosen_public_key,
+            &dh_keypair.public_key(),
+        )?;
+
+        let recovery_tx = RecoveryTransaction {
+            game_id: *self.game_id(),
+            public_key: public_key.to_vec(),
+            nullifier,
+            chosen_public_key: chosen_public_key.to_vec(),
+            chooser_dh_public_key: dh_keypair.public_key().to_vec(),
+            zk_proof,
+        };
+
+        self.publish(Transaction::Recovery(recovery_tx)).await
+    }
+
+    /// Whether `keypair` has not chosen and is the only participant left
+    /// unchosen, so they have to wait for the recovery round
+    pub fn is_stuck(&self, keypair: &KeyPair) -> bool {
+        self.state.choice_by(&self.choice_nullifier(keypair)).is_none()
+            && self.state.is_last_unchosen(keypair.public_key.as_bytes())
+    }
+
+    /// Participants `keypair` may pick in the recovery round
+    pub fn get_recovery_choices(&self, keypair: &KeyPair) -> Vec<Vec<u8>> {
+        self.state.recovery_choices(keypair.public_key.as_bytes())
+    }
+
+    /// CHOICE nullifier of `keypair` in this game
+    pub fn choice_nullifier(&self, keypair: &KeyPair) -> Vec<u8> {
+        let secret = identity_secret(keypair.secret_bytes());
+        field_to_bytes(&choice_nullifier(secret, game_field(self.game_id().as_bytes()))).to_vec()
+    }
+
+    /// Execute REVEAL phase - participant reveals identity to their Secret Santa.
+    ///
+    /// The payload is encrypted under a fresh DH key, so the REVEAL cannot be
+    /// linked to the CHOICE its author published.
+    pub async fn reveal_phase(
+        &mut self,
+        keypair: &KeyPair,
+        identity_info: &str,
+        santa_dh_public_key: &[u8],
+    ) -> crate::utils::Result<()> {
+        self.refresh().await?;
 
-        if participant_state.chosen_by.is_none() {
+        let participant_pk: &[u8] = keypair.public_key.as_bytes();
+        if self.state.choice_for(participant_pk).is_none() {
             return Err(crate::utils::Error::ProtocolError(
                 "Participant has not been chosen by anyone".to_string()
             ));
         }
 
         // Generate shared secret and encrypt identity
-        let shared_secret = dh_keypair.compute_shared_secret(santa_dh_public_key)?;
-        let encrypted_identity = crate::crypto::encrypt_data(identity_info.as_bytes(), &shared_secret)?;
+        let game_id = *self.game_id();
+        let dh_keypair = crate::crypto::DHKeyExchange::generate();
+        let shared_secret = dh_keypair.compute_shared_secret(santa_dh_public_key, game_id.as_bytes())?;
+        let associated_data = crate::crypto::encryption::associated_data(
+            game_id.as_bytes(),
+            &dh_keypair.public_key(),
+            santa_dh_public_key,
+        );
+        let encrypted_identity = crate::crypto::encrypt_data(
+            identity_info.as_bytes(),
+            &shared_secret,
+            &associated_data,
+        )?;
+
+        // Generate zero-knowledge proof binding the payload to the Santa's DH key
+        let zk_proof = self.zk_system.prove_reveal_phase(
+            game_id.as_bytes(),
+            participant_pk,
+            keypair.secret_bytes(),
+            santa_dh_public_key,
+            &dh_keypair.public_key(),
+            &encrypted_identity,
+        )?;
 
         // Create signature proving ownership of public key
-        let message = format!("reveal:{}", hex::encode(participant_pk));
-        let signature = keypair.sign(message.as_bytes());
+        let signature = keypair.sign(&RevealTransaction::signing_message(&game_id, participant_pk));
 
-        // Create REVEAL transaction
         let reveal_tx = RevealTransaction {
+            game_id,
             public_key: participant_pk.to_vec(),
             encrypted_identity,
             dh_public_key: dh_keypair.public_key().to_vec(),
+            santa_dh_public_key: santa_dh_public_key.to_vec(),
+            zk_proof,
             signature: signature.to_bytes().to_vec(),
-            timestamp: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
         };
 
-        // Serialize and store transaction
-        let tx_data = bincode::serialize(&reveal_tx)
-            .map_err(|e| crate::utils::Error::SerializationError(e.to_string()))?;
+        self.publish(Transaction::Reveal(reveal_tx)).await
+    }
+
+    /// Check a REVEAL record against the ENTER record of the revealer and the
+    /// CHOICE record that targets them
+    pub fn verify_reveal(
+        &self,
+        reveal: &RevealTransaction,
+        enter: &EnterTransaction,
+        choice: &ChoiceTransaction,
+    ) -> crate::utils::Result<bool> {
+        if enter.game_id != reveal.game_id
+            || choice.game_id != reveal.game_id
+            || enter.public_key != reveal.public_key
+            || choice.chosen_public_key != reveal.public_key
+            || choice.chooser_dh_public_key != reveal.santa_dh_public_key
+        {
+            return Ok(false);
+        }
 
-        let _record = self.storage.store_data(tx_data, RecordType::RevealTransaction).await?;
+        let inputs = match reveal.zk_proof.reveal_inputs() {
+            Ok(inputs) => inputs,
+            Err(_) => return Ok(false),
+        };
 
-        // Update participant state
-        if let Some(participant_state) = self.participants.get_mut(participant_pk) {
-            participant_state.has_revealed = true;
+        if inputs.commitment != enter.zk_proof.enter_inputs()?.commitment
+            || inputs.public_key[..] != reveal.public_key[..]
+            || inputs.santa_dh_public_key[..] != reveal.santa_dh_public_key[..]
+            || inputs.dh_public_key[..] != reveal.dh_public_key[..]
+            || inputs.payload_digest != payload_digest(&reveal.encrypted_identity)
+            || inputs.game != game_field(reveal.game_id.as_bytes())
+        {
+            return Ok(false);
         }
 
-        self.current_phase = Phase::Reveal;
-        Ok(())
+        self.zk_system.verify_proof(&reveal.zk_proof)
+    }
+
+    /// Game this instance plays
+    pub fn game_id(&self) -> &GameId {
+        self.state.game_id()
     }
 
     /// Get current phase of the protocol
     pub fn current_phase(&self) -> &Phase {
-        &self.current_phase
+        &self.state.phase
+    }
+
+    /// Game state as of the last refresh
+    pub fn state(&self) -> &ProtocolState {
+        &self.state
+    }
+
+    /// Storage backend the protocol reads from and writes to
+    pub fn storage(&self) -> &S {
+        &self.storage
     }
 
     /// Get list of available public keys for choosing
     pub async fn get_available_choices(&self) -> crate::utils::Result<Vec<Vec<u8>>> {
-        let all_keys = self.storage.get_all_public_keys().await?;
-        
-        // Filter out keys that have already been chosen
-        let available_keys = all_keys.into_iter()
-            .filter(|key| {
-                !self.participants.get(key)
-                    .map(|p| p.chosen_by.is_some())
-                    .unwrap_or(false)
-            })
-            .collect();
-
-        Ok(available_keys)
+        Ok(self.state.available_choices())
+    }
+
+    /// Store `transaction` if the game state accepts it, so nothing is
+    /// published that a replay would reject
+    async fn publish(&mut self, transaction: Transaction) -> crate::utils::Result<()> {
+        let mut next = self.state.clone();
+        next.apply(&transaction, (self.clock)())?;
+
+        self.storage.put(transaction.encode()?, transaction.record_type()).await?;
+        self.state = next;
+        Ok(())
     }
 }
@@ -1,4 +1,451 @@
-#[derive(Debug)]
+//! Game state derived from the stored transcript.
+//!
+//! `ProtocolState::apply` is a pure reducer: it checks one transaction against
+//! the state so far and folds it in. `ProtocolState::replay` rebuilds the state
+//! from every record of a game: it verifies all proofs in one batch, orders
+//! the records canonically and applies them one by one, so every client that
+//! sees the same records computes the same state. Anyone can publish records
+//! for a game, so invalid ones are skipped and listed in `rejected` rather
+//! than failing the replay. Records of other games are ignored.
+//!
+//! Each record is judged against the deadlines of the game config by a time
+//! its submitter cannot choose: on Filecoin the epoch of the block that
+//! included its announcement, a message signed by the sender's wallet and
+//! ordered by consensus; in local backends the time it was stored. A record
+//! that arrives late is rejected no matter when the replay runs. Records are
+//! applied in that order, ties broken by CID.
+//!
+//! ENTER and CHOICE nullifiers are derived from the player's identity secret
+//! and the game, so each player enters and chooses once per game. Conflicting
+//! records, such as two CHOICEs with one nullifier or two CHOICEs of the same
+//! participant, are settled by the canonical order: the first one applied
+//! wins and the rest are rejected. A rejected CHOICE does not use up its
+//! nullifier, so its author can choose again.
+//!
+//! Free choice can leave dead-ends when the CHOICE deadline passes: players
+//! nobody chose, and a last chooser whose only unchosen participant is
+//! themselves. Players who have not chosen get a RECOVERY round to pick one
+//! of the unchosen participants. A player who is the last one unchosen picks
+//! any chosen participant instead and is spliced in between them and their
+//! Santa, whose pick now names the recovering player.
+
+use super::game::{GameId, SignedGameConfig};
+use super::phases::Phase;
+use super::transaction::{EnterTransaction, RevealTransaction, Transaction};
+use crate::crypto::keypair::verify_signature;
+use crate::crypto::public_inputs::{field_to_bytes, game_field, payload_digest};
+use crate::crypto::{MerkleTree, ZKProofSystem};
+use crate::filecoin::StorageRecord;
+use ark_bn254::Fr;
+use cid::Cid;
+
+/// An entered player and the identity commitment from their ENTER proof
+#[derive(Debug, Clone)]
+pub struct Participant {
+    pub public_key: Vec<u8>,
+    pub commitment: Fr,
+    pub nullifier: Vec<u8>,
+}
+
+/// An accepted pick, made by a CHOICE or RECOVERY record
+#[derive(Debug, Clone)]
+pub struct Choice {
+    pub nullifier: Vec<u8>,
+    pub chosen_public_key: Vec<u8>,
+    pub chooser_dh_public_key: Vec<u8>,
+    /// Public key of the chooser if they picked in the recovery round
+    pub recovered_by: Option<Vec<u8>>,
+}
+
+/// A record that was left out of the state, and why
+#[derive(Debug, Clone)]
+pub struct Rejection {
+    pub record_id: String,
+    pub content_cid: Cid,
+    /// Nullifier of a rejected ENTER or CHOICE, so its author can find it
+    pub nullifier: Option<Vec<u8>>,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
 pub struct ProtocolState {
-    pub phase: super::phases::Phase,
+    /// Phase at the time the state was last built or refreshed
+    pub phase: Phase,
+    game_id: GameId,
+    config: SignedGameConfig,
+    participants: Vec<Participant>,
+    // Root of the ENTER set after each ENTER, any of which a CHOICE may commit to
+    known_roots: Vec<[u8; 32]>,
+    choices: Vec<Choice>,
+    reveals: Vec<RevealTransaction>,
+    rejected: Vec<Rejection>,
+}
+
+impl ProtocolState {
+    /// Empty state of the game started by `config`
+    pub fn new(config: SignedGameConfig) -> crate::utils::Result<Self> {
+        Ok(Self {
+            phase: Phase::Setup,
+            game_id: config.id()?,
+            config,
+            participants: Vec::new(),
+            known_roots: Vec::new(),
+            choices: Vec::new(),
+            reveals: Vec::new(),
+            rejected: Vec::new(),
+        })
+    }
+
+    /// Rebuild the state of the game started by `config` from the content of
+    /// every transaction record, as seen at unix time `now`
+    pub fn replay(
+        zk_system: &ZKProofSystem,
+        config: SignedGameConfig,
+        records: Vec<(StorageRecord, Vec<u8>)>,
+        now: u64,
+    ) -> crate::utils::Result<Self> {
+        let mut state = Self::new(config)?;
+        state.phase = state.phase_at(now);
+
+        let mut decoded = Vec::new();
+        for (record, data) in records {
+            match Transaction::decode(record.record_type, &data) {
+                Ok(transaction) if *transaction.game_id() != state.game_id => {}
+                Ok(transaction) => decoded.push((record, transaction)),
+                Err(e) => state.reject(&record, None, e.to_string()),
+            }
+        }
+
+        let proofs: Vec<_> = decoded.iter().map(|(_, tx)| tx.zk_proof().clone()).collect();
+        let failed = zk_system.verify_batch(&proofs)?;
+
+        let mut verified = Vec::new();
+        for (index, (record, transaction)) in decoded.into_iter().enumerate() {
+            if failed.binary_search(&index).is_ok() {
+                state.reject(&record, Some(&transaction), "Zero-knowledge proof does not verify".to_string());
+            } else {
+                verified.push((record, transaction));
+            }
+        }
+
+        // Canonical order: record time, then CID to break ties
+        verified.sort_by_key(|(record, _)| (state.record_time(record), record.content_cid.to_bytes()));
+
+        for (record, transaction) in &verified {
+            if let Err(e) = state.apply(transaction, state.record_time(record)) {
+                state.reject(record, Some(transaction), e.to_string());
+            }
+        }
+
+        Ok(state)
+    }
+
+    /// Check `transaction`, published at unix time `time`, against the current
+    /// state and apply it.
+    ///
+    /// Proofs are only checked against the state here; verifying them is up
+    /// to the caller, as `replay` does.
+    pub fn apply(&mut self, transaction: &Transaction, time: u64) -> crate::utils::Result<()> {
+        if *transaction.game_id() != self.game_id {
+            return Err(protocol_error(&format!("Transaction belongs to game {}", transaction.game_id())));
+        }
+        let game = game_field(self.game_id.as_bytes());
+
+        match transaction {
+            Transaction::Enter(tx) => {
+                self.expect_window(Phase::Enter, time, "ENTER")?;
+                if self.participants.len() >= self.config.config.max_participants as usize {
+                    return Err(protocol_error("Game is full"));
+                }
+
+                let inputs = tx.zk_proof.enter_inputs()?;
+                if inputs.public_key[..] != tx.public_key[..]
+                    || field_to_bytes(&inputs.nullifier)[..] != tx.nullifier[..]
+                    || inputs.game != game
+                {
+                    return Err(protocol_error("ENTER fields do not match its proof"));
+                }
+                let message = EnterTransaction::signing_message(
+                    &self.game_id, &field_to_bytes(&inputs.commitment), &tx.nullifier,
+                );
+                if !verify_signature(&tx.public_key, &message, &tx.signature) {
+                    return Err(protocol_error("ENTER signature does not verify"));
+                }
+                if self.participant(&tx.public_key).is_some() {
+                    return Err(protocol_error(&format!("{} has already entered", hex::encode(&tx.public_key))));
+                }
+                if self.participants.iter().any(|participant| participant.nullifier == tx.nullifier) {
+                    return Err(protocol_error("This identity has already entered"));
+                }
+
+                self.participants.push(Participant {
+                    public_key: tx.public_key.clone(),
+                    commitment: inputs.commitment,
+                    nullifier: tx.nullifier.clone(),
+                });
+                self.known_roots.push(field_to_bytes(&self.enter_set()?.root()));
+            }
+
+            Transaction::Choice(tx) => {
+                self.expect_window(Phase::Choice, time, "CHOICE")?;
+                self.expect_enough_participants()?;
+
+                let inputs = tx.zk_proof.choice_inputs()?;
+                if field_to_bytes(&inputs.merkle_root)[..] != tx.merkle_root[..]
+                    || field_to_bytes(&inputs.nullifier)[..] != tx.nullifier[..]
+                    || inputs.chosen_public_key[..] != tx.chosen_public_key[..]
+                    || inputs.dh_public_key[..] != tx.chooser_dh_public_key[..]
+                    || inputs.game != game
+                {
+                    return Err(protocol_error("CHOICE fields do not match its proof"));
+                }
+                if !self.known_roots.iter().any(|root| root[..] == tx.merkle_root[..]) {
+                    return Err(protocol_error("CHOICE commits to an unknown ENTER set"));
+                }
+                if self.participant(&tx.chosen_public_key).is_none() {
+                    return Err(protocol_error("Chosen participant has not entered"));
+                }
+                if self.choice_by(&tx.nullifier).is_some() {
+                    return Err(protocol_error("This participant has already chosen"));
+                }
+                self.expect_unused_dh_key(&tx.chooser_dh_public_key)?;
+                if self.choice_for(&tx.chosen_public_key).is_some() {
+                    return Err(protocol_error(&format!(
+                        "{} was already chosen by an earlier CHOICE, choose another participant",
+                        hex::encode(&tx.chosen_public_key)
+                    )));
+                }
+
+                self.choices.push(Choice {
+                    nullifier: tx.nullifier.clone(),
+                    chosen_public_key: tx.chosen_public_key.clone(),
+                    chooser_dh_public_key: tx.chooser_dh_public_key.clone(),
+                    recovered_by: None,
+                });
+            }
+
+            Transaction::Recovery(tx) => {
+                self.expect_window(Phase::Recovery, time, "RECOVERY")?;
+                self.expect_enough_participants()?;
+
+                let participant = self.participant(&tx.public_key)
+                    .ok_or_else(|| protocol_error("Recovering player has not entered"))?;
+                let inputs = tx.zk_proof.recovery_inputs()?;
+                if inputs.commitment != participant.commitment
+                    || inputs.public_key[..] != tx.public_key[..]
+                    || field_to_bytes(&inputs.nullifier)[..] != tx.nullifier[..]
+                    || inputs.chosen_public_key[..] != tx.chosen_public_key[..]
+                    || inputs.dh_public_key[..] != tx.chooser_dh_public_key[..]
+                    || inputs.game != game
+                {
+                    return Err(protocol_error("RECOVERY fields do not match its proof"));
+                }
+                if self.participant(&tx.chosen_public_key).is_none() {
+                    return Err(protocol_error("Chosen participant has not entered"));
+                }
+                if self.choice_by(&tx.nullifier).is_some() {
+                    return Err(protocol_error("This participant has already chosen"));
+                }
+                self.expect_unused_dh_key(&tx.chooser_dh_public_key)?;
+
+                let santa = self.choices.iter().position(|choice| choice.chosen_public_key == tx.chosen_public_key);
+                match santa {
+                    None => {}
+                    // The last unchosen player takes over the chosen participant's Santa
+                    Some(index) if self.is_last_unchosen(&tx.public_key) => {
+                        self.choices[index].chosen_public_key = tx.public_key.clone();
+                    }
+                    Some(_) => {
+                        return Err(protocol_error(&format!(
+                            "{} was already chosen, pick one of the unchosen participants",
+                            hex::encode(&tx.chosen_public_key)
+                        )));
+                    }
+                }
+
+                self.choices.push(Choice {
+                    nullifier: tx.nullifier.clone(),
+                    chosen_public_key: tx.chosen_public_key.clone(),
+                    chooser_dh_public_key: tx.chooser_dh_public_key.clone(),
+                    recovered_by: Some(tx.public_key.clone()),
+                });
+            }
+
+            Transaction::Reveal(tx) => {
+                self.expect_window(Phase::Reveal, time, "REVEAL")?;
+
+                let message = RevealTransaction::signing_message(&self.game_id, &tx.public_key);
+                if !verify_signature(&tx.public_key, &message, &tx.signature) {
+                    return Err(protocol_error("REVEAL signature does not verify"));
+                }
+                let participant = self.participant(&tx.public_key)
+                    .ok_or_else(|| protocol_error("Revealer has not entered"))?;
+                let answers_choice = self.choice_for(&tx.public_key)
+                    .is_some_and(|choice| choice.chooser_dh_public_key == tx.santa_dh_public_key);
+                if !answers_choice {
+                    return Err(protocol_error("REVEAL does not answer the CHOICE naming the revealer"));
+                }
+
+                let inputs = tx.zk_proof.reveal_inputs()?;
+                if inputs.commitment != participant.commitment
+                    || inputs.public_key[..] != tx.public_key[..]
+                    || inputs.santa_dh_public_key[..] != tx.santa_dh_public_key[..]
+                    || inputs.dh_public_key[..] != tx.dh_public_key[..]
+                    || inputs.payload_digest != payload_digest(&tx.encrypted_identity)
+                    || inputs.game != game
+                {
+                    return Err(protocol_error("REVEAL fields do not match its proof"));
+                }
+                if self.reveals.iter().any(|reveal| reveal.public_key == tx.public_key) {
+                    return Err(protocol_error("Participant has already revealed"));
+                }
+
+                self.reveals.push(tx.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn game_id(&self) -> &GameId {
+        &self.game_id
+    }
+
+    pub fn config(&self) -> &SignedGameConfig {
+        &self.config
+    }
+
+    /// Phase of the game at unix time `time`, from the config deadlines
+    pub fn phase_at(&self, time: u64) -> Phase {
+        self.config.config.phase_at(time)
+    }
+
+    /// Unix time `record` was published at: the epoch of its announcement
+    /// where the backend knows it, else the time the backend stored it
+    pub fn record_time(&self, record: &StorageRecord) -> u64 {
+        record.epoch.map_or(record.timestamp, |epoch| self.config.config.epoch_time(epoch))
+    }
+
+    /// Entered players, in the order their ENTER records were applied
+    pub fn participants(&self) -> &[Participant] {
+        &self.participants
+    }
+
+    pub fn participant(&self, public_key: &[u8]) -> Option<&Participant> {
+        self.participants.iter().find(|participant| participant.public_key == public_key)
+    }
+
+    /// Merkle tree over the commitments of every entered player
+    pub fn enter_set(&self) -> crate::utils::Result<MerkleTree> {
+        let commitments: Vec<Fr> = self.participants.iter().map(|participant| participant.commitment).collect();
+        MerkleTree::new(&commitments)
+    }
+
+    pub fn choices(&self) -> &[Choice] {
+        &self.choices
+    }
+
+    pub fn reveals(&self) -> &[RevealTransaction] {
+        &self.reveals
+    }
+
+    pub fn rejected(&self) -> &[Rejection] {
+        &self.rejected
+    }
+
+    /// The pick of `public_key`, if anyone has picked them
+    pub fn choice_for(&self, public_key: &[u8]) -> Option<&Choice> {
+        self.choices.iter().find(|choice| choice.chosen_public_key == public_key)
+    }
+
+    /// The accepted pick with `nullifier`
+    pub fn choice_by(&self, nullifier: &[u8]) -> Option<&Choice> {
+        self.choices.iter().find(|choice| choice.nullifier == nullifier)
+    }
+
+    /// The last rejected record carrying `nullifier`
+    pub fn rejection_for(&self, nullifier: &[u8]) -> Option<&Rejection> {
+        self.rejected.iter().rev().find(|rejection| rejection.nullifier.as_deref() == Some(nullifier))
+    }
+
+    /// The REVEAL addressed to the Santa holding `santa_dh_public_key`
+    pub fn reveal_for(&self, santa_dh_public_key: &[u8]) -> Option<&RevealTransaction> {
+        self.reveals.iter().find(|reveal| reveal.santa_dh_public_key == santa_dh_public_key)
+    }
+
+    /// Entered players nobody has chosen yet
+    pub fn available_choices(&self) -> Vec<Vec<u8>> {
+        self.participants.iter()
+            .filter(|participant| self.choice_for(&participant.public_key).is_none())
+            .map(|participant| participant.public_key.clone())
+            .collect()
+    }
+
+    /// Whether `public_key` is the only participant nobody has chosen, so
+    /// that if they have not chosen either they are left with themselves
+    pub fn is_last_unchosen(&self, public_key: &[u8]) -> bool {
+        self.available_choices() == [public_key.to_vec()]
+    }
+
+    /// Whether the game has enough players but some of them have no Santa,
+    /// which the recovery round is for
+    pub fn needs_recovery(&self) -> bool {
+        self.participants.len() >= self.config.config.min_participants as usize
+            && !self.available_choices().is_empty()
+    }
+
+    /// Participants a player who has not chosen may pick in the recovery
+    /// round: the unchosen ones but themselves, or any chosen one when they
+    /// are the last unchosen participant
+    pub fn recovery_choices(&self, public_key: &[u8]) -> Vec<Vec<u8>> {
+        if self.is_last_unchosen(public_key) {
+            return self.choices.iter().map(|choice| choice.chosen_public_key.clone()).collect();
+        }
+
+        self.available_choices()
+            .into_iter()
+            .filter(|candidate| candidate != public_key)
+            .collect()
+    }
+
+    fn expect_enough_participants(&self) -> crate::utils::Result<()> {
+        let min_participants = self.config.config.min_participants as usize;
+        if self.participants.len() < min_participants {
+            return Err(protocol_error(&format!(
+                "Game closed with fewer than {} participants", min_participants
+            )));
+        }
+        Ok(())
+    }
+
+    // Reveals are found by the chooser's DH key, so no two choices may share one
+    fn expect_unused_dh_key(&self, dh_public_key: &[u8]) -> crate::utils::Result<()> {
+        if self.choices.iter().any(|choice| choice.chooser_dh_public_key == dh_public_key) {
+            return Err(protocol_error("DH key was already published by an earlier CHOICE"));
+        }
+        Ok(())
+    }
+
+    fn expect_window(&self, phase: Phase, timestamp: u64, action: &str) -> crate::utils::Result<()> {
+        let actual = self.phase_at(timestamp);
+        if actual != phase {
+            return Err(protocol_error(&format!(
+                "{} published at {} falls in the {:?} phase", action, timestamp, actual
+            )));
+        }
+        Ok(())
+    }
+
+    fn reject(&mut self, record: &StorageRecord, transaction: Option<&Transaction>, reason: String) {
+        self.rejected.push(Rejection {
+            record_id: record.id.clone(),
+            content_cid: record.content_cid,
+            nullifier: transaction.and_then(|tx| tx.nullifier()).map(|nullifier| nullifier.to_vec()),
+            reason,
+        });
+    }
+}
+
+fn protocol_error(message: &str) -> crate::utils::Error {
+    crate::utils::Error::ProtocolError(message.to_string())
 }
@@ -0,0 +1,159 @@
+use super::game::GameId;
+use crate::crypto::ZKProof;
+use crate::filecoin::RecordType;
+use serde::{Deserialize, Serialize};
+
+/// ENTER record. `nullifier` is derived from the entrant's identity secret and
+/// the game, so the same secret can enter a game only once. `signature` shows
+/// that the holder of `public_key` entered, not just someone who knows the
+/// secret the circuits derive from it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnterTransaction {
+    pub game_id: GameId,
+    pub public_key: Vec<u8>,
+    pub nullifier: Vec<u8>,
+    pub zk_proof: ZKProof,
+    pub signature: Vec<u8>,
+}
+
+impl EnterTransaction {
+    /// Message the entrant signs with their ed25519 key, over the identity
+    /// commitment of the proof
+    pub fn signing_message(game_id: &GameId, commitment: &[u8], nullifier: &[u8]) -> Vec<u8> {
+        format!("enter:{}:{}:{}", game_id, hex::encode(commitment), hex::encode(nullifier)).into_bytes()
+    }
+}
+
+/// CHOICE record. Carries nothing that identifies the chooser: the proof only
+/// shows membership in the ENTER set committed to by `merkle_root`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChoiceTransaction {
+    pub game_id: GameId,
+    pub merkle_root: Vec<u8>,
+    pub nullifier: Vec<u8>,
+    pub chosen_public_key: Vec<u8>,
+    pub chooser_dh_public_key: Vec<u8>,
+    pub zk_proof: ZKProof,
+}
+
+/// RECOVERY record, published after the CHOICE deadline by a player who has
+/// not chosen. Names the player, who spends their CHOICE nullifier on
+/// `chosen_public_key`; see `ProtocolState::apply` for the picks it allows.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecoveryTransaction {
+    pub game_id: GameId,
+    pub public_key: Vec<u8>,
+    pub nullifier: Vec<u8>,
+    pub chosen_public_key: Vec<u8>,
+    pub chooser_dh_public_key: Vec<u8>,
+    pub zk_proof: ZKProof,
+}
+
+/// REVEAL record. `santa_dh_public_key` names the CHOICE record the payload is
+/// addressed to; `zk_proof` ties both DH keys and the payload to the
+/// revealer's ENTER commitment.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevealTransaction {
+    pub game_id: GameId,
+    pub public_key: Vec<u8>,
+    pub encrypted_identity: Vec<u8>,
+    pub dh_public_key: Vec<u8>,
+    pub santa_dh_public_key: Vec<u8>,
+    pub zk_proof: ZKProof,
+    pub signature: Vec<u8>,
+}
+
+impl RevealTransaction {
+    /// Message the revealer signs with their ed25519 key
+    pub fn signing_message(game_id: &GameId, public_key: &[u8]) -> Vec<u8> {
+        format!("reveal:{}:{}", game_id, hex::encode(public_key)).into_bytes()
+    }
+}
+
+/// Any game transaction, as stored in one record
+#[derive(Debug, Clone)]
+pub enum Transaction {
+    Enter(EnterTransaction),
+    Choice(ChoiceTransaction),
+    Recovery(RecoveryTransaction),
+    Reveal(RevealTransaction),
+}
+
+impl Transaction {
+    /// Record types holding game transactions
+    pub const RECORD_TYPES: [RecordType; 4] = [
+        RecordType::EnterTransaction,
+        RecordType::ChoiceTransaction,
+        RecordType::RecoveryTransaction,
+        RecordType::RevealTransaction,
+    ];
+
+    /// Decode the content of a record of type `record_type`
+    pub fn decode(record_type: RecordType, data: &[u8]) -> crate::utils::Result<Self> {
+        let transaction = match record_type {
+            RecordType::EnterTransaction => Transaction::Enter(deserialize(data)?),
+            RecordType::ChoiceTransaction => Transaction::Choice(deserialize(data)?),
+            RecordType::RecoveryTransaction => Transaction::Recovery(deserialize(data)?),
+            RecordType::RevealTransaction => Transaction::Reveal(deserialize(data)?),
+            RecordType::CeremonyContribution | RecordType::GameConfig => {
+                return Err(crate::utils::Error::SerializationError(format!(
+                    "{:?} records are not game transactions", record_type
+                )));
+            }
+        };
+
+        Ok(transaction)
+    }
+
+    pub fn encode(&self) -> crate::utils::Result<Vec<u8>> {
+        let encoded = match self {
+            Transaction::Enter(tx) => bincode::serialize(tx),
+            Transaction::Choice(tx) => bincode::serialize(tx),
+            Transaction::Recovery(tx) => bincode::serialize(tx),
+            Transaction::Reveal(tx) => bincode::serialize(tx),
+        };
+
+        encoded.map_err(|e| crate::utils::Error::SerializationError(e.to_string()))
+    }
+
+    pub fn record_type(&self) -> RecordType {
+        match self {
+            Transaction::Enter(_) => RecordType::EnterTransaction,
+            Transaction::Choice(_) => RecordType::ChoiceTransaction,
+            Transaction::Recovery(_) => RecordType::RecoveryTransaction,
+            Transaction::Reveal(_) => RecordType::RevealTransaction,
+        }
+    }
+
+    pub fn game_id(&self) -> &GameId {
+        match self {
+            Transaction::Enter(tx) => &tx.game_id,
+            Transaction::Choice(tx) => &tx.game_id,
+            Transaction::Recovery(tx) => &tx.game_id,
+            Transaction::Reveal(tx) => &tx.game_id,
+        }
+    }
+
+    /// Nullifier of an ENTER, CHOICE or RECOVERY
+    pub fn nullifier(&self) -> Option<&[u8]> {
+        match self {
+            Transaction::Enter(tx) => Some(&tx.nullifier),
+            Transaction::Choice(tx) => Some(&tx.nullifier),
+            Transaction::Recovery(tx) => Some(&tx.nullifier),
+            Transaction::Reveal(_) => None,
+        }
+    }
+
+    pub fn zk_proof(&self) -> &ZKProof {
+        match self {
+            Transaction::Enter(tx) => &tx.zk_proof,
+            Transaction::Choice(tx) => &tx.zk_proof,
+            Transaction::Recovery(tx) => &tx.zk_proof,
+            Transaction::Reveal(tx) => &tx.zk_proof,
+        }
+    }
+}
+
+fn deserialize<T: serde::de::DeserializeOwned>(data: &[u8]) -> crate::utils::Result<T> {
+    bincode::deserialize(data).map_err(|e| crate::utils::Error::SerializationError(e.to_string()))
+}
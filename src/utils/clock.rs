@@ -0,0 +1,16 @@
+use std::sync::Arc;
+
+/// Source of the current unix time
+pub type Clock = Arc<dyn Fn() -> u64 + Send + Sync>;
+
+/// Clock reading the system time
+pub fn system_clock() -> Clock {
+    Arc::new(unix_now)
+}
+
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
@@ -3,14 +3,21 @@ use thiserror::Error;
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Crypto error: {0}")]
-    Crypto(String),
+    CryptoError(String),
+    #[error("Authentication failed: ciphertext, associated data or key do not match")]
+    AuthenticationFailed,
     #[error("Serialization error: {0}")]
-    Serialization(String),
+    SerializationError(String),
     #[error("Storage error: {0}")]
-    Storage(String),
+    StorageError(String),
+    #[error("Integrity error: {0}")]
+    IntegrityError(String),
     #[error("Protocol error: {0}")]
-    Protocol(String),
+    ProtocolError(String),
+    #[error("File error: {0}")]
+    FileError(String),
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
-
@@ -1,3 +1,4 @@
+pub mod clock;
 pub mod error;
+pub use clock::Clock;
 pub use error::{Error, Result};
-
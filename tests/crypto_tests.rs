@@ -0,0 +1,356 @@
+use zkret_santa_filecoin::crypto::{decrypt_data, encrypt_data, encryption, keystore, DHKeyExchange, KeyPair, Keystore, ZKProofSystem};
+use zkret_santa_filecoin::Error;
+
+#[test]
+fn test_enter_proof_round_trip() {
+    use zkret_santa_filecoin::crypto::circuits::{enter_nullifier, identity_secret};
+    use zkret_santa_filecoin::crypto::public_inputs::{field_to_bytes, game_field};
+
+    let zk_system = ZKProofSystem::new().unwrap();
+    let keypair = KeyPair::generate();
+    let (public_hex, secret_hex) = keypair.to_hex_strings();
+    let public_key = hex::decode(public_hex).unwrap();
+    let secret_key = hex::decode(secret_hex).unwrap();
+
+    let proof = zk_system.prove_enter_phase(&[3u8; 32], &public_key, &secret_key).unwrap();
+    assert!(zk_system.verify_proof(&proof).unwrap());
+
+    let inputs = proof.enter_inputs().unwrap();
+    assert_eq!(inputs.public_key.to_vec(), public_key);
+    assert_eq!(inputs.game, game_field(&[3u8; 32]));
+    let secret = identity_secret(&secret_key);
+    assert_eq!(inputs.nullifier, enter_nullifier(secret, game_field(&[3u8; 32])));
+
+    // A proof must not verify for somebody else's public key
+    let mut forged = proof.clone();
+    forged.public_inputs.elements.swap(0, 1);
+    assert!(!zk_system.verify_proof(&forged).unwrap());
+
+    // Nor for another game
+    let mut replayed = proof.clone();
+    replayed.public_inputs.elements[4] = field_to_bytes(&game_field(&[4u8; 32]));
+    assert!(!zk_system.verify_proof(&replayed).unwrap());
+
+    // Nor with the nullifier the same secret has in another game
+    let mut renullified = proof.clone();
+    renullified.public_inputs.elements[3] = field_to_bytes(&enter_nullifier(secret, game_field(&[4u8; 32])));
+    assert!(!zk_system.verify_proof(&renullified).unwrap());
+}
+
+#[test]
+fn test_choice_proof_hides_chooser() {
+    use zkret_santa_filecoin::crypto::{circuits, public_inputs, MerkleTree};
+
+    let zk_system = ZKProofSystem::new().unwrap();
+    let participants: Vec<(Vec<u8>, Vec<u8>)> = (0..4)
+        .map(|_| {
+            let (public_hex, secret_hex) = KeyPair::generate().to_hex_strings();
+            (hex::decode(public_hex).unwrap(), hex::decode(secret_hex).unwrap())
+        })
+        .collect();
+
+    let commitments: Vec<_> = participants.iter()
+        .map(|(pk, sk)| {
+            circuits::identity_commitment(
+                circuits::identity_secret(sk),
+                public_inputs::pack_key(pk).unwrap(),
+            )
+        })
+        .collect();
+    let enter_set = MerkleTree::new(&commitments).unwrap();
+
+    let (chooser_pk, chooser_sk) = &participants[0];
+    let chosen_pk = &participants[1].0;
+    let dh_public_key = [7u8; 32];
+
+    let proof = zk_system
+        .prove_choice_phase(&[3u8; 32], chooser_pk, chooser_sk, chosen_pk, &dh_public_key, &enter_set)
+        .unwrap();
+    assert!(zk_system.verify_proof(&proof).unwrap());
+    let inputs = proof.choice_inputs().unwrap();
+    assert_eq!(inputs.merkle_root, enter_set.root());
+    assert_eq!(inputs.chosen_public_key.to_vec(), *chosen_pk);
+
+    // The same player gets an unrelated nullifier in another game
+    let other_game = zk_system
+        .prove_choice_phase(&[4u8; 32], chooser_pk, chooser_sk, chosen_pk, &dh_public_key, &enter_set)
+        .unwrap();
+    assert_ne!(other_game.choice_inputs().unwrap().nullifier, inputs.nullifier);
+
+    // Choosing yourself is rejected before any proof is produced
+    assert!(zk_system
+        .prove_choice_phase(&[3u8; 32], chooser_pk, chooser_sk, chooser_pk, &dh_public_key, &enter_set)
+        .is_err());
+}
+
+#[test]
+fn test_reveal_proof_binds_payload() {
+    use zkret_santa_filecoin::crypto::public_inputs::{field_to_bytes, payload_digest};
+
+    let zk_system = ZKProofSystem::new().unwrap();
+    let (public_hex, secret_hex) = KeyPair::generate().to_hex_strings();
+    let public_key = hex::decode(public_hex).unwrap();
+    let secret_key = hex::decode(secret_hex).unwrap();
+
+    let proof = zk_system
+        .prove_reveal_phase(&[3u8; 32], &public_key, &secret_key, &[1u8; 32], &[2u8; 32], b"ciphertext")
+        .unwrap();
+    assert!(zk_system.verify_proof(&proof).unwrap());
+    assert_eq!(proof.reveal_inputs().unwrap().payload_digest, payload_digest(b"ciphertext"));
+
+    // Swapping in another payload digest invalidates the proof
+    let mut forged = proof.clone();
+    forged.public_inputs.elements[7] = field_to_bytes(&payload_digest(b"other"));
+    assert!(!zk_system.verify_proof(&forged).unwrap());
+}
+
+#[test]
+fn test_params_dir_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let zk_system = ZKProofSystem::new().unwrap();
+    let manifest = zk_system.write_params_dir(dir.path()).unwrap();
+
+    // Another participant loading the same directory verifies our proofs
+    let loaded = ZKProofSystem::from_params_dir(dir.path()).unwrap();
+    let (public_hex, secret_hex) = KeyPair::generate().to_hex_strings();
+    let proof = zk_system
+        .prove_enter_phase(&[3u8; 32], &hex::decode(public_hex).unwrap(), &hex::decode(secret_hex).unwrap())
+        .unwrap();
+    assert!(loaded.verify_proof(&proof).unwrap());
+
+    let inspected = zkret_santa_filecoin::crypto::params::inspect_params(dir.path()).unwrap();
+    assert_eq!(inspected.params_hash, manifest.params_hash);
+    assert_eq!(zk_system.params_hash(), manifest.params_hash);
+    assert_eq!(loaded.params_hash(), manifest.params_hash);
+
+    // Tampered key files are rejected
+    std::fs::write(dir.path().join("enter.vk"), b"tampered").unwrap();
+    assert!(ZKProofSystem::from_params_dir(dir.path()).is_err());
+}
+
+#[test]
+fn test_ceremony_contribution_verifies() {
+    use zkret_santa_filecoin::crypto::ceremony::{contribute_circuit, verify_circuit_contribution};
+    use zkret_santa_filecoin::crypto::circuits::EnterCircuit;
+    use zkret_santa_filecoin::crypto::ProofType;
+    use ark_bn254::Bn254;
+    use ark_groth16::Groth16;
+    use ark_snark::CircuitSpecificSetupSNARK;
+
+    let mut rng = rand::rngs::OsRng;
+    let (proving_key, _) = Groth16::<Bn254>::setup(EnterCircuit::blank(), &mut rng).unwrap();
+
+    let (updated, contribution) =
+        contribute_circuit(ProofType::EnterPhase, &proving_key, b"context", &mut rng).unwrap();
+    assert_ne!(updated.delta_g1, proving_key.delta_g1);
+    assert!(verify_circuit_contribution(&proving_key, &updated, &contribution, b"context").unwrap());
+
+    // The proof of knowledge is bound to the ceremony context
+    assert!(!verify_circuit_contribution(&proving_key, &updated, &contribution, b"other").unwrap());
+}
+
+#[test]
+fn test_powers_of_tau_contributions_and_derived_keys() {
+    use ark_bn254::{Bn254, Fr};
+    use ark_groth16::Groth16;
+    use ark_relations::lc;
+    use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+    use ark_snark::SNARK;
+    use zkret_santa_filecoin::crypto::ceremony::{contribute_circuit, verify_circuit_contribution};
+    use zkret_santa_filecoin::crypto::powers_of_tau::{required_size, verify_contribution, PowersOfTau};
+    use zkret_santa_filecoin::crypto::ProofType;
+
+    // Knowledge of x and y with x·y·y = z for a public z
+    #[derive(Clone)]
+    struct Cube {
+        x: Option<Fr>,
+        y: Option<Fr>,
+        z: Option<Fr>,
+    }
+
+    impl ConstraintSynthesizer<Fr> for Cube {
+        fn generate_constraints(self, cs: ConstraintSystemRef<Fr>) -> Result<(), SynthesisError> {
+            let missing = || SynthesisError::AssignmentMissing;
+            let z = cs.new_input_variable(|| self.z.ok_or_else(missing))?;
+            let x = cs.new_witness_variable(|| self.x.ok_or_else(missing))?;
+            let y = cs.new_witness_variable(|| self.y.ok_or_else(missing))?;
+            let xy = cs.new_witness_variable(|| Ok(self.x.ok_or_else(missing)? * self.y.ok_or_else(missing)?))?;
+            cs.enforce_constraint(lc!() + x, lc!() + y, lc!() + xy)?;
+            cs.enforce_constraint(lc!() + xy, lc!() + y, lc!() + z)?;
+            Ok(())
+        }
+    }
+
+    let blank = Cube { x: None, y: None, z: None };
+    let size = required_size(blank.clone()).unwrap();
+    let mut rng = rand::rngs::OsRng;
+
+    let initial = PowersOfTau::new(size).unwrap();
+    let (first, first_contribution) = initial.contribute(b"first", &mut rng).unwrap();
+    let (second, second_contribution) = first.contribute(b"second", &mut rng).unwrap();
+    assert!(verify_contribution(&initial, &first, &first_contribution, b"first").unwrap());
+    assert!(verify_contribution(&first, &second, &second_contribution, b"second").unwrap());
+
+    // Proofs are bound to their context and to the powers they were applied to
+    assert!(!verify_contribution(&first, &second, &second_contribution, b"first").unwrap());
+    assert!(!verify_contribution(&initial, &second, &second_contribution, b"second").unwrap());
+    let mut broken = second.clone();
+    broken.tau_g1.swap(2, 3);
+    assert!(!verify_contribution(&first, &broken, &second_contribution, b"second").unwrap());
+
+    // Keys derived from the powers, with δ moved in phase 2, prove and verify
+    let derived = second.derive_proving_key(blank.clone()).unwrap();
+    let (proving_key, contribution) =
+        contribute_circuit(ProofType::EnterPhase, &derived, b"phase 2", &mut rng).unwrap();
+    assert!(verify_circuit_contribution(&derived, &proving_key, &contribution, b"phase 2").unwrap());
+
+    let (x, y) = (Fr::from(3u64), Fr::from(5u64));
+    let circuit = Cube { x: Some(x), y: Some(y), z: Some(x * y * y) };
+    let proof = Groth16::<Bn254>::prove(&proving_key, circuit, &mut rng).unwrap();
+    assert!(Groth16::<Bn254>::verify(&proving_key.vk, &[x * y * y], &proof).unwrap());
+    assert!(!Groth16::<Bn254>::verify(&proving_key.vk, &[x * y], &proof).unwrap());
+
+    // Powers that are too small for the circuit are refused
+    assert!(PowersOfTau::new(size / 2).unwrap().derive_proving_key(blank).is_err());
+}
+
+#[test]
+fn test_public_input_encoding_round_trips() {
+    use zkret_santa_filecoin::crypto::public_inputs::{pack_key, unpack_key, PublicInputs, PUBLIC_INPUTS_VERSION};
+    use zkret_santa_filecoin::crypto::ProofType;
+
+    let key: Vec<u8> = (0u8..32).map(|i| i.wrapping_mul(37)).collect();
+    let limbs = pack_key(&key).unwrap();
+    assert_eq!(unpack_key(limbs).unwrap().to_vec(), key);
+
+    let encoded = PublicInputs::from_field_elements(&[limbs[0], limbs[1], limbs[0], limbs[1], limbs[0]]);
+    assert_eq!(encoded.version, PUBLIC_INPUTS_VERSION);
+    assert_eq!(encoded.to_field_elements(ProofType::EnterPhase).unwrap()[1], limbs[1]);
+
+    // Wrong arity, unknown versions and non-canonical elements are rejected
+    assert!(encoded.to_field_elements(ProofType::ChoicePhase).is_err());
+    let mut future = encoded.clone();
+    future.version = PUBLIC_INPUTS_VERSION + 1;
+    assert!(future.to_field_elements(ProofType::EnterPhase).is_err());
+    let mut overflow = encoded.clone();
+    overflow.elements[2] = [0xff; 32];
+    assert!(overflow.to_field_elements(ProofType::EnterPhase).is_err());
+}
+
+#[test]
+fn test_verify_batch_reports_bad_indices() {
+    let zk_system = ZKProofSystem::new().unwrap();
+
+    let mut proofs = Vec::new();
+    for _ in 0..4 {
+        let (public_hex, secret_hex) = KeyPair::generate().to_hex_strings();
+        let public_key = hex::decode(public_hex).unwrap();
+        let secret_key = hex::decode(secret_hex).unwrap();
+        proofs.push(zk_system.prove_enter_phase(&[3u8; 32], &public_key, &secret_key).unwrap());
+        proofs.push(
+            zk_system
+                .prove_reveal_phase(&[3u8; 32], &public_key, &secret_key, &[1u8; 32], &[2u8; 32], b"payload")
+                .unwrap(),
+        );
+    }
+    assert!(zk_system.verify_batch(&proofs).unwrap().is_empty());
+
+    // One proof with swapped inputs and one that cannot be decoded
+    proofs[2].public_inputs.elements.swap(0, 1);
+    proofs[5].proof_data.truncate(3);
+    assert_eq!(zk_system.verify_batch(&proofs).unwrap(), vec![2, 5]);
+}
+
+#[test]
+fn test_dh_shared_secret_is_per_game() {
+    let santa = DHKeyExchange::generate();
+    let santee = DHKeyExchange::generate();
+
+    let santa_key = santa.compute_shared_secret(&santee.public_key(), b"game-1").unwrap();
+    let santee_key = santee.compute_shared_secret(&santa.public_key(), b"game-1").unwrap();
+    assert_eq!(*santa_key, *santee_key);
+
+    let other_game = santa.compute_shared_secret(&santee.public_key(), b"game-2").unwrap();
+    assert_ne!(*santa_key, *other_game);
+
+    let restored = DHKeyExchange::from_secret_bytes(&*santa.secret_key()).unwrap();
+    assert_eq!(restored.public_key(), santa.public_key());
+
+    // The identity point (and other low-order points) must be rejected
+    assert!(santa.compute_shared_secret(&[0u8; 32], b"game-1").is_err());
+    let mut order_two = [0u8; 32];
+    order_two[0] = 1;
+    assert!(santa.compute_shared_secret(&order_two, b"game-1").is_err());
+}
+
+#[test]
+fn test_envelope_rejects_tampering() {
+    let santa = DHKeyExchange::generate();
+    let santee = DHKeyExchange::generate();
+    let key = santee.compute_shared_secret(&santa.public_key(), b"game-1").unwrap();
+    let associated_data = encryption::associated_data(b"game-1", &santee.public_key(), &santa.public_key());
+
+    let envelope = encrypt_data(b"1 Main St", &key, &associated_data).unwrap();
+    assert_eq!(envelope[0], encryption::ENVELOPE_VERSION);
+
+    let santa_key = santa.compute_shared_secret(&santee.public_key(), b"game-1").unwrap();
+    assert_eq!(decrypt_data(&envelope, &santa_key, &associated_data).unwrap(), b"1 Main St");
+
+    // Flipped ciphertext bit
+    let mut tampered = envelope.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert!(matches!(
+        decrypt_data(&tampered, &key, &associated_data),
+        Err(Error::AuthenticationFailed)
+    ));
+
+    // Envelope replayed into another game
+    let other_game = encryption::associated_data(b"game-2", &santee.public_key(), &santa.public_key());
+    assert!(matches!(
+        decrypt_data(&envelope, &key, &other_game),
+        Err(Error::AuthenticationFailed)
+    ));
+
+    // Unknown version is not an authentication failure
+    let mut future = envelope.clone();
+    future[0] = 2;
+    assert!(matches!(
+        decrypt_data(&future, &key, &associated_data),
+        Err(Error::CryptoError(_))
+    ));
+}
+
+#[test]
+fn test_keystore_round_trip_and_migration() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("key.zkret");
+
+    let keypair = KeyPair::generate();
+    let (public_hex, secret_hex) = keypair.to_hex_strings();
+    let mut keystore = Keystore::new(KeyPair::from_hex_strings(&public_hex, &secret_hex).unwrap());
+    let dh_keypair = DHKeyExchange::generate();
+    let dh_public_key = dh_keypair.public_key();
+    keystore.insert_dh_key(keystore::DEFAULT_GAME, dh_keypair);
+    keystore.save(&path, "correct horse").unwrap();
+
+    // Public key is readable without the passphrase, secrets are not in the clear
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(Keystore::read_public_key(&path).unwrap(), public_hex);
+    assert!(!contents.contains(&secret_hex));
+
+    let loaded = Keystore::load(&path, "correct horse").unwrap();
+    assert_eq!(loaded.keypair().to_hex_strings(), keypair.to_hex_strings());
+    assert_eq!(loaded.dh_key(keystore::DEFAULT_GAME).unwrap().public_key(), dh_public_key);
+    assert!(matches!(Keystore::load(&path, "wrong"), Err(Error::AuthenticationFailed)));
+
+    // Plaintext files from older versions
+    let legacy_path = dir.path().join("legacy.zkret");
+    std::fs::write(&legacy_path, format!("{}:{}", public_hex, secret_hex)).unwrap();
+    std::fs::write(legacy_path.with_extension("dh"), hex::encode(DHKeyExchange::generate().secret_key())).unwrap();
+    assert!(Keystore::load(&legacy_path, "pw").is_err());
+
+    let migrated = Keystore::migrate_legacy(&legacy_path, "pw").unwrap();
+    assert!(!legacy_path.with_extension("dh").exists());
+    assert_eq!(migrated.games().collect::<Vec<_>>(), vec![keystore::DEFAULT_GAME]);
+    assert_eq!(Keystore::load(&legacy_path, "pw").unwrap().keypair().to_hex_strings(), keypair.to_hex_strings());
+}
@@ -6,3 +6,550 @@ fn test_keypair() {
     let sig = kp.sign(msg);
     assert!(kp.verify(msg, &sig));
 }
+
+#[tokio::test]
+async fn test_local_storage_shared_between_handles() {
+    use zkret_santa_filecoin::filecoin::backend::compute_cid;
+    use zkret_santa_filecoin::filecoin::{LocalStorage, RecordType, StorageBackend};
+    use zkret_santa_filecoin::Error;
+
+    let dir = tempfile::tempdir().unwrap();
+
+    // Several writers on the same directory, as separate processes would be
+    let mut writers = Vec::new();
+    for i in 0..8u8 {
+        let mut storage = LocalStorage::open(dir.path()).unwrap();
+        writers.push(tokio::task::spawn_blocking(move || {
+            tokio::runtime::Handle::current().block_on(async move {
+                storage.put(vec![i; 64], RecordType::EnterTransaction).await.unwrap()
+            })
+        }));
+    }
+    for writer in writers {
+        writer.await.unwrap();
+    }
+
+    let mut reader = LocalStorage::open(dir.path()).unwrap();
+    let records = reader.list(RecordType::EnterTransaction).await.unwrap();
+    assert_eq!(records.len(), 8);
+    assert!(reader.list(RecordType::ChoiceTransaction).await.unwrap().is_empty());
+
+    let data = vec![3u8; 64];
+    let cid = compute_cid(&data);
+    assert!(records.iter().any(|record| record.content_cid == cid));
+    assert_eq!(reader.get(&cid).await.unwrap(), data);
+
+    // Same content again maps to the same record
+    let again = reader.put(data, RecordType::EnterTransaction).await.unwrap();
+    assert_eq!(again.content_cid, cid);
+    assert_eq!(reader.list(RecordType::EnterTransaction).await.unwrap().len(), 8);
+
+    // A corrupted block is detected on read
+    std::fs::write(dir.path().join("blocks").join(cid.to_string()), b"garbage").unwrap();
+    assert!(matches!(reader.get(&cid).await, Err(Error::IntegrityError(_))));
+}
+
+#[test]
+fn test_cids_are_computed_and_verified_locally() {
+    use zkret_santa_filecoin::filecoin::backend::{compute_cid, verify_cid, RAW_CODEC, SHA2_256_CODE};
+    use zkret_santa_filecoin::Error;
+
+    let data = b"enter transaction bytes";
+    let cid = compute_cid(data);
+    assert_eq!(cid.version(), cid::Version::V1);
+    assert_eq!(cid.codec(), RAW_CODEC);
+    assert_eq!(cid.hash().code(), SHA2_256_CODE);
+    assert_eq!(cid.to_string(), compute_cid(data).to_string());
+
+    assert!(verify_cid(&cid, data).is_ok());
+    assert!(matches!(verify_cid(&cid, b"other bytes"), Err(Error::IntegrityError(_))));
+}
+
+#[tokio::test]
+async fn test_car_export_import_round_trip() {
+    use zkret_santa_filecoin::filecoin::car::{export_car, import_car, read_car};
+    use zkret_santa_filecoin::filecoin::{MemoryStorage, RecordType, StorageBackend};
+    use zkret_santa_filecoin::Error;
+    use std::sync::Arc;
+
+    let mut source = MemoryStorage::new();
+    source.set_clock(Arc::new(|| 1_000));
+    source.put(b"enter-1".to_vec(), RecordType::EnterTransaction).await.unwrap();
+    source.put(b"enter-2".to_vec(), RecordType::EnterTransaction).await.unwrap();
+    source.put(b"choice-1".to_vec(), RecordType::ChoiceTransaction).await.unwrap();
+    source.put(b"reveal-1".to_vec(), RecordType::RevealTransaction).await.unwrap();
+
+    let mut car = Vec::new();
+    let root = export_car(&source, |_, _| true, &mut car).await.unwrap();
+
+    let parsed = read_car(car.as_slice()).unwrap();
+    assert_eq!(parsed.root, root);
+    assert_eq!(parsed.manifest().unwrap().records.len(), 4);
+
+    // Imported records get the target's time, not the one in the manifest
+    let mut target = MemoryStorage::new();
+    target.set_clock(Arc::new(|| 5_000));
+    let imported = import_car(&mut target, car.as_slice()).await.unwrap();
+    assert_eq!(imported.len(), 4);
+    assert!(imported.iter().all(|record| record.timestamp == 5_000 && record.epoch.is_none()));
+    for record_type in [RecordType::EnterTransaction, RecordType::ChoiceTransaction, RecordType::RevealTransaction] {
+        let mut expected: Vec<_> = source.list(record_type).await.unwrap()
+            .into_iter().map(|record| record.content_cid).collect();
+        let mut actual: Vec<_> = target.list(record_type).await.unwrap()
+            .into_iter().map(|record| record.content_cid).collect();
+        expected.sort();
+        actual.sort();
+        assert_eq!(expected, actual);
+    }
+
+    // Only the records the filter keeps are exported
+    let mut filtered = Vec::new();
+    export_car(&source, |_, data| data.starts_with(b"enter"), &mut filtered).await.unwrap();
+    assert_eq!(read_car(filtered.as_slice()).unwrap().manifest().unwrap().records.len(), 2);
+
+    // Flipping a byte of the last block breaks its CID
+    let mut tampered = car.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert!(matches!(read_car(tampered.as_slice()), Err(Error::IntegrityError(_))));
+}
+
+#[test]
+fn test_record_index_persists_and_rebuilds() {
+    use zkret_santa_filecoin::filecoin::backend::compute_cid;
+    use zkret_santa_filecoin::filecoin::{RecordIndex, RecordType, StorageRecord};
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("records.jsonl");
+    let record = |data: &[u8], record_type| StorageRecord {
+        id: hex::encode(data),
+        content_cid: compute_cid(data),
+        timestamp: 0,
+        record_type,
+        epoch: None,
+    };
+
+    let index = RecordIndex::open(&path).unwrap();
+    index.insert(record(b"alice", RecordType::EnterTransaction)).unwrap();
+    index.insert(record(b"bob", RecordType::EnterTransaction)).unwrap();
+    index.insert(record(b"choice", RecordType::ChoiceTransaction)).unwrap();
+
+    // Inserting the same content again keeps the first record
+    let duplicate = index.insert(StorageRecord {
+        id: "other".to_string(),
+        ..record(b"alice", RecordType::EnterTransaction)
+    }).unwrap();
+    assert_eq!(duplicate.id, hex::encode(b"alice"));
+
+    // A fresh handle, as in a later process, sees everything
+    let reopened = RecordIndex::open(&path).unwrap();
+    assert_eq!(reopened.list(RecordType::EnterTransaction).unwrap().len(), 2);
+    assert_eq!(reopened.records().unwrap().len(), 3);
+
+    let count = reopened.rebuild(vec![
+        record(b"carol", RecordType::EnterTransaction),
+        record(b"carol", RecordType::EnterTransaction),
+    ]).unwrap();
+    assert_eq!(count, 1);
+    assert_eq!(index.records().unwrap()[0].content_cid, compute_cid(b"carol"));
+}
+
+#[test]
+fn test_game_discovery_encoding() {
+    use zkret_santa_filecoin::filecoin::backend::compute_cid;
+    use zkret_santa_filecoin::filecoin::discovery::{discovery_address, game_address, Announcement, Network};
+    use zkret_santa_filecoin::filecoin::RecordType;
+
+    // Blake2b-160 payload with a Blake2b-32 checksum, base32 encoded
+    assert_eq!(
+        game_address(b"default", Network::Mainnet),
+        "f1owhm4tfwxiwzsrwjwx3hlbooqeul6lkdwuax3sy"
+    );
+    assert!(game_address(b"default", Network::Testnet).starts_with("t1"));
+    assert_ne!(game_address(b"default", Network::Mainnet), game_address(b"other", Network::Mainnet));
+    assert_ne!(discovery_address(Network::Mainnet), game_address(b"", Network::Mainnet));
+
+    let cid = compute_cid(b"enter transaction");
+    let announcement = Announcement::new(RecordType::EnterTransaction, &cid);
+    let decoded = Announcement::from_params(&announcement.to_params().unwrap()).unwrap();
+    assert_eq!(decoded, announcement);
+    assert_eq!(decoded.content_cid().unwrap(), cid);
+
+    // Unrelated messages to the address are ignored
+    assert!(Announcement::from_params(b"").is_none());
+    assert!(Announcement::from_params(b"{\"hello\":1}").is_none());
+}
+
+#[tokio::test]
+async fn test_deal_submission_and_backoff_polling() {
+    use std::sync::Mutex;
+    use zkret_santa_filecoin::filecoin::backend::compute_cid;
+    use zkret_santa_filecoin::filecoin::deals::{backoff_delay, select_miner, DealClient, DealProposal, MinerAsk};
+    use zkret_santa_filecoin::filecoin::{DealConfig, DealManager, DealState};
+    use zkret_santa_filecoin::Error;
+
+    // Node double that answers asks and replays scripted deal states
+    struct ScriptedNode {
+        asks: Vec<MinerAsk>,
+        states: Mutex<Vec<zkret_santa_filecoin::utils::Result<DealState>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl DealClient for ScriptedNode {
+        async fn query_ask(&self, miner: &str) -> zkret_santa_filecoin::utils::Result<MinerAsk> {
+            self.asks.iter()
+                .find(|ask| ask.miner == miner)
+                .cloned()
+                .ok_or_else(|| Error::StorageError(format!("{} unreachable", miner)))
+        }
+
+        async fn start_deal(&self, proposal: &DealProposal) -> zkret_santa_filecoin::utils::Result<String> {
+            Ok(format!("proposal-{}", proposal.miner))
+        }
+
+        async fn deal_state(&self, _proposal_cid: &str) -> zkret_santa_filecoin::utils::Result<DealState> {
+            self.states.lock().unwrap().remove(0)
+        }
+    }
+
+    let ask = |miner: &str, price| MinerAsk {
+        miner: miner.to_string(),
+        price_per_gib_epoch: price,
+        min_piece_size: 256,
+        max_piece_size: 1 << 30,
+    };
+    let node = ScriptedNode {
+        asks: vec![ask("f01000", 500), ask("f02000", 100), ask("f03000", 50)],
+        states: Mutex::new(vec![
+            Ok(DealState::InProgress("StorageDealSealing".to_string())),
+            Err(Error::StorageError("node restarting".to_string())),
+            Ok(DealState::Active),
+        ]),
+    };
+    let config = DealConfig {
+        // f03000 is cheapest but above the limit, f04000 does not answer
+        miners: vec!["f04000".into(), "f01000".into(), "f02000".into()],
+        max_price_per_gib_epoch: Some(200),
+        poll_initial_secs: 10,
+        poll_max_secs: 25,
+        ..DealConfig::default()
+    };
+
+    assert_eq!(backoff_delay(0, &config), 10);
+    assert_eq!(backoff_delay(1, &config), 20);
+    assert_eq!(backoff_delay(2, &config), 25);
+    assert_eq!(backoff_delay(64, &config), 25);
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("deals.json");
+    let cid = compute_cid(b"choice");
+
+    let mut manager = DealManager::open(&path, config.clone()).unwrap();
+    let deal = manager.submit(&node, &cid, 2048).await.unwrap();
+    assert_eq!(deal.miner, "f02000");
+    assert_eq!(deal.state, DealState::Proposed);
+
+    // Not due yet, so nothing is polled
+    assert_eq!(manager.poll(&node, deal.submitted_at).await.unwrap(), 0);
+    assert_eq!(manager.deal_for(&cid).unwrap().attempts, 0);
+
+    // Deal state survives a restart
+    let mut manager = DealManager::open(&path, config.clone()).unwrap();
+    assert_eq!(manager.deal_for(&cid), Some(&deal));
+
+    let now = deal.next_poll_at;
+    assert_eq!(manager.poll(&node, now).await.unwrap(), 1);
+    let polled = manager.deal_for(&cid).unwrap();
+    assert_eq!(polled.state, DealState::InProgress("StorageDealSealing".to_string()));
+    assert_eq!(polled.next_poll_at, now + 20);
+
+    // A failed poll keeps the state and backs off further
+    let now = polled.next_poll_at;
+    assert_eq!(manager.poll(&node, now).await.unwrap(), 0);
+    let polled = manager.deal_for(&cid).unwrap();
+    assert!(polled.last_error.is_some());
+    assert_eq!(polled.next_poll_at, now + 25);
+
+    manager.poll(&node, polled.next_poll_at).await.unwrap();
+    assert_eq!(manager.deal_for(&cid).unwrap().state, DealState::Active);
+
+    // Final deals are not polled again; the script is exhausted
+    manager.poll(&node, u64::MAX).await.unwrap();
+    let reloaded = DealManager::open(&path, config).unwrap();
+    assert_eq!(reloaded.deal_for(&cid).unwrap().state, DealState::Active);
+    assert_eq!(reloaded.deal_for(&cid).unwrap().attempts, 3);
+
+    // No acceptable miner is an error rather than a silent skip
+    let strict = DealConfig { max_price_per_gib_epoch: Some(10), ..manager.config().clone() };
+    let mut strict = DealManager::open(&dir.path().join("strict.json"), strict).unwrap();
+    assert!(matches!(strict.submit(&node, &cid, 2048).await, Err(Error::StorageError(_))));
+
+    // Miners are compared by what the padded piece costs, not by the quoted rate
+    let padded = MinerAsk { min_piece_size: 1 << 20, ..ask("f05000", 20) };
+    let asks = [padded, ask("f01000", 500), ask("f02000", 100)];
+    assert_eq!(select_miner(&asks, 2048, manager.config()).unwrap().miner, "f02000");
+    assert_eq!(select_miner(&asks, 1 << 20, manager.config()).unwrap().miner, "f05000");
+}
+
+/// One-shot HTTP server answering each connection with the next canned
+/// `(status, body, delay)` and recording the raw requests it received
+async fn spawn_http_server(
+    responses: Vec<(u16, String, std::time::Duration)>,
+) -> (String, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+
+    let received = requests.clone();
+    tokio::spawn(async move {
+        for (status, body, delay) in responses {
+            let (mut socket, _) = listener.accept().await.unwrap();
+
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let length = text.lines()
+                        .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                        .unwrap_or(0);
+                    if request.len() >= end + 4 + length || n == 0 {
+                        break;
+                    }
+                }
+            }
+            received.lock().unwrap().push(String::from_utf8_lossy(&request).to_string());
+
+            tokio::time::sleep(delay).await;
+            let response = format!(
+                "HTTP/1.1 {} X\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                status, body.len(), body
+            );
+            let _ = socket.write_all(response.as_bytes()).await;
+        }
+    });
+
+    (url, requests)
+}
+
+#[tokio::test]
+async fn test_lotus_client_against_http_server() {
+    use std::time::Duration;
+    use zkret_santa_filecoin::filecoin::backend::compute_cid;
+    use zkret_santa_filecoin::filecoin::{LotusClient, LotusError};
+
+    let cid = compute_cid(b"announcement");
+    let instant = Duration::ZERO;
+    let (url, requests) = spawn_http_server(vec![
+        (200, r#"{"jsonrpc":"2.0","id":1,"result":{"Cids":[],"Height":4242}}"#.to_string(), instant),
+        (200, format!(r#"{{"jsonrpc":"2.0","id":2,"result":[{{"/":"{}"}}]}}"#, cid), instant),
+        (200, r#"{"jsonrpc":"2.0","id":3,"error":{"code":1,"message":"actor not found"}}"#.to_string(), instant),
+        (401, "unauthorized".to_string(), instant),
+        (200, r#"{"jsonrpc":"2.0","id":5,"result":"mainnet"}"#.to_string(), Duration::from_secs(2)),
+    ]).await;
+
+    let client = LotusClient::new(&format!("{}/rpc/v1", url), Some("secret-token"), Duration::from_millis(500)).unwrap();
+
+    assert_eq!(client.chain_head().await.unwrap().height, 4242);
+    assert_eq!(client.state_list_messages("f1game", 1_000).await.unwrap(), vec![cid]);
+    assert!(matches!(
+        client.state_miner_peer_id("f01000").await,
+        Err(LotusError::Rpc { code: 1, ref message }) if message == "actor not found"
+    ));
+    assert!(matches!(client.wallet_default_address().await, Err(LotusError::Http { status: 401, .. })));
+    assert!(matches!(client.state_network_name().await, Err(LotusError::Timeout)));
+
+    let requests = requests.lock().unwrap();
+    assert!(requests[0].to_ascii_lowercase().contains("authorization: bearer secret-token"));
+    assert!(requests[0].contains(r#""method":"Filecoin.ChainHead""#));
+    assert!(requests[1].contains(r#""params":[{"To":"f1game"},[],1000]"#));
+}
+
+#[tokio::test]
+async fn test_filecoin_storage_against_mock_lotus() {
+    use std::time::Duration;
+    use zkret_santa_filecoin::filecoin::{
+        DealConfig, DealManager, DealState, FilecoinStorage, LotusClient, MockConfig, MockLotus, RecordType,
+        RetrievalSource, Retriever, StorageBackend,
+    };
+
+    let mock = MockLotus::start(MockConfig {
+        auth_token: Some("token".to_string()),
+        accept_delay: Duration::from_millis(100),
+        seal_delay: Duration::from_millis(200),
+        ..MockConfig::default()
+    }).await.unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let deal_config = DealConfig {
+        miners: vec!["t01000".to_string()],
+        poll_initial_secs: 0,
+        ..DealConfig::default()
+    };
+    async fn open(url: &str, dir: &std::path::Path, name: &str, deal_config: &DealConfig) -> FilecoinStorage {
+        let client = LotusClient::new(url, Some("token"), Duration::from_secs(5)).unwrap();
+        let deals = DealManager::open(&dir.join(format!("{}-deals.json", name)), deal_config.clone()).unwrap();
+        let sources = vec![
+            RetrievalSource::Cache(dir.join(format!("{}-cache", name))),
+            RetrievalSource::Lotus(client.clone()),
+        ];
+        let retriever = Retriever::new(sources, Duration::from_secs(5)).unwrap();
+        FilecoinStorage::new(client, &dir.join(format!("{}.jsonl", name)), b"office", deals, retriever).await.unwrap()
+    }
+    let mut alice = open(&mock.url(), dir.path(), "alice", &deal_config).await;
+    let mut bob = open(&mock.url(), dir.path(), "bob", &deal_config).await;
+    assert!(alice.game_address().starts_with("t1"));
+
+    // Storing returns as soon as the deal is proposed
+    let record = alice.put(b"enter alice".to_vec(), RecordType::EnterTransaction).await.unwrap();
+    assert_eq!(alice.deal_for(&record.content_cid).unwrap().state, DealState::Proposed);
+    assert_eq!(mock.message_count(), 1);
+
+    alice.poll_deals().await.unwrap();
+    let state = &alice.deal_for(&record.content_cid).unwrap().state;
+    assert_eq!(*state, DealState::InProgress("StorageDealCheckForAcceptance".to_string()));
+
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    alice.poll_deals().await.unwrap();
+    let state = &alice.deal_for(&record.content_cid).unwrap().state;
+    assert_eq!(*state, DealState::InProgress("StorageDealSealing".to_string()));
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    alice.poll_deals().await.unwrap();
+    assert_eq!(alice.deal_for(&record.content_cid).unwrap().state, DealState::Active);
+
+    // Another player finds the record through the game address
+    assert_eq!(bob.sync().await.unwrap(), 1);
+    let synced = bob.list(RecordType::EnterTransaction).await.unwrap();
+    assert_eq!(synced[0].content_cid, record.content_cid);
+    let epoch = synced[0].epoch.unwrap();
+    assert_eq!(bob.get(&record.content_cid).await.unwrap(), b"enter alice");
+    let fetched = mock.call_count("ChainGetMessage");
+    assert_eq!(bob.sync().await.unwrap(), 0);
+    assert_eq!(mock.call_count("ChainGetMessage"), fetched);
+
+    // The writer learns the announcement epoch of its own record on sync
+    assert!(record.epoch.is_none());
+    assert_eq!(alice.sync().await.unwrap(), 0);
+    assert_eq!(alice.list(RecordType::EnterTransaction).await.unwrap()[0].epoch, Some(epoch));
+
+    // Deal failures surface once the miner would have accepted
+    mock.configure(|config| config.deal_failure = Some("miner out of space".to_string()));
+    let record = bob.put(b"enter bob".to_vec(), RecordType::EnterTransaction).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    bob.poll_deals().await.unwrap();
+    assert_eq!(
+        bob.deal_for(&record.content_cid).unwrap().state,
+        DealState::Failed("miner out of space".to_string())
+    );
+
+    // Records a player wrote or already fetched stay readable from the cache
+    mock.configure(|config| config.fail_retrievals = true);
+    assert!(alice.get(&record.content_cid).await.is_err());
+    assert!(matches!(bob.retrieve(&record.content_cid).await.unwrap().source, RetrievalSource::Cache(_)));
+    let synced = bob.retrieve(&synced[0].content_cid).await.unwrap();
+    assert!(matches!(synced.source, RetrievalSource::Cache(_)));
+
+    // Without a usable miner the record is still announced, with a failed deal
+    let mut dave = open(&mock.url(), dir.path(), "dave", &DealConfig::default()).await;
+    let record = dave.put(b"enter dave".to_vec(), RecordType::EnterTransaction).await.unwrap();
+    assert!(matches!(dave.deal_for(&record.content_cid).unwrap().state, DealState::Failed(_)));
+    assert_eq!(mock.message_count(), 3);
+
+    // Records that cannot be fetched are skipped and picked up by a later sync
+    let mut carol = open(&mock.url(), dir.path(), "carol", &deal_config).await;
+    let report = carol.sync_game().await.unwrap();
+    assert!(report.synced.is_empty());
+    assert_eq!(report.skipped.len(), 3);
+    assert!(report.skipped.iter().any(|skipped| skipped.content_cid == record.content_cid));
+    mock.configure(|config| config.fail_retrievals = false);
+    assert_eq!(carol.sync().await.unwrap(), 3);
+
+    // A sync looks no further back than the epoch it starts from
+    let mut frank = open(&mock.url(), dir.path(), "frank", &deal_config).await;
+    frank.set_start_epoch(epoch + 1_000);
+    assert_eq!(frank.sync().await.unwrap(), 0);
+    frank.set_start_epoch(epoch);
+    assert_eq!(frank.sync().await.unwrap(), 3);
+
+    // Configs are also announced to the discovery address, where games are listed
+    let config = alice.put(b"office config".to_vec(), RecordType::GameConfig).await.unwrap();
+    assert_eq!(mock.message_count(), 5);
+    let client = LotusClient::new(&mock.url(), Some("token"), Duration::from_secs(5)).unwrap();
+    let deals = DealManager::open(&dir.path().join("lobby-deals.json"), deal_config.clone()).unwrap();
+    let retriever = Retriever::new(vec![RetrievalSource::Lotus(client.clone())], Duration::from_secs(5)).unwrap();
+    let mut lobby = FilecoinStorage::discovery(client, &dir.path().join("lobby.jsonl"), deals, retriever).await.unwrap();
+    assert_eq!(lobby.sync().await.unwrap(), 1);
+    assert_eq!(lobby.list(RecordType::GameConfig).await.unwrap()[0].content_cid, config.content_cid);
+
+    // Imported records keep the epoch of their announcement only if the chain
+    // shows it announced that content; others are announced again
+    let mut erin = open(&mock.url(), dir.path(), "erin", &deal_config).await;
+    let announced = alice.list(RecordType::EnterTransaction).await.unwrap()[0].clone();
+    assert_eq!(erin.import(b"enter alice".to_vec(), &announced).await.unwrap().epoch, Some(epoch));
+    assert_eq!(mock.message_count(), 5);
+    let relabelled = erin.import(b"enter erin".to_vec(), &announced).await.unwrap();
+    assert_ne!(relabelled.id, announced.id);
+    assert!(relabelled.epoch.is_none());
+    assert_eq!(mock.message_count(), 6);
+
+    // Requests without the token are refused
+    let anonymous = LotusClient::new(&mock.url(), None, Duration::from_secs(5)).unwrap();
+    assert!(anonymous.chain_head().await.is_err());
+}
+
+#[tokio::test]
+async fn test_retriever_tries_sources_in_order() {
+    use std::time::Duration;
+    use zkret_santa_filecoin::filecoin::backend::compute_cid;
+    use zkret_santa_filecoin::filecoin::{LotusClient, MockConfig, MockLotus, RetrievalSource, Retriever};
+    use zkret_santa_filecoin::Error;
+
+    let mock = MockLotus::start(MockConfig::default()).await.unwrap();
+    let client = LotusClient::new(&mock.url(), None, Duration::from_secs(5)).unwrap();
+    let on_lotus = client.client_import(b"choice record").await.unwrap();
+    let on_gateway = compute_cid(b"enter record");
+
+    let instant = Duration::ZERO;
+    let (tampering, _) = spawn_http_server(vec![
+        (200, "tampered".to_string(), instant),
+        (200, "tampered".to_string(), instant),
+        (200, "tampered".to_string(), instant),
+    ]).await;
+    let (gateway, requests) = spawn_http_server(vec![
+        (404, "not found".to_string(), instant),
+        (200, "enter record".to_string(), instant),
+    ]).await;
+
+    let cache = tempfile::tempdir().unwrap();
+    let retriever = Retriever::new(vec![
+        RetrievalSource::Cache(cache.path().to_path_buf()),
+        RetrievalSource::Gateway(tampering),
+        RetrievalSource::Gateway(gateway.clone()),
+        RetrievalSource::Lotus(client),
+    ], Duration::from_secs(5)).unwrap();
+
+    // Bad bytes from one gateway and a miss on the other fall through to Lotus
+    let retrieved = retriever.retrieve(&on_lotus).await.unwrap();
+    assert_eq!(retrieved.data, b"choice record");
+    assert!(matches!(retrieved.source, RetrievalSource::Lotus(_)));
+    assert!(requests.lock().unwrap()[0].starts_with(&format!("GET /ipfs/{}?format=raw", on_lotus)));
+
+    let retrieved = retriever.retrieve(&on_gateway).await.unwrap();
+    assert!(matches!(retrieved.source, RetrievalSource::Gateway(ref url) if *url == gateway));
+
+    // Both are cached now, so no network source is asked again
+    drop(mock);
+    for cid in [on_lotus, on_gateway] {
+        assert!(matches!(retriever.retrieve(&cid).await.unwrap().source, RetrievalSource::Cache(_)));
+    }
+
+    // Content nobody serves intact is an integrity failure if some source
+    // served other bytes, and a plain miss otherwise
+    let unknown = compute_cid(b"unknown");
+    assert!(matches!(retriever.retrieve(&unknown).await, Err(Error::IntegrityError(_))));
+    assert!(matches!(retriever.retrieve(&unknown).await, Err(Error::StorageError(_))));
+}
@@ -0,0 +1,552 @@
+#[tokio::test]
+async fn test_enter_phase_with_memory_backend() {
+    use zkret_santa_filecoin::crypto::{KeyPair, ZKProofSystem};
+    use zkret_santa_filecoin::filecoin::{MemoryStorage, RecordType, StorageBackend};
+    use zkret_santa_filecoin::protocol::{GameConfig, SecretSantaProtocol};
+
+    let zk_system = ZKProofSystem::new().unwrap();
+    let organizer = KeyPair::generate();
+    let config = GameConfig::new("office", &organizer, zk_system.params_hash()).sign(&organizer).unwrap();
+    let mut protocol = SecretSantaProtocol::create_game(MemoryStorage::new(), zk_system, config).await.unwrap();
+
+    let alice = KeyPair::generate();
+    let bob = KeyPair::generate();
+    protocol.enter_phase(&alice).await.unwrap();
+    protocol.enter_phase(&bob).await.unwrap();
+
+    let records = protocol.storage().list(RecordType::EnterTransaction).await.unwrap();
+    assert_eq!(records.len(), 2);
+    assert!(protocol.storage().list(RecordType::ChoiceTransaction).await.unwrap().is_empty());
+
+    let choices = protocol.get_available_choices().await.unwrap();
+    assert_eq!(choices.len(), 2);
+    assert!(choices.contains(&alice.public_key.as_bytes().to_vec()));
+    assert!(choices.contains(&bob.public_key.as_bytes().to_vec()));
+}
+
+#[tokio::test]
+async fn test_state_is_replayed_from_storage() {
+    use zkret_santa_filecoin::crypto::{DHKeyExchange, KeyPair, ZKProofSystem};
+    use zkret_santa_filecoin::filecoin::{LocalStorage, RecordType, StorageBackend};
+    use zkret_santa_filecoin::protocol::{
+        ChoiceTransaction, Clock, Deadline, EnterTransaction, GameConfig, Phase, SecretSantaProtocol, Transaction,
+    };
+    use zkret_santa_filecoin::crypto::public_inputs::field_to_bytes;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    let params = tempfile::tempdir().unwrap();
+    let manifest = ZKProofSystem::new().unwrap().write_params_dir(params.path()).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let organizer = KeyPair::generate();
+    let config = GameConfig {
+        created_at: 1_000,
+        enter_deadline: Deadline::Timestamp(2_000),
+        choice_deadline: Deadline::Timestamp(3_000),
+        recovery_deadline: Deadline::Timestamp(3_200),
+        reveal_deadline: Deadline::Timestamp(4_000),
+        ..GameConfig::new("office", &organizer, &manifest.params_hash)
+    };
+    let config = config.sign(&organizer).unwrap();
+    let game = config.id().unwrap();
+
+    let time = Arc::new(AtomicU64::new(1_500));
+    let clock: Clock = {
+        let time = time.clone();
+        Arc::new(move || time.load(Ordering::SeqCst))
+    };
+
+    let alice = KeyPair::generate();
+    let bob = KeyPair::generate();
+    let carol = KeyPair::generate();
+    let alice_dh = DHKeyExchange::generate();
+    let bob_dh = DHKeyExchange::generate();
+
+    let mut storage = LocalStorage::open(dir.path()).unwrap();
+    storage.set_clock(clock.clone());
+    let mut first = SecretSantaProtocol::create_game(
+        storage,
+        ZKProofSystem::from_params_dir(params.path()).unwrap(),
+        config,
+    ).await.unwrap();
+    first.set_clock(clock.clone());
+    for keypair in [&alice, &bob, &carol] {
+        first.enter_phase(keypair).await.unwrap();
+    }
+    assert!(first.enter_phase(&alice).await.is_err());
+
+    time.store(2_500, Ordering::SeqCst);
+    first.choice_phase(&alice, bob.public_key.as_bytes(), &alice_dh).await.unwrap();
+    first.choice_phase(&bob, carol.public_key.as_bytes(), &bob_dh).await.unwrap();
+    assert!(first.choice_phase(&alice, alice.public_key.as_bytes(), &alice_dh).await.is_err());
+
+    // Publish records a replay must reject: a second ENTER for alice, a
+    // CHOICE whose fields no longer match its proof and a CHOICE by carol
+    // under the DH key bob already published
+    let mut writer = LocalStorage::open(dir.path()).unwrap();
+    writer.set_clock(Arc::new(|| 1_800));
+    let (_, alice_secret) = alice.to_hex_strings();
+    let zk_proof = ZKProofSystem::from_params_dir(params.path()).unwrap().prove_enter_phase(
+        game.as_bytes(),
+        alice.public_key.as_bytes(),
+        &hex::decode(alice_secret).unwrap(),
+    ).unwrap();
+    let inputs = zk_proof.enter_inputs().unwrap();
+    let nullifier = field_to_bytes(&inputs.nullifier).to_vec();
+    let message = EnterTransaction::signing_message(&game, &field_to_bytes(&inputs.commitment), &nullifier);
+    let enter = Transaction::Enter(EnterTransaction {
+        game_id: game,
+        public_key: alice.public_key.as_bytes().to_vec(),
+        nullifier,
+        zk_proof,
+        signature: alice.sign(&message).to_bytes().to_vec(),
+    });
+    writer.put(enter.encode().unwrap(), RecordType::EnterTransaction).await.unwrap();
+
+    writer.set_clock(clock.clone());
+    let records = writer.list(RecordType::ChoiceTransaction).await.unwrap();
+    let data = writer.get(&records[0].content_cid).await.unwrap();
+    let mut choice = Transaction::decode(RecordType::ChoiceTransaction, &data).unwrap();
+    if let Transaction::Choice(tx) = &mut choice {
+        tx.chosen_public_key = alice.public_key.as_bytes().to_vec();
+    }
+    writer.put(choice.encode().unwrap(), RecordType::ChoiceTransaction).await.unwrap();
+
+    let zk_proof = ZKProofSystem::from_params_dir(params.path()).unwrap().prove_choice_phase(
+        game.as_bytes(),
+        carol.public_key.as_bytes(),
+        carol.secret_bytes(),
+        alice.public_key.as_bytes(),
+        &bob_dh.public_key(),
+        &first.state().enter_set().unwrap(),
+    ).unwrap();
+    let inputs = zk_proof.choice_inputs().unwrap();
+    let reused_key = Transaction::Choice(ChoiceTransaction {
+        game_id: game,
+        merkle_root: field_to_bytes(&inputs.merkle_root).to_vec(),
+        nullifier: field_to_bytes(&inputs.nullifier).to_vec(),
+        chosen_public_key: alice.public_key.as_bytes().to_vec(),
+        chooser_dh_public_key: bob_dh.public_key().to_vec(),
+        zk_proof,
+    });
+    writer.set_clock(Arc::new(|| 2_700));
+    writer.put(reused_key.encode().unwrap(), RecordType::ChoiceTransaction).await.unwrap();
+
+    // A second client sees the same game without having taken part in it
+    let mut storage = LocalStorage::open(dir.path()).unwrap();
+    storage.set_clock(clock.clone());
+    let mut second = SecretSantaProtocol::new(
+        storage,
+        ZKProofSystem::from_params_dir(params.path()).unwrap(),
+        game,
+    ).await.unwrap();
+    second.set_clock(clock);
+    assert_eq!(*second.current_phase(), Phase::Choice);
+    assert_eq!(second.state().participants().len(), 3);
+    assert_eq!(second.state().choices().len(), 2);
+    assert_eq!(second.state().rejected().len(), 3);
+    assert!(second.state().rejected().iter().any(|rejection| rejection.reason.contains("DH key")));
+    assert_eq!(
+        second.get_available_choices().await.unwrap(),
+        vec![alice.public_key.as_bytes().to_vec()]
+    );
+
+    let santa_dh = second.state().choice_for(carol.public_key.as_bytes()).unwrap().chooser_dh_public_key.clone();
+    assert_eq!(santa_dh, bob_dh.public_key().to_vec());
+    assert!(second.reveal_phase(&carol, "carol@example.com", &santa_dh).await.is_err());
+
+    time.store(3_500, Ordering::SeqCst);
+    second.reveal_phase(&carol, "carol@example.com", &santa_dh).await.unwrap();
+    assert!(second.reveal_phase(&alice, "alice@example.com", &santa_dh).await.is_err());
+
+    first.refresh().await.unwrap();
+    assert_eq!(*first.current_phase(), Phase::Reveal);
+    assert!(first.state().reveal_for(&bob_dh.public_key()).is_some());
+    assert_eq!(first.state().rejected().len(), 3);
+}
+
+#[tokio::test]
+async fn test_games_sharing_storage_stay_apart() {
+    use zkret_santa_filecoin::crypto::{KeyPair, ZKProofSystem};
+    use zkret_santa_filecoin::filecoin::{LocalStorage, MemoryStorage, RecordType, StorageBackend};
+    use zkret_santa_filecoin::protocol::game::list_games;
+    use zkret_santa_filecoin::protocol::{GameConfig, SecretSantaProtocol, Transaction};
+
+    let params = tempfile::tempdir().unwrap();
+    let manifest = ZKProofSystem::new().unwrap().write_params_dir(params.path()).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let organizer = KeyPair::generate();
+    let alice = KeyPair::generate();
+    let bob = KeyPair::generate();
+
+    let office = GameConfig::new("office", &organizer, &manifest.params_hash).sign(&organizer).unwrap();
+    let family = GameConfig::new("family", &organizer, &manifest.params_hash).sign(&organizer).unwrap();
+    let family_id = family.id().unwrap();
+    assert_ne!(office.id().unwrap(), family_id);
+
+    // Only the organizer can sign a config
+    assert!(GameConfig::new("friends", &organizer, &manifest.params_hash).sign(&alice).is_err());
+
+    // A game set up for other parameters cannot be played with these
+    let other_params = GameConfig::new("friends", &organizer, &"00".repeat(32)).sign(&organizer).unwrap();
+    let error = SecretSantaProtocol::create_game(
+        MemoryStorage::new(),
+        ZKProofSystem::from_params_dir(params.path()).unwrap(),
+        other_params,
+    ).await.err().unwrap();
+    assert!(error.to_string().contains("parameter set"));
+    assert!(GameConfig::new("friends", &organizer, "not a hash").sign(&organizer).is_err());
+
+    let mut office_game = SecretSantaProtocol::create_game(
+        LocalStorage::open(dir.path()).unwrap(),
+        ZKProofSystem::from_params_dir(params.path()).unwrap(),
+        office,
+    ).await.unwrap();
+    office_game.enter_phase(&alice).await.unwrap();
+
+    let mut family_game = SecretSantaProtocol::create_game(
+        LocalStorage::open(dir.path()).unwrap(),
+        ZKProofSystem::from_params_dir(params.path()).unwrap(),
+        family,
+    ).await.unwrap();
+    family_game.enter_phase(&bob).await.unwrap();
+
+    let storage = LocalStorage::open(dir.path()).unwrap();
+    let games = list_games(&storage).await.unwrap();
+    assert_eq!(games.len(), 2);
+    assert!(games.iter().any(|(id, signed)| *id == family_id && signed.config.name == "family"));
+
+    // Alice's office ENTER relabelled for the family game fails its proof's game input
+    let mut storage = storage;
+    let records = storage.list(RecordType::EnterTransaction).await.unwrap();
+    for record in records {
+        let data = storage.get(&record.content_cid).await.unwrap();
+        let mut enter = Transaction::decode(RecordType::EnterTransaction, &data).unwrap();
+        if let Transaction::Enter(tx) = &mut enter {
+            if tx.public_key == alice.public_key.as_bytes().to_vec() {
+                tx.game_id = family_id;
+                storage.put(enter.encode().unwrap(), RecordType::EnterTransaction).await.unwrap();
+            }
+        }
+    }
+
+    let office_state = office_game.refresh().await.unwrap();
+    assert_eq!(office_state.participants().len(), 1);
+    assert!(office_state.rejected().is_empty());
+
+    let family_state = family_game.refresh().await.unwrap();
+    assert_eq!(family_state.participants().len(), 1);
+    assert_eq!(family_state.participants()[0].public_key, bob.public_key.as_bytes().to_vec());
+    assert_eq!(family_state.rejected().len(), 1);
+}
+
+#[tokio::test]
+async fn test_deadlines_close_each_phase() {
+    use zkret_santa_filecoin::crypto::{DHKeyExchange, KeyPair, ZKProofSystem};
+    use zkret_santa_filecoin::filecoin::MemoryStorage;
+    use zkret_santa_filecoin::protocol::{Clock, Deadline, GameConfig, Phase, SecretSantaProtocol};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    let organizer = KeyPair::generate();
+    let (full_params, short_params) = (ZKProofSystem::new().unwrap(), ZKProofSystem::new().unwrap());
+    let schedule = GameConfig {
+        created_at: 1_000,
+        min_participants: 3,
+        max_participants: 3,
+        enter_deadline: Deadline::Timestamp(2_000),
+        choice_deadline: Deadline::Epoch(100),
+        recovery_deadline: Deadline::Timestamp(4_000),
+        reveal_deadline: Deadline::Epoch(200),
+        genesis_timestamp: 0,
+        ..GameConfig::new("office", &organizer, full_params.params_hash())
+    };
+    assert_eq!(schedule.deadlines(), [2_000, 3_000, 4_000, 6_000]);
+    assert_eq!(schedule.creation_epoch(), 33);
+    assert_eq!(schedule.phase_at(999), Phase::Setup);
+    assert_eq!(schedule.phase_at(2_000), Phase::Choice);
+    assert_eq!(schedule.phase_at(3_000), Phase::Recovery);
+    assert_eq!(schedule.phase_at(6_000), Phase::Complete);
+    assert_eq!("epoch:100".parse::<Deadline>().unwrap(), Deadline::Epoch(100));
+
+    let reversed = GameConfig { choice_deadline: Deadline::Timestamp(1_500), ..schedule.clone() };
+    assert!(reversed.sign(&organizer).is_err());
+    let too_small = GameConfig { min_participants: 1, ..schedule.clone() };
+    assert!(too_small.sign(&organizer).is_err());
+    let overflowing = GameConfig { reveal_deadline: Deadline::Epoch(u64::MAX / 2), ..schedule.clone() };
+    assert_eq!(overflowing.deadlines()[3], u64::MAX);
+    assert!(overflowing.sign(&organizer).unwrap_err().to_string().contains("too far"));
+
+    let time = Arc::new(AtomicU64::new(500));
+    let clock: Clock = {
+        let time = time.clone();
+        Arc::new(move || time.load(Ordering::SeqCst))
+    };
+    let players: Vec<KeyPair> = (0..4).map(|_| KeyPair::generate()).collect();
+
+    // The game takes at most three players, and none before it starts or
+    // after the ENTER deadline
+    let mut storage = MemoryStorage::new();
+    storage.set_clock(clock.clone());
+    let mut full = SecretSantaProtocol::create_game(
+        storage,
+        full_params,
+        schedule.clone().sign(&organizer).unwrap(),
+    ).await.unwrap();
+    full.set_clock(clock.clone());
+    assert_eq!(*full.current_phase(), Phase::Setup);
+    assert!(full.enter_phase(&players[0]).await.is_err());
+
+    time.store(1_500, Ordering::SeqCst);
+    full.refresh().await.unwrap();
+    assert_eq!(*full.current_phase(), Phase::Enter);
+    for keypair in &players[..3] {
+        full.enter_phase(keypair).await.unwrap();
+    }
+    assert!(full.enter_phase(&players[3]).await.is_err());
+
+    time.store(2_500, Ordering::SeqCst);
+    full.refresh().await.unwrap();
+    assert_eq!(*full.current_phase(), Phase::Choice);
+    assert!(full.enter_phase(&players[3]).await.is_err());
+    assert_eq!(full.state().participants().len(), 3);
+
+    // A game that closes below the minimum takes no choices
+    time.store(1_500, Ordering::SeqCst);
+    let short = GameConfig {
+        name: "short".to_string(),
+        params_hash: short_params.params_hash().to_string(),
+        ..schedule
+    };
+    let mut storage = MemoryStorage::new();
+    storage.set_clock(clock.clone());
+    let mut short = SecretSantaProtocol::create_game(
+        storage,
+        short_params,
+        short.sign(&organizer).unwrap(),
+    ).await.unwrap();
+    short.set_clock(clock);
+    for keypair in &players[..2] {
+        short.enter_phase(keypair).await.unwrap();
+    }
+
+    time.store(2_500, Ordering::SeqCst);
+    let dh = DHKeyExchange::generate();
+    let error = short.choice_phase(&players[0], players[1].public_key.as_bytes(), &dh).await.unwrap_err();
+    assert!(error.to_string().contains("fewer than 3 participants"));
+    assert!(short.state().choices().is_empty());
+}
+
+#[tokio::test]
+async fn test_nullifier_conflicts_go_to_the_earliest_record() {
+    use zkret_santa_filecoin::crypto::{DHKeyExchange, KeyPair, ZKProofSystem};
+    use zkret_santa_filecoin::filecoin::{LocalStorage, RecordType, StorageBackend};
+    use zkret_santa_filecoin::protocol::{Clock, Deadline, EnterTransaction, GameConfig, SecretSantaProtocol, Transaction};
+    use zkret_santa_filecoin::crypto::public_inputs::field_to_bytes;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    let params = tempfile::tempdir().unwrap();
+    let manifest = ZKProofSystem::new().unwrap().write_params_dir(params.path()).unwrap();
+    let organizer = KeyPair::generate();
+    let config = GameConfig {
+        created_at: 1_000,
+        enter_deadline: Deadline::Timestamp(2_000),
+        choice_deadline: Deadline::Timestamp(3_000),
+        recovery_deadline: Deadline::Timestamp(3_500),
+        reveal_deadline: Deadline::Timestamp(4_000),
+        ..GameConfig::new("office", &organizer, &manifest.params_hash)
+    };
+    let config = config.sign(&organizer).unwrap();
+    let game = config.id().unwrap();
+
+    let time = Arc::new(AtomicU64::new(1_500));
+    let clock: Clock = {
+        let time = time.clone();
+        Arc::new(move || time.load(Ordering::SeqCst))
+    };
+    let alice = KeyPair::generate();
+    let bob = KeyPair::generate();
+    let carol = KeyPair::generate();
+
+    // Two clients publish to their own storage and exchange records later
+    let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    let mut storage_a = LocalStorage::open(dir_a.path()).unwrap();
+    storage_a.set_clock(clock.clone());
+    let mut first = SecretSantaProtocol::create_game(
+        storage_a,
+        ZKProofSystem::from_params_dir(params.path()).unwrap(),
+        config.clone(),
+    ).await.unwrap();
+    first.set_clock(clock.clone());
+    for keypair in [&alice, &bob, &carol] {
+        first.enter_phase(keypair).await.unwrap();
+    }
+
+    // Alice's identity secret entered under a second key is turned away
+    let zk_system = ZKProofSystem::from_params_dir(params.path()).unwrap();
+    let (_, alice_secret) = alice.to_hex_strings();
+    let mallory = KeyPair::generate();
+    let zk_proof = zk_system.prove_enter_phase(
+        game.as_bytes(),
+        mallory.public_key.as_bytes(),
+        &hex::decode(alice_secret).unwrap(),
+    ).unwrap();
+    let signed_enter = |public_key: &[u8], zk_proof: zkret_santa_filecoin::crypto::ZKProof, signer: &KeyPair| {
+        let inputs = zk_proof.enter_inputs().unwrap();
+        let nullifier = field_to_bytes(&inputs.nullifier).to_vec();
+        let message = EnterTransaction::signing_message(&game, &field_to_bytes(&inputs.commitment), &nullifier);
+        Transaction::Enter(EnterTransaction {
+            game_id: game,
+            public_key: public_key.to_vec(),
+            nullifier,
+            zk_proof,
+            signature: signer.sign(&message).to_bytes().to_vec(),
+        })
+    };
+    let sybil = signed_enter(mallory.public_key.as_bytes(), zk_proof, &mallory);
+    let mut storage_a = LocalStorage::open(dir_a.path()).unwrap();
+    storage_a.set_clock(clock.clone());
+    time.store(1_600, Ordering::SeqCst);
+    let sybil = storage_a.put(sybil.encode().unwrap(), RecordType::EnterTransaction).await.unwrap();
+    first.refresh().await.unwrap();
+    assert_eq!(first.state().participants().len(), 3);
+    assert!(first.state().participant(alice.public_key.as_bytes()).is_some());
+    let rejection = &first.state().rejected()[0];
+    assert_eq!(rejection.content_cid, sybil.content_cid);
+    assert!(rejection.reason.contains("already entered"));
+
+    // Nor can Mallory enter someone else's key with a secret of her own
+    let dave = KeyPair::generate();
+    let (_, mallory_secret) = mallory.to_hex_strings();
+    let zk_proof = zk_system.prove_enter_phase(
+        game.as_bytes(),
+        dave.public_key.as_bytes(),
+        &hex::decode(mallory_secret).unwrap(),
+    ).unwrap();
+    let impostor = signed_enter(dave.public_key.as_bytes(), zk_proof, &mallory);
+    storage_a.put(impostor.encode().unwrap(), RecordType::EnterTransaction).await.unwrap();
+    first.refresh().await.unwrap();
+    assert_eq!(first.state().participants().len(), 3);
+    assert!(first.state().rejected().iter().any(|rejection| rejection.reason.contains("signature")));
+
+    // Exchanged records are stored at the time they arrive, in the order the
+    // first client stored them
+    let mut storage_b = LocalStorage::open(dir_b.path()).unwrap();
+    storage_b.set_clock(clock.clone());
+    for (i, record) in storage_a.list(RecordType::EnterTransaction).await.unwrap().into_iter().enumerate() {
+        time.store(1_700 + i as u64, Ordering::SeqCst);
+        let data = storage_a.get(&record.content_cid).await.unwrap();
+        storage_b.put(data, RecordType::EnterTransaction).await.unwrap();
+    }
+    let mut second = SecretSantaProtocol::create_game(
+        storage_b,
+        ZKProofSystem::from_params_dir(params.path()).unwrap(),
+        config,
+    ).await.unwrap();
+    second.set_clock(clock.clone());
+
+    // Bob and Alice both pick Carol without seeing each other's CHOICE
+    time.store(2_500, Ordering::SeqCst);
+    second.choice_phase(&bob, carol.public_key.as_bytes(), &DHKeyExchange::generate()).await.unwrap();
+    time.store(2_600, Ordering::SeqCst);
+    let alice_dh = DHKeyExchange::generate();
+    first.choice_phase(&alice, carol.public_key.as_bytes(), &alice_dh).await.unwrap();
+    assert!(first.choice_phase(&alice, bob.public_key.as_bytes(), &alice_dh).await.is_err());
+
+    time.store(2_700, Ordering::SeqCst);
+    let mut storage_b = LocalStorage::open(dir_b.path()).unwrap();
+    storage_b.set_clock(clock.clone());
+    for record in storage_a.list(RecordType::ChoiceTransaction).await.unwrap() {
+        let data = storage_a.get(&record.content_cid).await.unwrap();
+        storage_b.put(data, RecordType::ChoiceTransaction).await.unwrap();
+    }
+
+    // Bob's earlier CHOICE wins and Alice is told to choose again
+    let error = second.confirm_choice(&alice).await.unwrap_err();
+    assert!(error.to_string().contains("choose another participant"));
+    assert_eq!(second.state().choices().len(), 1);
+    assert!(second.confirm_choice(&bob).await.is_ok());
+
+    second.choice_phase(&alice, bob.public_key.as_bytes(), &alice_dh).await.unwrap();
+    let choice = second.confirm_choice(&alice).await.unwrap();
+    assert_eq!(choice.chosen_public_key, bob.public_key.as_bytes().to_vec());
+}
+
+#[tokio::test]
+async fn test_recovery_round_splices_in_the_last_player() {
+    use zkret_santa_filecoin::crypto::{DHKeyExchange, KeyPair, ZKProofSystem};
+    use zkret_santa_filecoin::filecoin::MemoryStorage;
+    use zkret_santa_filecoin::protocol::{Clock, Deadline, GameConfig, SecretSantaProtocol};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    let zk_system = ZKProofSystem::new().unwrap();
+    let organizer = KeyPair::generate();
+    let config = GameConfig {
+        created_at: 1_000,
+        enter_deadline: Deadline::Timestamp(2_000),
+        choice_deadline: Deadline::Timestamp(3_000),
+        recovery_deadline: Deadline::Timestamp(3_500),
+        reveal_deadline: Deadline::Timestamp(4_000),
+        ..GameConfig::new("office", &organizer, zk_system.params_hash())
+    };
+    let time = Arc::new(AtomicU64::new(1_500));
+    let clock: Clock = {
+        let time = time.clone();
+        Arc::new(move || time.load(Ordering::SeqCst))
+    };
+
+    let mut storage = MemoryStorage::new();
+    storage.set_clock(clock.clone());
+    let mut protocol = SecretSantaProtocol::create_game(
+        storage,
+        zk_system,
+        config.sign(&organizer).unwrap(),
+    ).await.unwrap();
+    protocol.set_clock(clock);
+
+    let alice = KeyPair::generate();
+    let bob = KeyPair::generate();
+    let carol = KeyPair::generate();
+    let (alice_dh, bob_dh, carol_dh) = (DHKeyExchange::generate(), DHKeyExchange::generate(), DHKeyExchange::generate());
+    for keypair in [&alice, &bob, &carol] {
+        protocol.enter_phase(keypair).await.unwrap();
+    }
+
+    // Alice and Bob pick each other, leaving Carol with only herself
+    time.store(2_500, Ordering::SeqCst);
+    protocol.choice_phase(&alice, bob.public_key.as_bytes(), &alice_dh).await.unwrap();
+    protocol.choice_phase(&bob, alice.public_key.as_bytes(), &bob_dh).await.unwrap();
+    assert!(protocol.is_stuck(&carol));
+    assert!(!protocol.is_stuck(&alice));
+    assert!(protocol.state().needs_recovery());
+    let error = protocol.choice_phase(&carol, alice.public_key.as_bytes(), &carol_dh).await.unwrap_err();
+    assert!(error.to_string().contains("recovery round"));
+    assert!(protocol.recovery_phase(&carol, bob.public_key.as_bytes(), &carol_dh).await.is_err());
+
+    // In the recovery round Carol goes in between Bob and his Santa
+    time.store(3_200, Ordering::SeqCst);
+    protocol.refresh().await.unwrap();
+    assert_eq!(protocol.get_recovery_choices(&carol).len(), 2);
+    assert!(protocol.recovery_phase(&alice, carol.public_key.as_bytes(), &alice_dh).await.is_err());
+    protocol.recovery_phase(&carol, bob.public_key.as_bytes(), &carol_dh).await.unwrap();
+    assert!(!protocol.state().needs_recovery());
+
+    let state = protocol.state();
+    assert_eq!(state.choice_for(carol.public_key.as_bytes()).unwrap().chooser_dh_public_key, alice_dh.public_key().to_vec());
+    let bob_santa = state.choice_for(bob.public_key.as_bytes()).unwrap();
+    assert_eq!(bob_santa.chooser_dh_public_key, carol_dh.public_key().to_vec());
+    assert_eq!(bob_santa.recovered_by, Some(carol.public_key.as_bytes().to_vec()));
+
+    time.store(3_700, Ordering::SeqCst);
+    protocol.reveal_phase(&carol, "carol@example.com", &alice_dh.public_key()).await.unwrap();
+    protocol.reveal_phase(&bob, "bob@example.com", &carol_dh.public_key()).await.unwrap();
+    assert_eq!(protocol.state().reveals().len(), 2);
+
+    // Each REVEAL is made under a fresh key, so it cannot be traced to a CHOICE
+    let state = protocol.state();
+    assert!(state.reveals().iter().all(|reveal| {
+        state.choices().iter().all(|choice| choice.chooser_dh_public_key != reveal.dh_public_key)
+    }));
+}